@@ -0,0 +1,88 @@
+//! Measures the parsing of generated multi-megabyte archives, in both formats:
+//! `cargo bench --bench parse [-- <messages>]`.
+use lib_messenger_archive::{Data, Message, MessengerPlusWriter, Parser, Text, XmlWriter};
+use std::time::Instant;
+
+/// The count of messages generated by default, about 7 MB of XML.
+const MESSAGES: usize = 20_000;
+/// The count of times each archive is parsed.
+const RUNS: u32 = 5;
+
+fn main() {
+    let count = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(MESSAGES);
+    let directory = std::env::temp_dir().join("lib-messenger-archive-bench");
+    std::fs::create_dir_all(&directory).unwrap();
+
+    let xml = directory.join("alice1234.xml");
+    std::fs::write(&xml, XmlWriter::new(vec![]).write(generate(count, true)).unwrap()).unwrap();
+    bench(xml.to_str().unwrap(), count);
+
+    let html = directory.join("alice@example.com.html");
+    MessengerPlusWriter::new(html.to_str().unwrap())
+        .write(generate(count, false))
+        .unwrap();
+    bench(html.to_str().unwrap(), count);
+}
+
+/// Generates a conversation between Alice and Bob, 100 messages per session.
+fn generate(count: usize, milliseconds: bool) -> impl Iterator<Item = Message> {
+    (0..count).map(move |index| {
+        let session = index / 100;
+        let (sender, receiver) = if index % 2 == 0 { ("Alice", "Bob") } else { ("Bob", "Alice") };
+        let second = index % 100;
+        Message {
+            datetime: if milliseconds {
+                format!("2009-04-{:02}T19:{:02}:{:02}.851Z", 1 + session % 28, second / 60, second % 60)
+            } else {
+                format!("2009-04-{:02}T19:{:02}", 1 + session % 28, second % 60)
+            },
+            timezone_offset: Some(120),
+            session_id: (session + 1).to_string().into(),
+            sender_friendly_name: sender.into(),
+            receiver_friendly_name: receiver.into(),
+            data: vec![
+                Data::Text(Text {
+                    style: "font-family:Courier New; color:#004000; ".to_string(),
+                    content: format!("Message number {} of a generated conversation, ", index),
+                }),
+                Data::Text(Text {
+                    style: "font-family:Arial; color:#004020; ".to_string(),
+                    content: "with a second styled part :-)".to_string(),
+                }),
+            ],
+        }
+    })
+}
+
+/// Measures the owned messages (`Iterator::next`) and the borrowed ones (`Parser::next_ref`).
+fn bench(path: &str, count: usize) {
+    measure(path, count, "next", |parser| parser.filter(Result::is_ok).count());
+    measure(path, count, "next_ref", |mut parser| {
+        let mut parsed = 0;
+        while let Some(message) = parser.next_ref() {
+            parsed += message.is_ok() as usize;
+        }
+        parsed
+    });
+}
+
+fn measure(path: &str, count: usize, name: &str, parse: fn(Parser) -> usize) {
+    let size = std::fs::metadata(path).unwrap().len() as f64 / 1_000_000.0;
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        assert_eq!(parse(Parser::new(path).unwrap()), count);
+    }
+    let elapsed = start.elapsed() / RUNS;
+    println!(
+        "{} ({}): {:.1} MB, {} messages in {:?} ({:.1} MB/s)",
+        path,
+        name,
+        size,
+        count,
+        elapsed,
+        size / elapsed.as_secs_f64()
+    );
+}
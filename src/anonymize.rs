@@ -0,0 +1,441 @@
+use crate::{ArchiveDetails, Data, Image, Message, UnknownElement};
+use std::collections::HashMap;
+
+/// The domain of the pseudonymous e-mail addresses, reserved so they cannot be real ones.
+const PSEUDONYM_DOMAIN: &str = "example.invalid";
+/// The minimum count of digits of a phone number.
+const PHONE_DIGITS: usize = 9;
+
+/// Scrubs the personal data of the messages, before sharing the archives.
+///
+/// The friendly names and the e-mail addresses are replaced by pseudonyms, the same in all the
+/// messages (e.g. `Contact 1` and `contact1@example.invalid`). In the texts, the known friendly
+/// names are replaced too and the e-mail addresses, URLs and phone numbers are masked. The
+/// anonymized messages can be written back with `XmlWriter` or `MessengerPlusWriter`:
+/// ```
+/// use lib_messenger_archive::{Anonymizer, Parser, XmlWriter};
+///
+/// let messages = Parser::new("test/alice1234.xml").unwrap().flatten();
+/// let xml = XmlWriter::new(vec![]).write(Anonymizer::new().apply(messages)).unwrap();
+/// assert!(!String::from_utf8(xml).unwrap().contains("Alice"));
+/// ```
+#[derive(Default)]
+pub struct Anonymizer {
+    /// The pseudonyms of the friendly names and e-mail addresses, by lowercase value.
+    pseudonyms: HashMap<String, String>,
+    /// The known friendly names, longest first, to replace them in the texts.
+    names: Vec<String>,
+    /// The masked paths of the images, by original path.
+    image_paths: HashMap<String, String>,
+    drop_images: bool,
+}
+
+/// An iterator anonymizing the messages of another one (see `Anonymizer::apply`).
+pub struct Anonymized<I> {
+    anonymizer: Anonymizer,
+    messages: I,
+}
+
+impl Anonymizer {
+    pub fn new() -> Self {
+        Anonymizer::default()
+    }
+
+    /// Removes the images, the ones of the custom emoticons too: only their alternative text is
+    /// kept. Otherwise the images are kept with their file names masked (e.g. `./Images/Image1.png`).
+    pub fn drop_images(mut self) -> Self {
+        self.drop_images = true;
+        self
+    }
+
+    /// The pseudonym of a friendly name.
+    pub fn pseudonym(&mut self, friendly_name: &str) -> String {
+        if friendly_name.is_empty() {
+            return String::new();
+        }
+        let key = friendly_name.trim().to_lowercase();
+        if let Some(pseudonym) = self.pseudonyms.get(&key) {
+            return pseudonym.clone();
+        }
+        let pseudonym = format!("Contact {}", self.next_number());
+        self.pseudonyms.insert(key, pseudonym.clone());
+        if !friendly_name.trim().is_empty() {
+            self.names.push(friendly_name.trim().to_string());
+            self.names.sort_by_key(|name| std::cmp::Reverse(name.len()));
+        }
+        pseudonym
+    }
+
+    /// The pseudonym of an e-mail address (or of an account ID).
+    pub fn email_pseudonym(&mut self, email: &str) -> String {
+        if email.is_empty() {
+            return String::new();
+        }
+        let key = format!("<{}>", email.trim().to_lowercase());
+        if let Some(pseudonym) = self.pseudonyms.get(&key) {
+            return pseudonym.clone();
+        }
+        let pseudonym = format!("contact{}@{}", self.next_number(), PSEUDONYM_DOMAIN);
+        self.pseudonyms.insert(key, pseudonym.clone());
+        pseudonym
+    }
+
+    /// Anonymizes a message.
+    pub fn anonymize(&mut self, mut message: Message) -> Message {
+        message.sender_friendly_name = self.pseudonym(&message.sender_friendly_name).into();
+        message.receiver_friendly_name = self.pseudonym(&message.receiver_friendly_name).into();
+        for data in message.data.iter_mut() {
+            match data {
+                Data::Text(text) => text.content = self.mask(&text.content),
+                Data::System(content) => *content = self.mask(content),
+                Data::Image(image) => self.mask_image(image),
+                Data::Emoticon(emoticon) => {
+                    if let Some(image) = emoticon.image.as_mut() {
+                        self.mask_image(image);
+                    }
+                }
+                Data::Unknown(element) => self.mask_element(element),
+            }
+        }
+        message
+    }
+
+    /// Anonymizes an image: its alternative text and the name of its file, or drops it.
+    fn mask_image(&mut self, image: &mut Image) {
+        image.alt = self.mask(&image.alt);
+        if self.drop_images {
+            image.src.clear();
+            image.content.clear();
+        } else if !image.src.is_empty() {
+            let count = self.image_paths.len();
+            image.src = self
+                .image_paths
+                .entry(image.src.clone())
+                .or_insert_with_key(|src| {
+                    // the folder is kept, with the extension telling the format of the image
+                    let start = src.rfind(['/', '\\']).map_or(0, |position| position + 1);
+                    match src[start..].rsplit_once('.') {
+                        Some((_, extension)) => format!("{}Image{}.{}", &src[..start], count + 1, extension),
+                        None => format!("{}Image{}", &src[..start], count + 1),
+                    }
+                })
+                .clone();
+        }
+    }
+
+    /// Anonymizes an element kept in raw mode, with its children.
+    fn mask_element(&mut self, element: &mut UnknownElement) {
+        element.text = self.mask(&element.text);
+        for (_, value) in element.attributes.iter_mut() {
+            *value = self.mask(value);
+        }
+        for child in element.children.iter_mut() {
+            self.mask_element(child);
+        }
+    }
+
+    /// Anonymizes the details of an archive, with the same pseudonyms as the messages.
+    pub fn anonymize_details(&mut self, mut details: ArchiveDetails) -> ArchiveDetails {
+        details.recipient_id = self.email_pseudonym(&details.recipient_id);
+        for participant in details.participants.iter_mut() {
+            participant.friendly_name = self.pseudonym(&participant.friendly_name);
+            participant.email = self.email_pseudonym(&participant.email);
+        }
+        details
+    }
+
+    /// Anonymizes all the given messages, e.g. before writing them.
+    pub fn apply<I>(self, messages: I) -> Anonymized<I::IntoIter>
+    where
+        I: IntoIterator<Item = Message>,
+    {
+        Anonymized {
+            anonymizer: self,
+            messages: messages.into_iter(),
+        }
+    }
+
+    fn next_number(&self) -> usize {
+        self.pseudonyms.len() + 1
+    }
+
+    /// Replaces the known friendly names and masks the e-mail addresses, URLs and phone numbers
+    /// of a text.
+    fn mask(&mut self, text: &str) -> String {
+        let mut words = vec![];
+        for word in text.split_inclusive(char::is_whitespace) {
+            let content = word.trim_end();
+            let trimmed = content.trim_end_matches(|c: char| ".,;:!?)]".contains(c));
+            let ending = &word[trimmed.len()..];
+            let start = trimmed.len() - trimmed.trim_start_matches(|c: char| "(<[".contains(c)).len();
+            let (opening, core) = trimmed.split_at(start);
+            let lowercase = core.to_lowercase();
+            if lowercase.starts_with("http://") || lowercase.starts_with("https://") || lowercase.starts_with("www.") {
+                words.push(format!("{}[url]{}", opening, ending));
+            } else {
+                words.push(word.to_string());
+            }
+        }
+        let mut masked = mask_phone_numbers(&self.mask_emails(&words.concat()));
+        for (name, pseudonym) in self.names_pseudonyms() {
+            masked = replace_word(&masked, &name, &pseudonym);
+        }
+        masked
+    }
+
+    /// Replaces the e-mail addresses of a text, wherever they start and end (e.g.
+    /// `<alice@example.com>` or `mailto:alice@example.com`).
+    fn mask_emails(&mut self, text: &str) -> String {
+        let mut masked = String::new();
+        let mut position = 0;
+        while let Some(found) = text[position..].find('@') {
+            let at = position + found;
+            let user = text[position..at]
+                .rfind(|c: char| !is_user_char(c))
+                .map_or(position, |found| position + found + 1);
+            let domain_length = text[at + 1..].find(|c: char| !is_domain_char(c)).unwrap_or(text.len() - at - 1);
+            let end = at + 1 + text[at + 1..at + 1 + domain_length].trim_end_matches(['.', '-']).len();
+            if is_email(&text[user..end]) {
+                masked.push_str(&text[position..user]);
+                let pseudonym = self.email_pseudonym(&text[user..end]);
+                masked.push_str(&pseudonym);
+                position = end;
+            } else {
+                masked.push_str(&text[position..=at]);
+                position = at + 1;
+            }
+        }
+        masked.push_str(&text[position..]);
+        masked
+    }
+
+    fn names_pseudonyms(&self) -> Vec<(String, String)> {
+        self.names
+            .iter()
+            .map(|name| (name.clone(), self.pseudonyms[&name.to_lowercase()].clone()))
+            .collect()
+    }
+}
+
+impl<I: Iterator<Item = Message>> Iterator for Anonymized<I> {
+    type Item = Message;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let message = self.messages.next()?;
+        Some(self.anonymizer.anonymize(message))
+    }
+}
+
+fn is_user_char(c: char) -> bool {
+    c.is_alphanumeric() || "._%+-".contains(c)
+}
+
+fn is_domain_char(c: char) -> bool {
+    c.is_alphanumeric() || ".-".contains(c)
+}
+
+fn is_email(word: &str) -> bool {
+    let Some((user, domain)) = word.split_once('@') else {
+        return false;
+    };
+    !user.is_empty()
+        && user.chars().all(is_user_char)
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && domain.chars().all(is_domain_char)
+}
+
+/// Masks the sequences of digits, spaces, dots, dashes and parentheses containing enough digits
+/// to be a phone number. The digits of a number must be separated by a single kind of separator
+/// (besides an area code in parentheses), so the dates and times are not masked (e.g.
+/// `2009-04-06 21:40`).
+fn mask_phone_numbers(text: &str) -> String {
+    let mut masked = String::new();
+    let mut candidate = String::new();
+    let flush = |masked: &mut String, candidate: &mut String| {
+        let number = candidate.trim_end_matches(|c: char| !c.is_ascii_digit() && c != ')');
+        if number.chars().filter(char::is_ascii_digit).count() >= PHONE_DIGITS && is_digit_group(number) {
+            masked.push_str("[phone]");
+            masked.push_str(&candidate[number.len()..]);
+        } else {
+            masked.push_str(candidate);
+        }
+        candidate.clear();
+    };
+    for c in text.chars() {
+        let starts = c.is_ascii_digit() || c == '+' || c == '(';
+        let continues = c.is_ascii_digit() || " .-()".contains(c);
+        if (candidate.is_empty() && starts) || (!candidate.is_empty() && continues) {
+            candidate.push(c);
+        } else {
+            flush(&mut masked, &mut candidate);
+            if starts {
+                candidate.push(c);
+            } else {
+                masked.push(c);
+            }
+        }
+    }
+    flush(&mut masked, &mut candidate);
+    masked
+}
+
+/// Whether the separators between the digits of a phone number candidate are all the same.
+fn is_digit_group(number: &str) -> bool {
+    let mut separators = vec![];
+    let mut previous = None;
+    for c in number.chars() {
+        // the space after an area code in parentheses is not a separator (e.g. `(555) 123-4567`)
+        if !c.is_ascii_digit() && !"+()".contains(c) && previous != Some(')') {
+            separators.push(c);
+        }
+        previous = Some(c);
+    }
+    separators.windows(2).all(|pair| pair[0] == pair[1])
+}
+
+/// Replaces the whole-word occurrences of `name` (case insensitive). The characters are compared
+/// one by one, since their lowercase forms may not have the same length (e.g. `İ`).
+fn replace_word(text: &str, name: &str, replacement: &str) -> String {
+    let boundary = |c: Option<char>| c.is_none_or(|c| !c.is_alphanumeric());
+    let same = |a: char, b: char| a == b || a.to_lowercase().eq(b.to_lowercase());
+    let mut replaced = String::new();
+    let mut position = 0;
+    let mut copied = 0;
+    while position < text.len() {
+        let mut end = Some(position);
+        for n in name.chars() {
+            end = end.and_then(|end| {
+                let c = text[end..].chars().next()?;
+                same(c, n).then(|| end + c.len_utf8())
+            });
+        }
+        match end {
+            Some(end)
+                if !name.is_empty()
+                    && boundary(text[..position].chars().next_back())
+                    && boundary(text[end..].chars().next()) =>
+            {
+                replaced.push_str(&text[copied..position]);
+                replaced.push_str(replacement);
+                position = end;
+                copied = end;
+            }
+            _ => position += text[position..].chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    replaced.push_str(&text[copied..]);
+    replaced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MessengerPlusWriter, Parser};
+
+    #[test]
+    fn mask_personal_data() {
+        let mut anonymizer = Anonymizer::new();
+        assert_eq!(anonymizer.pseudonym("Alice"), "Contact 1");
+        assert_eq!(anonymizer.pseudonym("alice "), "Contact 1");
+        assert_eq!(
+            anonymizer.mask("Hi ALICE! Write to (alice@example.com), see (www.example.com) or call +33 6 12 34 56 78."),
+            "Hi Contact 1! Write to (contact2@example.invalid), see ([url]) or call [phone]."
+        );
+        assert_eq!(anonymizer.mask("Malice, 2009-04-06 at 21:40"), "Malice, 2009-04-06 at 21:40");
+        assert_eq!(anonymizer.email_pseudonym("ALICE@example.com"), "contact2@example.invalid");
+    }
+
+    #[test]
+    fn mask_emails_within_words() {
+        let mut anonymizer = Anonymizer::new();
+        assert_eq!(
+            anonymizer.mask("<alice@example.com>, \"bob@example.com\" and mailto:alice@example.com."),
+            "<contact1@example.invalid>, \"contact2@example.invalid\" and mailto:contact1@example.invalid."
+        );
+        assert_eq!(anonymizer.mask("@alice or alice@home"), "@alice or alice@home");
+    }
+
+    #[test]
+    fn replace_names_of_any_case_and_length() {
+        let mut anonymizer = Anonymizer::new();
+        assert_eq!(anonymizer.pseudonym("İlkay"), "Contact 1");
+        assert_eq!(anonymizer.pseudonym("Al"), "Contact 2");
+        assert_eq!(
+            anonymizer.mask("İLKAY and İlkay met Al, not Alan"),
+            "Contact 1 and Contact 1 met Contact 2, not Alan"
+        );
+        assert_eq!(replace_word("Straße STRASSE", "straße", "x"), "x STRASSE");
+    }
+
+    #[test]
+    fn mask_phone_numbers_only() {
+        assert_eq!(mask_phone_numbers("2009-04-06 21:40"), "2009-04-06 21:40");
+        assert_eq!(mask_phone_numbers("at 2009-04-06 21:40:12"), "at 2009-04-06 21:40:12");
+        assert_eq!(
+            mask_phone_numbers("call 06.12.34.56.78, (555) 123-4567 or 0612345678"),
+            "call [phone], [phone] or [phone]"
+        );
+    }
+
+    #[test]
+    fn write_anonymized_archive() {
+        let messages = Parser::new("test/alice@example.com.html").unwrap().flatten();
+        let messages: Vec<Message> = Anonymizer::new().drop_images().apply(messages).collect();
+        assert_eq!(&*messages[0].sender_friendly_name, "Contact 1");
+        assert_eq!(&*messages[0].receiver_friendly_name, "Contact 2");
+        assert_eq!(messages[5].data, vec![Data::System("Contact 2 is now offline".to_string())]);
+
+        let path = std::env::temp_dir().join("anonymized").join("contact2@example.invalid.html");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let path = path.to_str().unwrap();
+        MessengerPlusWriter::new(path).write(messages.clone()).unwrap();
+        let written: Vec<Message> = Parser::new(path).unwrap().flatten().collect();
+        assert_eq!(written.len(), messages.len());
+        assert!(written
+            .iter()
+            .all(|message| message.data.iter().all(|d| !matches!(d, Data::Image(_)))));
+    }
+
+    #[test]
+    fn mask_image_names() {
+        let image = |src: &str| Image {
+            src: src.to_string(),
+            alt: "alice.jpg".to_string(),
+            content: vec![1],
+        };
+        let emoticon = Data::Emoticon(crate::Emoticon {
+            id: "wave".to_string(),
+            shortcut: ":wave:".to_string(),
+            image: Some(image("./Images/alice.gif")),
+        });
+        let message = Message {
+            sender_friendly_name: "Alice".into(),
+            data: vec![Data::Image(image("C:\\Photos\\alice at home.jpg")), emoticon.clone(), Data::Image(image("alice"))],
+            ..Message::default()
+        };
+
+        let masked = Anonymizer::new().anonymize(message.clone());
+        let sources: Vec<(&str, &str)> = masked
+            .data
+            .iter()
+            .filter_map(|d| match d {
+                Data::Image(image) => Some(image),
+                Data::Emoticon(emoticon) => emoticon.image.as_ref(),
+                _ => None,
+            })
+            .map(|image| (image.src.as_str(), image.alt.as_str()))
+            .collect();
+        assert_eq!(
+            sources,
+            vec![("C:\\Photos\\Image1.jpg", "Contact 1.jpg"), ("./Images/Image2.gif", "Contact 1.jpg"), ("Image3", "Contact 1.jpg")]
+        );
+
+        let dropped = Anonymizer::new().drop_images().anonymize(message);
+        let Data::Emoticon(crate::Emoticon { image: Some(image), .. }) = &dropped.data[1] else {
+            panic!("The emoticon must be kept");
+        };
+        assert_eq!((image.src.as_str(), image.content.len()), ("", 0));
+    }
+}
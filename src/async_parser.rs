@@ -0,0 +1,331 @@
+use crate::{ArchiveDetails, Data, FileType, Message, MessengerArchive, Parser};
+use futures_core::Stream;
+use std::error::Error;
+use std::future::Future;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::mpsc;
+
+/// A message, or an error that can be sent between threads.
+type AsyncItem = Result<Message, Box<dyn Error + Send + Sync>>;
+type ImagesLoading = Pin<Box<dyn Future<Output = AsyncItem> + Send>>;
+type Configure = Box<dyn FnOnce(Parser<'static>) -> Parser<'static> + Send>;
+
+/// The size of the chunks read from the `AsyncRead`.
+const CHUNK_SIZE: usize = 8192;
+/// The count of chunks read ahead, and of messages parsed ahead of the stream.
+const CHANNEL_SIZE: usize = 16;
+
+/// A stream through the messages of an archive, read from an `AsyncRead`.
+///
+/// The archive is parsed while it is read: a task reads it by chunks and sends them to the parser,
+/// which sends back the messages. Only a few chunks and messages are kept in memory. The parsing
+/// starts when the stream is first polled, so a tokio runtime is needed.
+///
+/// The parser is the synchronous `Parser` (xml-rs only reads from a `Read`), so it needs a
+/// blocking thread: each stream takes one from the blocking pool of the runtime (see
+/// `tokio::task::spawn_blocking`) until the archive is parsed or the stream is dropped.
+///
+/// The errors are the ones of `Parser`, which can be sent between threads: the I/O and XML
+/// errors keep their type, the other ones are given by their message.
+///
+/// The images of the Messenger Plus! archives are loaded asynchronously, from the folder
+/// containing the archive (see `images_directory`). Without this folder, they keep an empty
+/// content.
+/// ```
+/// # tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+/// use lib_messenger_archive::AsyncParser;
+/// use std::future::poll_fn;
+/// use std::pin::Pin;
+/// use futures_core::Stream;
+///
+/// let mut parser = AsyncParser::open("test/alice1234.xml").await.unwrap();
+/// let message = poll_fn(|cx| Pin::new(&mut parser).poll_next(cx)).await.unwrap().unwrap();
+/// assert_eq!(&*message.sender_friendly_name, "Alice");
+/// # });
+/// ```
+pub struct AsyncParser {
+    /// The archive to parse, until the parsing starts.
+    input: Option<Input>,
+    /// The messages sent by the parsing thread.
+    events: Option<mpsc::Receiver<Event>>,
+    details: Option<ArchiveDetails>,
+    /// The folder the images are loaded from, none to keep them empty.
+    images_directory: Option<PathBuf>,
+    loading: Option<ImagesLoading>,
+}
+
+struct Input {
+    reader: Box<dyn AsyncRead + Send + Unpin>,
+    file_type: FileType,
+    name: String,
+    configure: Configure,
+}
+
+/// What the parsing thread sends to the stream.
+enum Event {
+    Message(AsyncItem),
+    End(Option<ArchiveDetails>),
+}
+
+impl AsyncParser {
+    /// Reads the archive at `path`. Its type is given by its extension, like `Parser::new`.
+    pub async fn open(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = tokio::fs::File::open(path).await?;
+        let file_type = FileType::from_path(path);
+        let name = Path::new(path).file_name().unwrap_or_default().to_string_lossy();
+        let parser = Self::from_reader(file, file_type, &name);
+        Ok(match Path::new(path).parent() {
+            Some(directory) => parser.images_directory(directory),
+            None => parser,
+        })
+    }
+
+    /// Reads an archive of the given type from `reader`. The `name` of the archive file gives the
+    /// recipient of the conversation (see `ArchiveDetails::recipient_id`).
+    pub fn from_reader<R: AsyncRead + Send + Unpin + 'static>(reader: R, file_type: FileType, name: &str) -> Self {
+        AsyncParser {
+            input: Some(Input {
+                reader: Box::new(reader),
+                file_type,
+                name: name.to_string(),
+                configure: Box::new(|parser| parser),
+            }),
+            events: None,
+            details: None,
+            images_directory: None,
+            loading: None,
+        }
+    }
+
+    /// Loads the images of the Messenger Plus! archives from `directory`, the folder their paths
+    /// are relative to.
+    pub fn images_directory<P: Into<PathBuf>>(mut self, directory: P) -> Self {
+        self.images_directory = Some(directory.into());
+        self
+    }
+
+    /// Configures the underlying parser, e.g. to filter the messages:
+    /// `parser.configure(|parser| parser.from("Alice").with_emoticons())`.
+    /// It is applied by the parsing thread, so it has no effect once the stream is polled.
+    pub fn configure<F>(mut self, configure: F) -> Self
+    where
+        F: FnOnce(Parser<'static>) -> Parser<'static> + Send + 'static,
+    {
+        if let Some(input) = &mut self.input {
+            let previous = std::mem::replace(&mut input.configure, Box::new(|parser| parser));
+            input.configure = Box::new(move |parser| configure(previous(parser)));
+        }
+        self
+    }
+
+    /// Returns some global details of the archive, once the stream has ended.
+    pub fn details(&self) -> Option<&ArchiveDetails> {
+        self.details.as_ref()
+    }
+
+    /// Starts reading and parsing the archive.
+    fn start(input: Input) -> mpsc::Receiver<Event> {
+        let (chunks_sender, chunks) = mpsc::channel(CHANNEL_SIZE);
+        let (events_sender, events) = mpsc::channel(CHANNEL_SIZE);
+        tokio::spawn(feed(input.reader, chunks_sender));
+        tokio::task::spawn_blocking(move || {
+            let reader = ChannelReader {
+                chunks,
+                chunk: vec![],
+                position: 0,
+            };
+            let mut parser = (input.configure)(Parser::from_reader(reader, &input.name, input.file_type));
+            for item in parser.by_ref() {
+                // the parser cannot recover from an error, the stream ends with it
+                let failed = item.is_err();
+                if events_sender.blocking_send(Event::Message(item.map_err(sendable))).is_err() || failed {
+                    return;
+                }
+            }
+            let _ = events_sender.blocking_send(Event::End(parser.details().cloned()));
+        });
+        events
+    }
+}
+
+impl Stream for AsyncParser {
+    type Item = AsyncItem;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if let Some(loading) = &mut this.loading {
+                let item = ready!(loading.as_mut().poll(cx));
+                this.loading = None;
+                return Poll::Ready(Some(item));
+            }
+            let events = match (&mut this.events, this.input.take()) {
+                (Some(events), _) => events,
+                (None, Some(input)) => this.events.insert(Self::start(input)),
+                (None, None) => return Poll::Ready(None),
+            };
+            match ready!(events.poll_recv(cx)) {
+                Some(Event::Message(Ok(message))) => {
+                    this.loading = Some(Box::pin(load_images(message, this.images_directory.clone())));
+                }
+                Some(Event::Message(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Some(Event::End(details)) => {
+                    this.details = details;
+                    return Poll::Ready(None);
+                }
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+/// Reads `reader` by chunks and sends them to the parsing thread, until its end, an error or
+/// the parsing stops.
+async fn feed(mut reader: Box<dyn AsyncRead + Send + Unpin>, chunks: mpsc::Sender<io::Result<Vec<u8>>>) {
+    loop {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let chunk = match reader.read(&mut chunk).await {
+            Ok(0) => return,
+            Ok(length) => {
+                chunk.truncate(length);
+                Ok(chunk)
+            }
+            Err(e) => Err(e),
+        };
+        let failed = chunk.is_err();
+        if chunks.send(chunk).await.is_err() || failed {
+            return;
+        }
+    }
+}
+
+/// The archive read by the parsing thread, from the chunks sent by `feed`.
+struct ChannelReader {
+    chunks: mpsc::Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    /// The count of bytes of `chunk` already read.
+    position: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.chunks.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk?;
+                    self.position = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let length = buffer.len().min(self.chunk.len() - self.position);
+        buffer[..length].copy_from_slice(&self.chunk[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+/// Converts an error of the parser to one that can be sent to the stream, keeping its type when
+/// it is known.
+fn sendable(e: Box<dyn Error>) -> Box<dyn Error + Send + Sync> {
+    fn downcast<T: Error + Send + Sync + 'static>(e: Box<dyn Error>) -> Result<Box<dyn Error + Send + Sync>, Box<dyn Error>> {
+        e.downcast::<T>().map(|e| e as Box<dyn Error + Send + Sync>)
+    }
+    downcast::<io::Error>(e)
+        .or_else(downcast::<xml::reader::Error>)
+        .or_else(downcast::<chrono::ParseError>)
+        .unwrap_or_else(|e| e.to_string().into())
+}
+
+async fn load_images(mut message: Message, directory: Option<PathBuf>) -> AsyncItem {
+    let Some(directory) = directory else {
+        return Ok(message);
+    };
+    for data in message.data.iter_mut() {
+        if let Data::Image(image) = data {
+            if !image.src.is_empty() {
+                image.content = tokio::fs::read(directory.join(&image.src)).await?;
+            }
+        }
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DateFormat;
+    use std::future::poll_fn;
+    use tokio::io::AsyncWriteExt;
+
+    async fn next(parser: &mut AsyncParser) -> Option<AsyncItem> {
+        poll_fn(|cx| Pin::new(&mut *parser).poll_next(cx)).await
+    }
+
+    async fn collect(mut parser: AsyncParser) -> (Vec<Message>, AsyncParser) {
+        let mut messages = vec![];
+        while let Some(message) = next(&mut parser).await {
+            messages.push(message.unwrap());
+        }
+        (messages, parser)
+    }
+
+    #[tokio::test]
+    async fn stream_both_formats() {
+        for path in ["test/alice1234.xml", "test/alice@example.com.html"] {
+            let (messages, parser) = collect(AsyncParser::open(path).await.unwrap()).await;
+            let mut expected = Parser::new(path).unwrap();
+            assert_eq!(messages, expected.by_ref().flatten().collect::<Vec<_>>());
+            assert_eq!(parser.details(), expected.details());
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_from_reader() {
+        let file = tokio::fs::File::open("test/alice@example.com.html").await.unwrap();
+        let parser = AsyncParser::from_reader(file, FileType::MessengerPlus, "alice@example.com.html")
+            .configure(|parser| parser.with_images());
+        let (messages, parser) = collect(parser).await;
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].data.iter().any(|data| matches!(data, Data::Image(image) if image.content.is_empty())));
+        assert_eq!(parser.details().unwrap().recipient_id, "alice@example.com");
+    }
+
+    #[tokio::test]
+    async fn parse_while_reading() {
+        let content = std::fs::read("test/alice1234.xml").unwrap();
+        let split = content.windows(10).position(|window| window == b"</Message>").unwrap() + 10;
+        let (mut writer, reader) = tokio::io::duplex(64);
+        let mut parser = AsyncParser::from_reader(reader, FileType::XML, "alice1234.xml")
+            .configure(|parser| parser.date_format(DateFormat::DayMonthYear));
+        let first_part = content[..split].to_vec();
+        let writing = tokio::spawn(async move { writer.write_all(&first_part).await.map(|()| writer) });
+
+        // the first message is returned before the end of the archive is written
+        let first = next(&mut parser).await.unwrap().unwrap();
+        let mut writer = writing.await.unwrap().unwrap();
+        tokio::spawn(async move { writer.write_all(&content[split..]).await });
+        let (messages, parser) = collect(parser).await;
+        let expected: Vec<Message> = Parser::new("test/alice1234.xml").unwrap().flatten().collect();
+        assert_eq!([vec![first], messages].concat(), expected);
+        assert_eq!(parser.details().unwrap().recipient_id, "alice1234");
+    }
+
+    #[tokio::test]
+    async fn send_typed_errors() {
+        let content = std::fs::read_to_string("test/alice1234.xml").unwrap();
+        let broken = content.replace("</Log>", "</Message>");
+        let mut parser = AsyncParser::from_reader(io::Cursor::new(broken), FileType::XML, "alice1234.xml");
+        let mut errors = vec![];
+        while let Some(item) = next(&mut parser).await {
+            errors.extend(item.err());
+        }
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].downcast_ref::<xml::reader::Error>().is_some());
+        assert!(parser.details().is_none());
+    }
+}
@@ -0,0 +1,212 @@
+use lib_messenger_archive::{export, FileType, Message, MessengerArchive, MessengerPlusWriter, Parser, Severity, XmlWriter};
+use std::env;
+use std::error::Error;
+use std::io;
+use std::io::Write;
+use std::process::ExitCode;
+
+const USAGE: &str = "Usage:
+    messenger-archive info <archive>
+    messenger-archive cat <archive>
+    messenger-archive convert --to <json|csv|html|xml|mplus> [--output <path>] <archive>
+    messenger-archive validate <archive>...
+
+Exit codes: 0 on success, 1 if an archive cannot be parsed, 2 on invalid usage or I/O errors.";
+
+/// The errors ending the program, each one with its own exit code.
+enum Failure {
+    Parse(Box<dyn Error>),
+    Usage(String),
+    Io(Box<dyn Error>),
+}
+
+impl Failure {
+    fn exit_code(&self) -> ExitCode {
+        match self {
+            Failure::Parse(_) => ExitCode::from(1),
+            Failure::Usage(_) | Failure::Io(_) => ExitCode::from(2),
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("info") => single_archive(&args[1..]).and_then(info),
+        Some("cat") => single_archive(&args[1..]).and_then(cat),
+        Some("convert") => convert(&args[1..]),
+        Some("validate") => validate(&args[1..]),
+        Some("-h") | Some("--help") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(Failure::Usage("missing or unknown command".to_string())),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            match &failure {
+                Failure::Parse(e) => eprintln!("error: unable to parse the archive: {}", e),
+                Failure::Usage(e) => eprintln!("error: {}\n\n{}", e, USAGE),
+                Failure::Io(e) => eprintln!("error: {}", e),
+            }
+            failure.exit_code()
+        }
+    }
+}
+
+fn single_archive(args: &[String]) -> Result<&str, Failure> {
+    match args {
+        [path] => Ok(path),
+        _ => Err(Failure::Usage("expected exactly one archive".to_string())),
+    }
+}
+
+/// Reads all the messages of the archive, stopping at the first error.
+fn read_archive(path: &str) -> Result<(Vec<Message>, Parser<'_>), Failure> {
+    let mut parser = Parser::new(path).map_err(Failure::Io)?;
+    let messages = parser
+        .by_ref()
+        .collect::<Result<Vec<Message>, _>>()
+        .map_err(Failure::Parse)?;
+    Ok((messages, parser))
+}
+
+fn info(path: &str) -> Result<(), Failure> {
+    let (messages, parser) = read_archive(path)?;
+    let details = parser
+        .details()
+        .ok_or_else(|| Failure::Parse("the archive was not entirely read".into()))?;
+    let file_type = match details.file_type {
+        FileType::XML => "Windows Live Messenger XML",
+        FileType::MessengerPlus => "Messenger Plus!",
+    };
+    println!("File type: {}", file_type);
+    println!("Recipient: {}", details.recipient_id);
+    println!("First session: {}", details.first_session_id);
+    println!("Last session: {}", details.last_session_id);
+    println!("Messages: {}", messages.len());
+    Ok(())
+}
+
+fn cat(path: &str) -> Result<(), Failure> {
+    let mut output = io::stdout().lock();
+    let file_type = FileType::from_path(path);
+    for message in Parser::new(path).map_err(Failure::Io)? {
+        let message = message.map_err(Failure::Parse)?;
+        export::write_transcript_message(&mut output, &message, file_type).map_err(Failure::Io)?;
+    }
+    Ok(())
+}
+
+fn convert(args: &[String]) -> Result<(), Failure> {
+    let mut format = None;
+    let mut output = None;
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--to" => format = args.next(),
+            "--output" | "-o" => output = args.next(),
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(Failure::Usage(format!("unexpected argument `{}`", arg))),
+        }
+    }
+    let format = format.ok_or_else(|| Failure::Usage("missing `--to <format>`".to_string()))?;
+    let path = path.ok_or_else(|| Failure::Usage("missing archive".to_string()))?;
+    let (messages, parser) = read_archive(path)?;
+    let file_type = parser.details().map_or(FileType::from_path(path), |details| details.file_type);
+
+    if format == "mplus" {
+        let output = output
+            .ok_or_else(|| Failure::Usage("`--to mplus` requires `--output <path>`".to_string()))?;
+        return MessengerPlusWriter::new(output).write(messages).map_err(Failure::Io);
+    }
+    let mut writer: Box<dyn Write> = match output {
+        Some(output) => Box::new(std::fs::File::create(output).map_err(|e| Failure::Io(e.into()))?),
+        None => Box::new(io::stdout().lock()),
+    };
+    match format.as_str() {
+        "json" => export::write_json(&mut writer, &messages),
+        "csv" => export::write_csv(&mut writer, &messages, file_type),
+        "html" => export::write_html(&mut writer, &messages, file_type),
+        "xml" => XmlWriter::new(writer).write(messages).map(|_| ()),
+        _ => return Err(Failure::Usage(format!("unknown format `{}`", format))),
+    }
+    .map_err(Failure::Io)
+}
+
+fn validate(paths: &[String]) -> Result<(), Failure> {
+    if paths.is_empty() {
+        return Err(Failure::Usage("missing archive".to_string()));
+    }
+    let mut failure = None;
+    for path in paths {
+        match lib_messenger_archive::validate(path) {
+            Ok(report) if report.issues.is_empty() => println!("{}: ok ({} messages)", path, report.messages),
+            Ok(report) => {
+                let state = if report.has_errors() { "invalid" } else { "ok" };
+                println!("{}: {} ({} messages, {} issues)", path, state, report.messages, report.issues.len());
+                for issue in &report.issues {
+                    let severity = match issue.kind.severity() {
+                        Severity::Error => "error",
+                        Severity::Warning => "warning",
+                    };
+                    println!("  {}:{}:{}: {}: {}", path, issue.position.line, issue.position.column, severity, issue.kind);
+                }
+                if report.has_errors() {
+                    failure = Some(Failure::Parse(format!("{} is invalid", path).into()));
+                }
+            }
+            Err(e) => {
+                println!("{}: unreadable ({})", path, e);
+                failure = failure.or(Some(Failure::Io(format!("{} is unreadable", path).into())));
+            }
+        }
+    }
+    failure.map_or(Ok(()), Err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn convert_archives() {
+        let directory = std::env::temp_dir().join("messenger_archive_convert");
+        std::fs::create_dir_all(&directory).unwrap();
+        let json = directory.join("alice1234.json");
+        let json = json.to_str().unwrap();
+        assert!(convert(&args(&["--to", "json", "--output", json, "test/alice1234.xml"])).is_ok());
+        let output = std::fs::read_to_string(json).unwrap();
+        assert!(output.contains("\"sender_friendly_name\": \"Alice\""));
+
+        let xml = directory.join("alice@example.com.xml");
+        let xml = xml.to_str().unwrap();
+        assert!(convert(&args(&["--to", "xml", "-o", xml, "test/alice@example.com.html"])).is_ok());
+        // WLM does not save the system messages
+        assert_eq!(Parser::new(xml).unwrap().flatten().count(), 5);
+
+        assert!(matches!(convert(&args(&["--to", "pdf", "test/alice1234.xml"])), Err(Failure::Usage(_))));
+        assert!(matches!(convert(&args(&["--to", "mplus", "test/alice1234.xml"])), Err(Failure::Usage(_))));
+        assert!(matches!(convert(&args(&["test/alice1234.xml"])), Err(Failure::Usage(_))));
+    }
+
+    #[test]
+    fn validate_archives() {
+        assert!(validate(&args(&["test/alice1234.xml", "test/alice@example.com.html"])).is_ok());
+        assert!(matches!(validate(&[]), Err(Failure::Usage(_))));
+        assert!(matches!(validate(&args(&["test/missing.xml"])), Err(Failure::Io(_))));
+
+        let broken = std::env::temp_dir().join("messenger_archive_validate.xml");
+        std::fs::write(&broken, "<?xml version=\"1.0\"?><Log><Message>").unwrap();
+        let paths = args(&["test/alice1234.xml", broken.to_str().unwrap()]);
+        let failure = validate(&paths).err().unwrap();
+        assert!(matches!(failure, Failure::Parse(_)));
+        assert_eq!(failure.exit_code(), ExitCode::from(1));
+    }
+}
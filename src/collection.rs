@@ -0,0 +1,465 @@
+use crate::messenger::common;
+use crate::messenger::source::{self, ContainerKind};
+use crate::{ArchiveDetails, ContactList, FileType, IteratorItem, ListedContact, Message, MessengerArchive, Parser};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// The number of bytes read at the beginning of each file to detect archives.
+const HEADER_SIZE: u64 = 4096;
+/// The title of the Messenger Plus! event logs, which are not conversations (see `EventLogParser`).
+const EVENT_LOG_MARKER: &str = "Messenger Plus! Event Log";
+
+/// An archive found while scanning a directory.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ArchiveEntry {
+    /// The path to the archive file.
+    pub path: String,
+    /// Standard archive or Messenger Plus!
+    pub file_type: FileType,
+    /// The account owning the archive: the folder containing the `History` folder for WLM, or the
+    /// folder containing the archive otherwise.
+    pub account: String,
+    /// The contact exchanging with the account owner (see `ArchiveDetails::recipient_id`).
+    pub contact: String,
+}
+
+/// An archive entirely parsed by `ArchiveCollection::parse_all`, in one of its threads.
+pub struct ParsedArchive<'a> {
+    pub entry: &'a ArchiveEntry,
+    /// The messages, in the order of the archive. When the parsing failed, the messages read
+    /// before the error.
+    pub messages: Vec<Message>,
+    /// The details of the archive, if it was read until the end.
+    pub details: Option<ArchiveDetails>,
+    pub error: Option<Box<dyn Error>>,
+}
+
+/// All the archives found in a directory tree (e.g. a whole Windows profile).
+#[derive(Default)]
+pub struct ArchiveCollection {
+    entries: Vec<ArchiveEntry>,
+    errors: Vec<(String, Box<dyn Error>)>,
+    /// The contact list of the account, if one was attached.
+    contacts: Option<ContactList>,
+}
+
+impl ArchiveCollection {
+    /// Walks through the directory tree at `root` and detects every archive. The files that
+    /// cannot be read are reported by `errors()`, only an unreadable `root` returns an error. The
+    /// symbolic links are skipped.
+    pub fn scan(root: &str) -> Result<Self, Box<dyn Error>> {
+        let mut collection = ArchiveCollection::default();
+        let mut directories = vec![(root.to_string(), fs::read_dir(root)?)];
+        while let Some((directory_path, directory)) = directories.pop() {
+            for entry in directory {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        collection.errors.push((directory_path.clone(), Box::new(e)));
+                        continue;
+                    }
+                };
+                let path = entry.path();
+                // the symbolic links are not followed, they may loop
+                let file_type = match entry.file_type() {
+                    Ok(file_type) if file_type.is_symlink() => continue,
+                    Ok(file_type) => file_type,
+                    Err(e) => {
+                        collection.errors.push((path.display().to_string(), Box::new(e)));
+                        continue;
+                    }
+                };
+                if file_type.is_dir() {
+                    match fs::read_dir(&path) {
+                        Ok(directory) => directories.push((path.display().to_string(), directory)),
+                        Err(e) => collection.errors.push((path.display().to_string(), Box::new(e))),
+                    }
+                } else if let Some(kind) = ContainerKind::detect(&path) {
+                    collection.scan_container(&path, kind);
+                } else {
+                    match detect(&path) {
+                        Ok(Some(entry)) => collection.entries.push(entry),
+                        Ok(None) => {}
+                        Err(e) => collection.errors.push((path.display().to_string(), e)),
+                    }
+                }
+            }
+        }
+        collection.entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(collection)
+    }
+
+    /// Detects the archives inside a zip / tar container, without extracting them. Their paths go
+    /// through the container (e.g. `backup.zip/History/alice1234.xml`) and can be given to
+    /// `Parser::new`.
+    fn scan_container(&mut self, container: &Path, kind: ContainerKind) {
+        let wanted = |name: &str| file_type(Path::new(name)).is_some();
+        let headers = match source::read_headers(container, kind, HEADER_SIZE, wanted) {
+            Ok(headers) => headers,
+            Err(e) => {
+                self.errors.push((container.display().to_string(), e));
+                return;
+            }
+        };
+        for (name, header) in headers {
+            let path = container.join(&name);
+            let Some(file_type) = file_type(&path) else {
+                continue;
+            };
+            if let Some(entry) = archive_entry(&path, file_type, &decode_header(&header)) {
+                self.entries.push(entry);
+            }
+        }
+    }
+
+    /// The archives found, ordered by path.
+    pub fn entries(&self) -> &[ArchiveEntry] {
+        &self.entries
+    }
+
+    /// The files that could not be read while scanning, with the reason.
+    pub fn errors(&self) -> &[(String, Box<dyn Error>)] {
+        &self.errors
+    }
+
+    /// Attaches the contact list of the account (see `ContactList::parse`), to map the archives to
+    /// known contacts.
+    pub fn contact_list(mut self, contacts: ContactList) -> Self {
+        self.contacts = Some(contacts);
+        self
+    }
+
+    /// The contacts of the attached contact list, if any.
+    pub fn contacts(&self) -> Option<&ContactList> {
+        self.contacts.as_ref()
+    }
+
+    /// The listed contact the archive was saved for, if a contact list is attached.
+    pub fn contact(&self, entry: &ArchiveEntry) -> Option<&ListedContact> {
+        self.contacts.as_ref()?.find(&entry.contact)
+    }
+
+    /// The archives saved for a contact missing from the attached contact list (e.g. a contact
+    /// removed since). Empty if no contact list is attached.
+    pub fn unlisted_entries(&self) -> Vec<&ArchiveEntry> {
+        let Some(contacts) = &self.contacts else {
+            return vec![];
+        };
+        self.entries
+            .iter()
+            .filter(|entry| contacts.candidates(&entry.contact).is_empty())
+            .collect()
+    }
+
+    /// The archives matching several contacts of the attached contact list, with these contacts
+    /// (see `ContactList::candidates`).
+    pub fn ambiguous_entries(&self) -> Vec<(&ArchiveEntry, Vec<&ListedContact>)> {
+        let Some(contacts) = &self.contacts else {
+            return vec![];
+        };
+        self.entries
+            .iter()
+            .map(|entry| (entry, contacts.candidates(&entry.contact)))
+            .filter(|(_, candidates)| candidates.len() > 1)
+            .collect()
+    }
+
+    /// The contacts of the attached contact list without any archive, even an ambiguous one.
+    pub fn contacts_without_archive(&self) -> Vec<&ListedContact> {
+        let Some(contacts) = &self.contacts else {
+            return vec![];
+        };
+        let archived: HashSet<&str> = self
+            .entries
+            .iter()
+            .flat_map(|entry| contacts.candidates(&entry.contact))
+            .map(|contact| contact.email.as_str())
+            .collect();
+        contacts
+            .contacts()
+            .iter()
+            .filter(|contact| !archived.contains(contact.email.as_str()))
+            .collect()
+    }
+
+    /// The archives grouped by account, then by contact.
+    pub fn by_account(&self) -> BTreeMap<&str, BTreeMap<&str, Vec<&ArchiveEntry>>> {
+        let mut accounts: BTreeMap<&str, BTreeMap<&str, Vec<&ArchiveEntry>>> = BTreeMap::new();
+        for entry in &self.entries {
+            accounts
+                .entry(&entry.account)
+                .or_default()
+                .entry(&entry.contact)
+                .or_default()
+                .push(entry);
+        }
+        accounts
+    }
+
+    /// Parses all the archives in parallel, with the given count of `threads` (or one per CPU if
+    /// `0`). `handle` is called from the threads with each archive once it is parsed, in any order,
+    /// with the count of archives parsed and the total count. Only the archives being handled are
+    /// kept in memory.
+    pub fn parse_all<F>(&self, threads: usize, handle: F)
+    where
+        F: Fn(usize, usize, ParsedArchive<'_>) + Sync,
+    {
+        let threads = match threads {
+            0 => thread::available_parallelism().map_or(1, |count| count.get()),
+            count => count,
+        };
+        let entries = &self.entries;
+        let next = AtomicUsize::new(0);
+        let done = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for _ in 0..threads.min(entries.len()) {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(entry) = entries.get(index) else {
+                        return;
+                    };
+                    let archive = parse_entry(entry);
+                    handle(done.fetch_add(1, Ordering::Relaxed) + 1, entries.len(), archive);
+                });
+            }
+        });
+    }
+
+    /// Iterates through the messages of all the archives, one archive after the other.
+    /// When an archive cannot be parsed, the error is returned and the iteration continues with
+    /// the next archive.
+    pub fn messages(&self) -> CollectionMessages<'_> {
+        CollectionMessages {
+            entries: self.entries.iter(),
+            current: None,
+        }
+    }
+}
+
+/// An iterator through the messages of an `ArchiveCollection`, returning each message with the
+/// archive it comes from.
+pub struct CollectionMessages<'a> {
+    entries: std::slice::Iter<'a, ArchiveEntry>,
+    current: Option<(&'a ArchiveEntry, Parser<'a>)>,
+}
+
+impl<'a> Iterator for CollectionMessages<'a> {
+    type Item = (&'a ArchiveEntry, IteratorItem);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((entry, parser)) = &mut self.current {
+                let entry: &'a ArchiveEntry = entry;
+                match parser.next() {
+                    Some(Ok(message)) => return Some((entry, Ok(message))),
+                    Some(Err(e)) => {
+                        // the parser cannot recover from an error, skip the rest of the archive
+                        self.current = None;
+                        return Some((entry, Err(e)));
+                    }
+                    None => self.current = None,
+                }
+            }
+            let entry = self.entries.next()?;
+            match Parser::new(&entry.path) {
+                Ok(parser) => self.current = Some((entry, parser)),
+                Err(e) => return Some((entry, Err(e))),
+            }
+        }
+    }
+}
+
+fn parse_entry(entry: &ArchiveEntry) -> ParsedArchive<'_> {
+    let mut archive = ParsedArchive {
+        entry,
+        messages: vec![],
+        details: None,
+        error: None,
+    };
+    let mut parser = match Parser::new(&entry.path) {
+        Ok(parser) => parser,
+        Err(e) => {
+            archive.error = Some(e);
+            return archive;
+        }
+    };
+    for message in parser.by_ref() {
+        match message {
+            Ok(message) => archive.messages.push(message),
+            // the parser cannot recover from an error, skip the rest of the archive
+            Err(e) => {
+                archive.error = Some(e);
+                return archive;
+            }
+        }
+    }
+    archive.details = parser.details().cloned();
+    archive
+}
+
+/// Returns the archive details if the file at `path` looks like a conversation archive.
+fn detect(path: &Path) -> Result<Option<ArchiveEntry>, Box<dyn Error>> {
+    let Some(file_type) = file_type(path) else {
+        return Ok(None);
+    };
+    let header = read_header(path)?;
+    path.to_str().ok_or("The path is not valid UTF-8")?;
+    Ok(archive_entry(path, file_type, &header))
+}
+
+/// The type of archive the file may be, according to its extension.
+fn file_type(path: &Path) -> Option<FileType> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    match extension.as_str() {
+        "xml" => Some(FileType::XML),
+        "html" | "htm" => Some(FileType::MessengerPlus),
+        _ => None,
+    }
+}
+
+/// Returns the archive details if the beginning of the file looks like a conversation archive.
+fn archive_entry(path: &Path, file_type: FileType, header: &str) -> Option<ArchiveEntry> {
+    let markers: &[&str] = match file_type {
+        FileType::XML => &["<Log"],
+        FileType::MessengerPlus => &["mplsession", "Messenger Plus!"],
+    };
+    if !markers.iter().any(|marker| header.contains(marker)) || header.contains(EVENT_LOG_MARKER) {
+        return None;
+    }
+
+    Some(ArchiveEntry {
+        path: path.to_str()?.to_string(),
+        file_type,
+        account: account_name(path),
+        contact: path
+            .file_stem()
+            .unwrap_or_default()
+            .to_str()
+            .unwrap_or_default()
+            .to_string(),
+    })
+}
+
+/// Reads the beginning of the file as text.
+fn read_header(path: &Path) -> Result<String, Box<dyn Error>> {
+    let mut buffer = Vec::new();
+    fs::File::open(path)?.take(HEADER_SIZE).read_to_end(&mut buffer)?;
+    Ok(decode_header(&buffer))
+}
+
+/// Decodes the beginning of a file as text, whatever its encoding (UTF-8 or UTF-16).
+fn decode_header(content: &[u8]) -> String {
+    common::decode_text(&content[..content.len().min(HEADER_SIZE as usize)])
+}
+
+fn account_name(path: &Path) -> String {
+    let parent = path.parent();
+    let directory = match parent.and_then(|p| p.file_name()) {
+        Some(name) if name.eq_ignore_ascii_case("History") => parent.and_then(|p| p.parent()),
+        _ => parent,
+    };
+    directory
+        .and_then(|d| d.file_name())
+        .and_then(|name| name.to_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_test_directory() {
+        let collection = ArchiveCollection::scan("test").unwrap();
+        let contacts: Vec<&str> = collection.entries().iter().map(|e| e.contact.as_str()).collect();
+        assert_eq!(contacts, vec!["alice1234", "alice@example.com", "scrappy"]);
+        assert_eq!(collection.entries()[1].file_type, FileType::MessengerPlus);
+        assert!(collection.errors().is_empty());
+
+        let accounts = collection.by_account();
+        assert_eq!(accounts["test"].len(), 3);
+        assert_eq!(collection.messages().filter(|(_, m)| m.is_ok()).count(), 8);
+    }
+
+    #[test]
+    fn map_archives_to_contacts() {
+        let content = "<?xml version=\"1.0\"?><messenger><service><contactlist>\
+            <contact type=\"1\">alice@example.com</contact><contact type=\"1\">carol@example.com</contact>\
+            </contactlist></service></messenger>";
+        let collection = ArchiveCollection::scan("test")
+            .unwrap()
+            .contact_list(ContactList::from_content(content.as_bytes()).unwrap());
+        let emails: Vec<Option<&str>> = collection
+            .entries()
+            .iter()
+            .map(|entry| collection.contact(entry).map(|contact| contact.email.as_str()))
+            .collect();
+        assert_eq!(emails, vec![Some("alice@example.com"), Some("alice@example.com"), None]);
+        let unlisted: Vec<&str> = collection.unlisted_entries().iter().map(|entry| entry.contact.as_str()).collect();
+        assert_eq!(unlisted, vec!["scrappy"]);
+        assert_eq!(collection.contacts_without_archive(), vec![&collection.contacts().unwrap().contacts()[1]]);
+        assert!(collection.ambiguous_entries().is_empty());
+    }
+
+    #[test]
+    fn report_broken_archives() {
+        let root = std::env::temp_dir().join("report_broken_archives");
+        let history = root.join("bob@example.com").join("History");
+        fs::create_dir_all(&history).unwrap();
+        fs::copy("test/alice1234.xml", history.join("alice1234.xml")).unwrap();
+        fs::write(history.join("broken.xml"), "<?xml version=\"1.0\"?><Log><Message>").unwrap();
+        fs::write(history.join("notes.xml"), "<?xml version=\"1.0\"?><Notes/>").unwrap();
+        #[cfg(unix)]
+        if !history.join("loop").exists() {
+            std::os::unix::fs::symlink(&root, history.join("loop")).unwrap();
+        }
+
+        let collection = ArchiveCollection::scan(root.to_str().unwrap()).unwrap();
+        assert_eq!(collection.entries().len(), 2);
+        assert_eq!(collection.entries()[0].account, "bob@example.com");
+
+        let results: Vec<(&str, bool)> = collection
+            .messages()
+            .map(|(entry, message)| (entry.contact.as_str(), message.is_ok()))
+            .collect();
+        assert_eq!(results, vec![("alice1234", true), ("alice1234", true), ("broken", false)]);
+
+        let parsed = std::sync::Mutex::new(vec![]);
+        collection.parse_all(2, |_, _, archive| {
+            let recipient = archive.details.map(|details| details.recipient_id);
+            let error = archive.error.map(|e| e.to_string());
+            parsed.lock().unwrap().push((archive.entry.contact.clone(), archive.messages.len(), recipient, error));
+        });
+        let mut parsed = parsed.into_inner().unwrap();
+        parsed.sort();
+        assert_eq!(parsed[0], ("alice1234".to_string(), 2, Some("alice1234".to_string()), None));
+        assert_eq!((parsed[1].1, parsed[1].2.as_ref()), (0, None));
+        assert!(parsed[1].3.is_some());
+    }
+
+    #[test]
+    fn parse_all_in_parallel() {
+        let collection = ArchiveCollection::scan("test").unwrap();
+        let entries = collection.entries();
+        let parsed = std::sync::Mutex::new(vec![]);
+        collection.parse_all(0, |done, total, archive| {
+            let index = entries.iter().position(|entry| std::ptr::eq(entry, archive.entry)).unwrap();
+            parsed.lock().unwrap().push((index, done, total, archive.messages));
+        });
+        let mut parsed = parsed.into_inner().unwrap();
+        let mut progress: Vec<(usize, usize)> = parsed.iter().map(|(_, done, total, _)| (*done, *total)).collect();
+        progress.sort();
+        assert_eq!(progress, vec![(1, 3), (2, 3), (3, 3)]);
+
+        parsed.sort_by_key(|(index, ..)| *index);
+        let serial: Vec<Message> = collection.messages().filter_map(|(_, message)| message.ok()).collect();
+        let messages: Vec<Message> = parsed.into_iter().flat_map(|(.., messages)| messages).collect();
+        assert_eq!(messages, serial);
+    }
+}
@@ -0,0 +1,148 @@
+use crate::messenger::common;
+use crate::messenger::source::ArchiveSource;
+use std::collections::HashMap;
+use std::error::Error;
+use xml::reader::XmlEvent;
+
+/// A contact listed in a contact list export.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ListedContact {
+    /// The e-mail address of the contact's account.
+    pub email: String,
+    /// The `type` attribute saved by the latest WLM versions (e.g. `1` for a Messenger contact).
+    pub contact_type: Option<String>,
+}
+
+/// A contact list exported by WLM as a `.ctt` file:
+/// ```xml
+/// <messenger>
+///   <service name=".NET Messenger Service">
+///     <contactlist>
+///       <contact type="1">alice@example.com</contact>
+///     </contactlist>
+///   </service>
+/// </messenger>
+/// ```
+/// It gives the e-mail addresses the archives are named after (see
+/// `ArchiveCollection::contact_list`).
+#[derive(Default, PartialEq, Debug, Clone)]
+pub struct ContactList {
+    contacts: Vec<ListedContact>,
+    /// The positions of the contacts, by lowercase e-mail address.
+    emails: HashMap<String, usize>,
+    /// The positions of the contacts, by lowercase user name (the e-mail address before the `@`).
+    users: HashMap<String, Vec<usize>>,
+}
+
+impl ContactList {
+    /// Reads the contact list exported at `path`.
+    pub fn parse(path: &str) -> Result<Self, Box<dyn Error>> {
+        Self::from_content(&ArchiveSource::open(path)?.read()?)
+    }
+
+    /// Reads a contact list already in memory.
+    pub fn from_content(content: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = common::get_content_parser(content.to_vec());
+        let mut list = ContactList::default();
+        let mut parents: Vec<String> = vec![];
+        loop {
+            match reader.next()? {
+                XmlEvent::StartElement { name, attributes, .. } => {
+                    if parents.is_empty() && name.local_name != "messenger" {
+                        return Err("The file is not a contact list".into());
+                    }
+                    if name.local_name == "contact" {
+                        list.contacts.push(ListedContact {
+                            email: String::new(),
+                            contact_type: common::parse_attributes(&attributes).get("type").map(str::to_string),
+                        });
+                    }
+                    parents.push(name.local_name);
+                }
+                XmlEvent::Characters(data) if parents.last().is_some_and(|parent| parent == "contact") => {
+                    if let Some(contact) = list.contacts.last_mut() {
+                        contact.email.push_str(data.trim());
+                    }
+                }
+                XmlEvent::EndElement { .. } => {
+                    parents.pop();
+                }
+                XmlEvent::EndDocument => break,
+                _ => {}
+            }
+        }
+        list.contacts.retain(|contact| !contact.email.is_empty());
+        for (position, contact) in list.contacts.iter().enumerate() {
+            let email = contact.email.to_lowercase();
+            let user = email.split('@').next().unwrap_or_default().to_string();
+            list.emails.entry(email).or_insert(position);
+            list.users.entry(user).or_default().push(position);
+        }
+        Ok(list)
+    }
+
+    /// The contacts, in the order of the list.
+    pub fn contacts(&self) -> &[ListedContact] {
+        &self.contacts
+    }
+
+    /// The contact an archive was saved for, from its recipient ID (see
+    /// `ArchiveDetails::recipient_id`): Messenger Plus! names the archives after the e-mail address
+    /// of the contact (`alice@example.com`), WLM after its user name followed by a number
+    /// (`alice1234`). None if no contact or several ones match (see `candidates`).
+    pub fn find(&self, recipient_id: &str) -> Option<&ListedContact> {
+        match self.candidates(recipient_id)[..] {
+            [contact] => Some(contact),
+            _ => None,
+        }
+    }
+
+    /// The contacts an archive may have been saved for: the one with the e-mail address
+    /// `recipient_id`, otherwise the ones with the longest user name followed by a number. Several
+    /// contacts are returned when the user name is ambiguous (e.g. `alice@example.com` and
+    /// `alice@example.org` for `alice1234`).
+    pub fn candidates(&self, recipient_id: &str) -> Vec<&ListedContact> {
+        let recipient_id = recipient_id.to_lowercase();
+        if let Some(position) = self.emails.get(&recipient_id) {
+            return vec![&self.contacts[*position]];
+        }
+        let user = recipient_id.trim_end_matches(|c: char| c.is_ascii_digit());
+        // the user name may end with digits too: the longest one is tried first
+        (user.len()..recipient_id.len())
+            .rev()
+            .find_map(|length| self.users.get(&recipient_id[..length]))
+            .map_or(vec![], |positions| positions.iter().map(|position| &self.contacts[*position]).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_contact_list() {
+        let content = "<?xml version=\"1.0\"?>\n<messenger><service name=\".NET Messenger Service\"><contactlist>\
+            <contact type=\"1\">alice@example.com</contact><contact>\n  bob@example.com\n</contact><contact/>\
+            </contactlist></service></messenger>";
+        let list = ContactList::from_content(content.as_bytes()).unwrap();
+        let emails: Vec<&str> = list.contacts().iter().map(|contact| contact.email.as_str()).collect();
+        assert_eq!(emails, vec!["alice@example.com", "bob@example.com"]);
+        assert_eq!(list.contacts()[0].contact_type.as_deref(), Some("1"));
+
+        assert_eq!(list.find("alice1234"), Some(&list.contacts()[0]));
+        assert_eq!(list.find("Bob@Example.com"), Some(&list.contacts()[1]));
+        assert_eq!(list.find("alice"), None);
+        assert_eq!(list.find("alicia1234"), None);
+
+        let content = "<?xml version=\"1.0\"?><messenger><service><contactlist>\
+            <contact>alice@example.com</contact><contact>alice12@example.com</contact>\
+            <contact>bob@example.com</contact><contact>bob@example.org</contact>\
+            </contactlist></service></messenger>";
+        let list = ContactList::from_content(content.as_bytes()).unwrap();
+        assert_eq!(list.find("alice1234").unwrap().email, "alice12@example.com");
+        assert_eq!(list.find("alice5678").unwrap().email, "alice@example.com");
+        assert_eq!(list.find("bob1234"), None);
+        assert_eq!(list.candidates("bob1234").len(), 2);
+        assert!(ContactList::from_content(b"<?xml version=\"1.0\"?><Log/>").is_err());
+    }
+}
@@ -0,0 +1,277 @@
+use crate::{ArchiveDetails, Message, MessengerArchive, Parser};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+
+/// The ID given to the owner of a WLM archive, when it is unknown.
+pub const UNKNOWN_OWNER: &str = "owner";
+
+/// A message, with the stable IDs of its sender and receiver.
+#[derive(PartialEq, Debug)]
+pub struct IdentifiedMessage {
+    pub message: Message,
+    /// The ID of the sender, empty for the system messages.
+    pub sender_id: String,
+    /// The ID of the receiver, empty for the system messages.
+    pub receiver_id: String,
+}
+
+/// A nickname used by a contact.
+#[derive(PartialEq, Debug)]
+pub struct Nickname {
+    pub friendly_name: String,
+    /// The date of the first message sent or received with this nickname.
+    pub first_seen: String,
+    /// The date of the last message sent or received with this nickname.
+    pub last_seen: String,
+    /// The number of messages sent with this nickname.
+    pub messages: usize,
+}
+
+/// A participant of the conversations, with all the nicknames it used.
+#[derive(PartialEq, Debug)]
+pub struct Contact {
+    /// The e-mail address of the contact when known, otherwise the recipient ID of the archive or
+    /// the owner ID.
+    pub id: String,
+    /// The nicknames, in the order they were first used.
+    pub nicknames: Vec<Nickname>,
+}
+
+/// The contacts of an archive, by ID.
+#[derive(Default, PartialEq, Debug)]
+pub struct ContactRegistry {
+    contacts: BTreeMap<String, Contact>,
+}
+
+impl ContactRegistry {
+    pub fn contacts(&self) -> impl Iterator<Item = &Contact> {
+        self.contacts.values()
+    }
+
+    pub fn contact(&self, id: &str) -> Option<&Contact> {
+        self.contacts.get(id)
+    }
+
+    fn record(&mut self, id: &str, friendly_name: &str, datetime: &str, sent: bool) {
+        let contact = self.contacts.entry(id.to_string()).or_insert_with(|| Contact {
+            id: id.to_string(),
+            nicknames: vec![],
+        });
+        let index = match contact
+            .nicknames
+            .iter()
+            .position(|nickname| nickname.friendly_name == friendly_name)
+        {
+            Some(index) => index,
+            None => {
+                contact.nicknames.push(Nickname {
+                    friendly_name: friendly_name.to_string(),
+                    first_seen: datetime.to_string(),
+                    last_seen: datetime.to_string(),
+                    messages: 0,
+                });
+                contact.nicknames.len() - 1
+            }
+        };
+        let nickname = &mut contact.nicknames[index];
+        nickname.last_seen = datetime.to_string();
+        if sent {
+            nickname.messages += 1;
+        }
+    }
+}
+
+/// Resolves the participants of the messages of an archive, whatever the nicknames they used.
+///
+/// The e-mail addresses listed by Messenger Plus! identify the participants directly. Otherwise,
+/// the sessions are two-way conversations: the nicknames sending messages to the same nickname
+/// belong to the same participant, and the same nickname always belongs to the same participant.
+/// The participant whose nicknames look like the recipient ID of the archive is the recipient,
+/// otherwise the first sender is assumed to be the owner.
+#[derive(Default)]
+pub struct IdentityResolver {
+    owner: Option<String>,
+}
+
+/// The nicknames, grouped by participant: a union-find structure where each nickname knows if
+/// it belongs to the same participant as its parent (parity `false`) or to the other one.
+#[derive(Default)]
+struct Parties {
+    parents: HashMap<String, (String, bool)>,
+}
+
+impl Parties {
+    /// Returns the root of the nickname, and whether the nickname is on the other side.
+    fn find(&mut self, name: &str) -> (String, bool) {
+        let Some((parent, parity)) = self.parents.get(name).cloned() else {
+            self.parents.insert(name.to_string(), (name.to_string(), false));
+            return (name.to_string(), false);
+        };
+        if parent == name {
+            return (parent, false);
+        }
+        let (root, parent_parity) = self.find(&parent);
+        let parity = parity ^ parent_parity;
+        self.parents.insert(name.to_string(), (root.clone(), parity));
+        (root, parity)
+    }
+
+    /// Records that two nicknames belong to two different participants. Ignored if it
+    /// contradicts what is already known (e.g. both participants used the same nickname).
+    fn oppose(&mut self, a: &str, b: &str) {
+        let (root_a, parity_a) = self.find(a);
+        let (root_b, parity_b) = self.find(b);
+        if root_a != root_b {
+            self.parents.insert(root_b, (root_a, !(parity_a ^ parity_b)));
+        }
+    }
+}
+
+impl IdentityResolver {
+    pub fn new() -> Self {
+        IdentityResolver::default()
+    }
+
+    /// Sets the ID of the owner of the archives (e.g. the account found by `ArchiveCollection`),
+    /// used when the archive does not save it. Defaults to `UNKNOWN_OWNER`.
+    pub fn owner(mut self, id: &str) -> Self {
+        self.owner = Some(id.to_string());
+        self
+    }
+
+    /// Reads all the messages of the archive and resolves their participants.
+    pub fn resolve_archive(&self, mut parser: Parser) -> Result<(ContactRegistry, Vec<IdentifiedMessage>), Box<dyn Error>> {
+        let messages = parser.by_ref().collect::<Result<Vec<Message>, _>>()?;
+        let details = parser.details().cloned().unwrap_or_default();
+        Ok(self.resolve(messages, &details))
+    }
+
+    /// Resolves the participants of the messages of an archive.
+    pub fn resolve(&self, messages: Vec<Message>, details: &ArchiveDetails) -> (ContactRegistry, Vec<IdentifiedMessage>) {
+        let emails: HashMap<(&str, &str), &str> = details
+            .participants
+            .iter()
+            .map(|p| ((p.session_id.as_str(), p.friendly_name.as_str()), p.email.as_str()))
+            .collect();
+
+        let mut parties = Parties::default();
+        for message in messages.iter().filter(|m| Self::is_exchanged(m)) {
+            parties.oppose(&message.sender_friendly_name, &message.receiver_friendly_name);
+        }
+
+        // for each group of nicknames, the side of the recipient
+        let mut recipient_sides: HashMap<String, bool> = HashMap::new();
+        for message in messages.iter().filter(|m| Self::is_exchanged(m)) {
+            let (root, side) = parties.find(&message.sender_friendly_name);
+            let email = emails.get(&(&*message.session_id, &*message.sender_friendly_name));
+            if let Some(email) = email {
+                recipient_sides.insert(root, if *email == details.recipient_id { side } else { !side });
+            } else if Self::looks_like(&message.sender_friendly_name, &details.recipient_id) {
+                recipient_sides.entry(root).or_insert(side);
+            }
+        }
+
+        let owner = self.owner.as_deref().unwrap_or(UNKNOWN_OWNER);
+        let mut registry = ContactRegistry::default();
+        let mut identified = vec![];
+        for message in messages {
+            let (sender_id, receiver_id) = if Self::is_exchanged(&message) {
+                let (root, side) = parties.find(&message.sender_friendly_name);
+                // the first sender is assumed to be the owner
+                let recipient_side = *recipient_sides.entry(root).or_insert(!side);
+                let id = |name: &str, is_recipient: bool| {
+                    let email = emails.get(&(&*message.session_id, name));
+                    match (email, is_recipient) {
+                        (Some(email), _) => email.to_string(),
+                        (None, true) => details.recipient_id.clone(),
+                        (None, false) => owner.to_string(),
+                    }
+                };
+                let sender_id = id(&message.sender_friendly_name, side == recipient_side);
+                let receiver_id = id(&message.receiver_friendly_name, side != recipient_side);
+                registry.record(&sender_id, &message.sender_friendly_name, &message.datetime, true);
+                registry.record(&receiver_id, &message.receiver_friendly_name, &message.datetime, false);
+                (sender_id, receiver_id)
+            } else {
+                (String::new(), String::new())
+            };
+            identified.push(IdentifiedMessage {
+                message,
+                sender_id,
+                receiver_id,
+            });
+        }
+        (registry, identified)
+    }
+
+    /// The system messages have neither sender nor receiver.
+    fn is_exchanged(message: &Message) -> bool {
+        !message.sender_friendly_name.is_empty() && !message.receiver_friendly_name.is_empty()
+    }
+
+    /// Checks if a nickname looks like an account ID (e.g. `Alice` and `alice1234`).
+    fn looks_like(friendly_name: &str, id: &str) -> bool {
+        let account: String = id
+            .split('@')
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .filter(|c| c.is_alphabetic())
+            .flat_map(char::to_lowercase)
+            .collect();
+        let name: String = friendly_name
+            .chars()
+            .filter(|c| c.is_alphabetic())
+            .flat_map(char::to_lowercase)
+            .collect();
+        !account.is_empty() && !name.is_empty() && (name.contains(&account) || account.contains(&name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_xml_archive_nicknames() {
+        let mut messages: Vec<Message> = Parser::new("test/alice1234.xml").unwrap().flatten().collect();
+        let mut renamed = messages[0].clone();
+        renamed.sender_friendly_name = "Alice is away".into();
+        renamed.datetime = "2009-04-06T21:00:00.000Z".to_string();
+        messages.push(renamed);
+        let details = ArchiveDetails {
+            recipient_id: "alice1234".to_string(),
+            ..ArchiveDetails::default()
+        };
+
+        let (registry, identified) = IdentityResolver::new().owner("bob@example.com").resolve(messages, &details);
+        let ids: Vec<(&str, &str)> = identified
+            .iter()
+            .map(|m| (m.sender_id.as_str(), m.receiver_id.as_str()))
+            .collect();
+        assert_eq!(
+            ids,
+            vec![
+                ("alice1234", "bob@example.com"),
+                ("bob@example.com", "alice1234"),
+                ("alice1234", "bob@example.com"),
+            ]
+        );
+        let alice = registry.contact("alice1234").unwrap();
+        assert_eq!(alice.nicknames.len(), 2);
+        assert_eq!(alice.nicknames[0].friendly_name, "Alice");
+        assert_eq!(alice.nicknames[0].messages, 1);
+        assert_eq!(alice.nicknames[1].first_seen, "2009-04-06T21:00:00.000Z");
+    }
+
+    #[test]
+    fn resolve_messenger_plus_emails() {
+        let parser = Parser::new("test/alice@example.com.html").unwrap();
+        let (registry, identified) = IdentityResolver::new().resolve_archive(parser).unwrap();
+        assert_eq!(identified[0].sender_id, "bob@example.com");
+        assert_eq!(identified[0].receiver_id, "alice@example.com");
+        assert_eq!(identified[5].sender_id, "");
+        assert_eq!(registry.contacts().count(), 2);
+        assert_eq!(registry.contact("bob@example.com").unwrap().nicknames[0].messages, 3);
+    }
+}
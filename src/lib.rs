@@ -6,6 +6,9 @@
 //!
 //! Archives generated by the _Messenger Plus!_ plugin are supported too.
 //!
+//! Messages can also be written back as a _Windows Live Messenger_ XML archive with `XmlWriter`,
+//! or as a _Messenger Plus!_ archive with `MessengerPlusWriter`.
+//!
 //! # Exemple
 //!```
 //! use lib_messenger_archive::{Parser, FileType, MessengerArchive, Message, Data, Text};
@@ -14,9 +17,9 @@
 //! let expected =  Message {
 //!                 datetime: "2009-04-06T19:40:41.851Z".to_string(),
 //!                 timezone_offset: Some(120),
-//!                 session_id: "1".to_string(),
-//!                 sender_friendly_name: "Alice".to_string(),
-//!                 receiver_friendly_name: "Bob".to_string(),
+//!                 session_id: "1".into(),
+//!                 sender_friendly_name: "Alice".into(),
+//!                 receiver_friendly_name: "Bob".into(),
 //!                 data: vec![Data::Text(Text {
 //!                     style: "font-family:Courier New; color:#004000; ".to_string(),
 //!                     content: "Hello!".to_string(),
@@ -24,19 +27,70 @@
 //!             };
 //! assert_eq!(parser.next().unwrap().unwrap(), expected);
 //! ```
+mod anonymize;
+#[cfg(feature = "async")]
+mod async_parser;
+mod collection;
+mod contact_list;
+mod identity;
+mod merge;
+mod reverse;
 mod messenger;
+mod search;
+#[cfg(feature = "sqlite")]
+mod sqlite_store;
+mod stats;
+mod validate;
 
+use chrono::NaiveDateTime;
 use std::error;
-pub use crate::messenger::{ArchiveDetails, Message, MessengerArchive};
-pub use crate::messenger::{FileType, Data, Text, Image};
+pub use crate::anonymize::{Anonymized, Anonymizer};
+#[cfg(feature = "async")]
+pub use crate::async_parser::AsyncParser;
+pub use crate::collection::{ArchiveCollection, ArchiveEntry, CollectionMessages, ParsedArchive};
+pub use crate::contact_list::{ContactList, ListedContact};
+pub use crate::identity::{Contact, ContactRegistry, IdentifiedMessage, IdentityResolver, Nickname, UNKNOWN_OWNER};
+pub use crate::merge::{MergedMessage, Merger};
+pub use crate::reverse::{ReverseMessages, ReverseSessions};
+pub use crate::messenger::{ArchiveDetails, Message, MessengerArchive, SessionParticipant};
+pub use crate::messenger::{FileType, Data, Text, Image, Emoticon, UnknownElement};
+pub use crate::messenger::{emoticon, formatting};
+pub use crate::messenger::date_format::DateFormat;
+pub use crate::messenger::event_log::{ContactEvent, EventKind, EventLogParser, Status, StatusHistory};
+pub use crate::messenger::session_index::{IndexedSession, SessionIndex};
+pub use crate::messenger::source::{ArchiveSource, ContainerKind};
+pub use crate::messenger::timezone::{Normalizer, TimeZoneSpec};
+pub use crate::messenger::export;
+pub use crate::messenger::filter::MessageFilter;
+pub use crate::search::{search, search_collection, ArchiveErrors, Hit, Query, SearchIndex};
+pub use crate::stats::{ParticipantStats, SessionStats, Statistics};
+pub use crate::validate::{validate, Issue, IssueKind, Position, Severity, ValidationReport};
+pub use crate::messenger::messenger_plus_writer::MessengerPlusWriter;
+pub use crate::messenger::xml_writer::XmlWriter;
+#[cfg(feature = "sqlite")]
+pub use crate::sqlite_store::{SqliteMessages, SqliteStore};
 
 
 type IteratorItem = Result<Message, Box<dyn error::Error>>;
+/// A function returning the part of an archive to parse, from a session (see `SessionIndex`).
+type ArchivePart = fn(&SessionIndex, &ArchiveSource, &IndexedSession) -> Result<Vec<u8>, Box<dyn error::Error>>;
 
 /// This structure automatically handle the various archives types, based on their extensions and
 /// call the appropriate parser (Classic XML archive parser or Messenger Plus! parser).
 pub struct Parser<'a> {
-    parser: Box<dyn MessengerArchive<Item=IteratorItem> + 'a>,
+    parser: Box<dyn MessengerArchive<Item=IteratorItem> + Send + 'a>,
+    /// Where the archive is read from, to resume the parsing at a session.
+    source: ArchiveSource,
+    file_type: FileType,
+    index: Option<SessionIndex>,
+    date_format: Option<DateFormat>,
+    raw: bool,
+    filter: MessageFilter,
+    emoticons: bool,
+    formatting: bool,
+    normalizer: Option<Normalizer>,
+    /// The message returned by `next_ref`, reused from one message to the other.
+    buffer: Message,
 }
 
 impl<'a> Parser<'a> {
@@ -44,12 +98,258 @@ impl<'a> Parser<'a> {
     /// Generates a new parser for the given file at `path`.
     /// It may return an error if the file cannot be read.
     pub fn new(path: &'a str) -> Result<Self, Box<dyn error::Error>> {
-        Ok(Parser {
-            parser: Self::get_parser(path)?,
+        let parser = Self::get_parser(path)?;
+        Ok(Self::with_parser(parser, ArchiveSource::open(path)?, FileType::from_path(path)))
+    }
+
+    /// Generates a new parser for an archive of the given type, read from `source` (e.g. an
+    /// archive already in memory).
+    pub fn from_source(source: ArchiveSource, file_type: FileType) -> Result<Self, Box<dyn error::Error>> {
+        let parser: Box<dyn MessengerArchive<Item=IteratorItem> + Send> = match file_type {
+            FileType::XML => Box::new(messenger::xml_parser::XmlParser::from_source(source.clone())?),
+            FileType::MessengerPlus => Box::new(messenger::messenger_plus_parser::MessengerPlusParser::from_source(source.clone())?),
+        };
+        Ok(Self::with_parser(parser, source, file_type))
+    }
+
+    /// Generates a new parser for an archive of the given type, parsed while it is read from
+    /// `reader`. The `name` of the archive file gives the recipient of the conversation (see
+    /// `ArchiveDetails::recipient_id`). The archive is read only once, so it cannot be indexed
+    /// (see `seek`) and the images of the Messenger Plus! archives keep an empty content.
+    pub fn from_reader<R: std::io::Read + Send + 'static>(reader: R, name: &str, file_type: FileType) -> Self {
+        let source = ArchiveSource::Stream { name: name.to_string() };
+        let reader: Box<dyn std::io::Read + Send> = Box::new(reader);
+        let parser: Box<dyn MessengerArchive<Item=IteratorItem> + Send> = match file_type {
+            FileType::XML => Box::new(messenger::xml_parser::XmlParser::from_reader(source.clone(), reader)),
+            FileType::MessengerPlus => Box::new(messenger::messenger_plus_parser::MessengerPlusParser::from_reader(source.clone(), reader)),
+        };
+        Self::with_parser(parser, source, file_type)
+    }
+
+    fn with_parser(
+        parser: Box<dyn MessengerArchive<Item=IteratorItem> + Send + 'a>,
+        source: ArchiveSource,
+        file_type: FileType,
+    ) -> Self {
+        Parser {
+            parser,
+            source,
+            file_type,
+            index: None,
+            date_format: None,
+            raw: false,
+            filter: MessageFilter::default(),
+            emoticons: false,
+            formatting: false,
+            normalizer: None,
+            buffer: Message::default(),
+        }
+    }
+
+    /// Only returns the messages sent by a user whose friendly name contains `sender` (case
+    /// insensitive).
+    pub fn from(self, sender: &str) -> Self {
+        self.filtered(|filter| filter.sender = Some(sender.to_string()))
+    }
+
+    /// Only returns the messages sent between `since` and `until` (included), compared with their
+    /// local dates: the dates saved in the archive with the timezone offset of each message, when
+    /// known. With Messenger Plus! archives, the sessions outside this range are skipped.
+    pub fn between(self, since: NaiveDateTime, until: NaiveDateTime) -> Self {
+        self.filtered(|filter| {
+            filter.since = Some(since);
+            filter.until = Some(until);
+        })
+    }
+
+    /// Only returns the messages containing the given `text` (case insensitive). May be called
+    /// multiple times, all the texts must be found.
+    pub fn containing(self, text: &str) -> Self {
+        self.filtered(|filter| filter.containing.push(text.to_string()))
+    }
+
+    /// Only returns the messages containing an image.
+    pub fn with_images(self) -> Self {
+        self.filtered(|filter| filter.with_images = true)
+    }
+
+    /// Replaces the emoticon shortcuts typed in the texts (e.g. `:-)`) and the emoticon images of
+    /// Messenger Plus! archives by `Data::Emoticon` parts.
+    pub fn with_emoticons(mut self) -> Self {
+        self.emoticons = true;
+        self
+    }
+
+    /// Decodes the Messenger Plus! formatting codes (e.g. `[b]` or `·$4`): they are removed from
+    /// the friendly names, and the texts are split into styled runs.
+    pub fn decode_formatting(mut self) -> Self {
+        self.formatting = true;
+        self
+    }
+
+    /// Uses the given timezone for the messages saved without one (e.g. in the Messenger Plus!
+    /// archives). The messages without timezone first get the one of the previous message of the
+    /// same session.
+    pub fn timezone(mut self, timezone: TimeZoneSpec) -> Self {
+        let utc = self.normalizer.as_ref().is_some_and(|normalizer| normalizer.utc());
+        self.normalizer = Some(Normalizer::new(Some(timezone), utc));
+        self
+    }
+
+    /// Converts the dates of the messages to UTC, when their timezone is known (see `timezone`).
+    pub fn utc(mut self) -> Self {
+        let timezone = self.normalizer.take().and_then(Normalizer::into_timezone);
+        self.normalizer = Some(Normalizer::new(timezone, true));
+        self
+    }
+
+    /// Reads the local dates of a WLM archive with the given format, instead of detecting it from
+    /// the dates read (the first messages are kept until a single format matches their dates, or
+    /// a few dozen messages are read).
+    pub fn date_format(mut self, format: DateFormat) -> Self {
+        self.set_date_format(format);
+        self
+    }
+
+    /// Keeps the elements of the messages the parsers do not recognise, as `Data::Unknown` parts,
+    /// so that nothing saved in the archive is lost. The elements WLM saves next to the messages
+    /// (e.g. `Invitation`) are returned as messages made of the whole element.
+    pub fn raw(mut self) -> Self {
+        self.set_raw(true);
+        self
+    }
+
+    /// Uses an index of the sessions previously built (e.g. loaded from a file) to seek in the
+    /// archive, instead of building it on the first seek.
+    pub fn session_index(mut self, index: SessionIndex) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    /// Resumes the parsing at the first message of the session `id`, without reading the previous
+    /// sessions. The following messages are read until the end of the archive.
+    pub fn seek_session(&mut self, id: &str) -> Result<(), Box<dyn error::Error>> {
+        self.seek(SessionIndex::resume, |index| {
+            index
+                .session(id)
+                .ok_or_else(|| format!("The session {} is not in the archive", id).into())
         })
     }
 
-    fn get_parser(path: &str) -> Result<Box<dyn MessengerArchive<Item=IteratorItem> + '_>, Box<dyn error::Error>> {
+    /// Resumes the parsing at the beginning of the session containing `datetime`, compared with
+    /// the dates saved in the archive.
+    pub fn seek_date(&mut self, datetime: NaiveDateTime) -> Result<(), Box<dyn error::Error>> {
+        self.seek(SessionIndex::resume, |index| {
+            index
+                .session_at(datetime)
+                .ok_or_else(|| format!("No session started before {}", datetime).into())
+        })
+    }
+
+    /// Reads the next message without allocating a new one: the memory of the previous message is
+    /// reused, so it is only borrowed until the next call. The messages are the ones returned by
+    /// `next`, to read large archives faster when only a few messages are kept:
+    /// ```
+    /// use lib_messenger_archive::Parser;
+    ///
+    /// let mut parser = Parser::new("test/alice1234.xml").unwrap();
+    /// let mut senders = vec![];
+    /// while let Some(message) = parser.next_ref() {
+    ///     senders.push(message.unwrap().sender_friendly_name.to_string());
+    /// }
+    /// assert_eq!(senders, vec!["Alice", "Bob"]);
+    /// ```
+    pub fn next_ref(&mut self) -> Option<Result<&Message, Box<dyn error::Error>>> {
+        loop {
+            match self.parser.next_into(&mut self.buffer)? {
+                Ok(()) if !self.filter.matches(&self.buffer) => continue,
+                Ok(()) => {
+                    if self.formatting || self.emoticons || self.normalizer.is_some() {
+                        let message = std::mem::take(&mut self.buffer);
+                        self.buffer = self.transform(message);
+                    }
+                    return Some(Ok(&self.buffer));
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+
+    /// Applies the options decoding the messages.
+    fn transform(&mut self, mut message: Message) -> Message {
+        if self.formatting {
+            message = formatting::decode_message(message);
+        }
+        if self.emoticons {
+            message = emoticon::recognize(message);
+        }
+        if let Some(normalizer) = &mut self.normalizer {
+            message = normalizer.normalize(message);
+        }
+        message
+    }
+
+    /// Returns the latest messages first (see `ReverseMessages`).
+    pub fn reverse(self) -> ReverseMessages<'a> {
+        ReverseMessages::new(self.reverse_sessions())
+    }
+
+    /// Returns the messages session by session, the latest session first (see `ReverseSessions`).
+    pub fn reverse_sessions(self) -> ReverseSessions<'a> {
+        ReverseSessions::new(self)
+    }
+
+    /// The count of sessions in the archive, building the index if needed.
+    fn sessions_count(&mut self) -> Result<usize, Box<dyn error::Error>> {
+        if self.index.is_none() {
+            self.index = Some(SessionIndex::from_source(&self.source, self.file_type)?);
+        }
+        Ok(self.index.as_ref().map_or(0, |index| index.sessions().len()))
+    }
+
+    /// Replaces the parser by one reading the `part` of the archive starting at the session found
+    /// in the index. The index is built from the archive if none was given.
+    fn seek<F>(&mut self, part: ArchivePart, find: F) -> Result<(), Box<dyn error::Error>>
+    where
+        F: FnOnce(&SessionIndex) -> Result<&IndexedSession, Box<dyn error::Error>>,
+    {
+        let file_type = self.file_type;
+        let index = match self.index.take() {
+            Some(index) => index,
+            None => SessionIndex::from_source(&self.source, file_type)?,
+        };
+        let index = self.index.insert(index);
+        if index.file_type() != file_type {
+            return Err("The index was built for another type of archive".into());
+        }
+        let session = find(index)?;
+        let first_session = index.is_first(session);
+        let content = part(index, &self.source, session)?;
+        self.resume(content, first_session);
+        Ok(())
+    }
+
+    /// Replaces the parser by one reading `content`, a part of the archive.
+    fn resume(&mut self, content: Vec<u8>, first_session: bool) {
+        let source = self.source.clone();
+        self.parser = match self.file_type {
+            FileType::XML => Box::new(messenger::xml_parser::XmlParser::resume(source, content)),
+            FileType::MessengerPlus => Box::new(messenger::messenger_plus_parser::MessengerPlusParser::resume(source, content, first_session)),
+        };
+        self.parser.set_filter(self.filter.clone());
+        if let Some(format) = self.date_format {
+            self.parser.set_date_format(format);
+        }
+        self.parser.set_raw(self.raw);
+    }
+
+    fn filtered<F: FnOnce(&mut MessageFilter)>(mut self, update: F) -> Self {
+        update(&mut self.filter);
+        self.parser.set_filter(self.filter.clone());
+        self
+    }
+
+    fn get_parser(path: &str) -> Result<Box<dyn MessengerArchive<Item=IteratorItem> + Send + '_>, Box<dyn error::Error>> {
         if path.ends_with(".xml") {
             Ok(Box::new(messenger::xml_parser::XmlParser::new(path)?))
         }  else {
@@ -64,7 +364,13 @@ impl<'a> Iterator for Parser<'a> {
 
     /// Iterate through the messages in the archive.
     fn next(&mut self) -> Option<Self::Item> {
-        self.parser.next()
+        loop {
+            match self.parser.next()? {
+                Ok(message) if !self.filter.matches(&message) => continue,
+                Ok(message) => return Some(Ok(self.transform(message))),
+                item => return Some(item),
+            }
+        }
     }
 }
 
@@ -74,6 +380,21 @@ impl<'a> MessengerArchive for Parser<'a> {
     fn details(&self) -> Option<&ArchiveDetails> {
         self.parser.details()
     }
+
+    fn set_filter(&mut self, filter: MessageFilter) {
+        self.parser.set_filter(filter.clone());
+        self.filter = filter;
+    }
+
+    fn set_date_format(&mut self, format: DateFormat) {
+        self.parser.set_date_format(format);
+        self.date_format = Some(format);
+    }
+
+    fn set_raw(&mut self, raw: bool) {
+        self.parser.set_raw(raw);
+        self.raw = raw;
+    }
 }
 
 #[cfg(test)]
@@ -98,6 +419,63 @@ mod tests {
     }
     
     #[test]
+    fn filter_messages() {
+        let day = chrono::NaiveDate::from_ymd_opt(2009, 8, 5).unwrap();
+        let messages: Vec<Message> = Parser::new("test/alice@example.com.html")
+            .unwrap()
+            .from("Alice")
+            .between(day.and_hms_opt(19, 0, 0).unwrap(), day.and_hms_opt(19, 40, 0).unwrap())
+            .containing("called")
+            .flatten()
+            .collect();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].datetime, "2009-08-05T19:30");
+
+        let mut parser = Parser::new("test/alice1234.xml").unwrap().with_images();
+        assert!(parser.next().is_none());
+        assert_eq!(parser.details().unwrap().last_session_id, "1");
+
+        let day = day.succ_opt().unwrap();
+        let mut parser = Parser::new("test/alice@example.com.html")
+            .unwrap()
+            .between(day.and_hms_opt(0, 0, 0).unwrap(), day.and_hms_opt(23, 59, 0).unwrap());
+        assert!(parser.next().is_none());
+    }
+
+    #[test]
+    fn read_borrowed_messages() {
+        for path in ["test/alice1234.xml", "test/alice@example.com.html"] {
+            let messages: Vec<Message> = Parser::new(path).unwrap().with_emoticons().flatten().collect();
+            let mut parser = Parser::new(path).unwrap().with_emoticons();
+            let mut borrowed = vec![];
+            while let Some(message) = parser.next_ref() {
+                borrowed.push(message.unwrap().clone());
+            }
+            assert_eq!(borrowed, messages);
+            assert!(parser.details().is_some());
+            // the session IDs and the nicknames are shared by the messages
+            assert!(std::sync::Arc::ptr_eq(&messages[0].session_id, &messages[1].session_id));
+            assert!(std::sync::Arc::ptr_eq(&messages[0].sender_friendly_name, &messages[1].receiver_friendly_name));
+        }
+        let mut parser = Parser::new("test/alice@example.com.html").unwrap().from("Bob");
+        assert_eq!(&*parser.next_ref().unwrap().unwrap().sender_friendly_name, "Bob");
+    }
+
+    #[test]
+    fn skip_images_of_filtered_messages() {
+        let directory = std::env::temp_dir().join("skip_images_of_filtered_messages");
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("alice@example.com.html");
+        std::fs::copy("test/alice@example.com.html", &path).unwrap();
+
+        let parser = Parser::new(path.to_str().unwrap()).unwrap();
+        assert!(parser.into_iter().any(|message| message.is_err()));
+        let parser = Parser::new(path.to_str().unwrap()).unwrap().from("Alice");
+        assert_eq!(parser.flatten().count(), 2);
+    }
+
+    #[test]
+    #[allow(clippy::while_let_on_iterator)]
     fn display_messages() {
         let file = "test/alice1234.xml";
         let mut parser = Parser::new(file).expect("unable to read the archive");
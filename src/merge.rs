@@ -0,0 +1,192 @@
+use crate::messenger::common;
+use crate::{Data, Message, Parser};
+use chrono::{Duration, NaiveDateTime};
+use std::error::Error;
+
+/// A message of the merged stream, with the indexes of the sources it was found in.
+#[derive(PartialEq, Debug)]
+pub struct MergedMessage {
+    pub message: Message,
+    pub sources: Vec<usize>,
+}
+
+/// Merges several archives of the same contact (e.g. the WLM and the Messenger Plus! archives),
+/// removing the messages saved in more than one archive.
+///
+/// Two messages are considered identical when they have the same sender and the same content,
+/// and when they were sent in the same time window. When a message is found in several archives,
+/// the copy having the most precise date is kept.
+pub struct Merger<'a> {
+    parsers: Vec<Parser<'a>>,
+    window: Duration,
+}
+
+/// A message being merged, with the values used to align it with the others.
+struct Candidate {
+    merged: MergedMessage,
+    time: NaiveDateTime,
+    sender: String,
+    content: String,
+}
+
+impl<'a> Merger<'a> {
+    /// Creates a merger for the given archives. Their index in `parsers` is used to identify the
+    /// sources of the merged messages.
+    pub fn new(parsers: Vec<Parser<'a>>) -> Self {
+        Merger {
+            parsers,
+            window: Duration::minutes(2),
+        }
+    }
+
+    /// Sets the maximum difference between the dates of two copies of the same message.
+    /// Defaults to 2 minutes: Messenger Plus! dates are truncated to the minute.
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Reads all the archives and returns their messages, deduplicated and chronologically
+    /// ordered. Fails if one of the archives cannot be parsed.
+    pub fn merge(self) -> Result<std::vec::IntoIter<MergedMessage>, Box<dyn Error>> {
+        let mut candidates = vec![];
+        for (source, parser) in self.parsers.into_iter().enumerate() {
+            for message in parser {
+                candidates.push(Candidate::new(message?, source));
+            }
+        }
+        // the sort is stable: the order of each archive is kept for the messages of the same time
+        candidates.sort_by_key(|candidate| candidate.time);
+
+        let mut merged: Vec<Candidate> = vec![];
+        for candidate in candidates {
+            let source = candidate.merged.sources[0];
+            let duplicate = merged
+                .iter_mut()
+                .rev()
+                .take_while(|other| candidate.time - other.time <= self.window)
+                .find(|other| {
+                    !other.merged.sources.contains(&source)
+                        && other.sender == candidate.sender
+                        && other.content == candidate.content
+                });
+            match duplicate {
+                Some(other) => {
+                    other.merged.sources.push(source);
+                    other.merged.sources.sort();
+                    if candidate.merged.message.datetime.len() > other.merged.message.datetime.len() {
+                        other.merged.message = candidate.merged.message;
+                        other.time = candidate.time;
+                    }
+                }
+                None => merged.push(candidate),
+            }
+        }
+        merged.sort_by_key(|candidate| candidate.time);
+
+        Ok(merged
+            .into_iter()
+            .map(|candidate| candidate.merged)
+            .collect::<Vec<MergedMessage>>()
+            .into_iter())
+    }
+}
+
+impl Candidate {
+    fn new(message: Message, source: usize) -> Self {
+        // the messages are compared in local time, as Messenger Plus! does not save the timezone
+        let time = common::parse_datetime(&message.datetime)
+            .map(|datetime| datetime + Duration::minutes(message.timezone_offset.unwrap_or(0)))
+            .unwrap_or_default();
+        let sender = message.sender_friendly_name.trim().to_lowercase();
+        let content = message
+            .data
+            .iter()
+            .map(|d| match d {
+                Data::Text(text) => text.content.clone(),
+                Data::Image(image) => image.alt.clone(),
+                Data::Emoticon(emoticon) => emoticon.shortcut.clone(),
+                Data::System(content) => content.clone(),
+                Data::Unknown(element) => element.text_content(),
+            })
+            .collect::<String>()
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect();
+        Candidate {
+            merged: MergedMessage {
+                message,
+                sources: vec![source],
+            },
+            time,
+            sender,
+            content,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::XmlWriter;
+    use std::fs::File;
+
+    /// Writes a WLM copy of the Messenger Plus! sample archive, with more precise dates.
+    fn write_xml_copy() -> String {
+        let messages = Parser::new("test/alice@example.com.html")
+            .unwrap()
+            .flatten()
+            .filter(|message| !matches!(message.data[0], Data::System(_)))
+            .map(|mut message| {
+                let mut local = common::parse_datetime(&message.datetime).unwrap();
+                if message.datetime.len() == "2009-08-05T19:30".len() {
+                    local += Duration::seconds(30);
+                }
+                let utc = local - Duration::hours(2);
+                message.datetime = utc.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+                message.timezone_offset = Some(120);
+                message
+            });
+        let path = std::env::temp_dir().join("merge_alice@example.com.xml");
+        XmlWriter::new(File::create(&path).unwrap()).write(messages).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn merge_xml_and_messenger_plus_archives() {
+        let xml = write_xml_copy();
+        let parsers = vec![
+            Parser::new(&xml).unwrap(),
+            Parser::new("test/alice@example.com.html").unwrap(),
+        ];
+        let merged: Vec<MergedMessage> = Merger::new(parsers).merge().unwrap().collect();
+
+        assert_eq!(merged.len(), 6);
+        assert_eq!(merged[0].sources, vec![0, 1]);
+        assert_eq!(merged[0].message.datetime, "2009-08-05T17:30:21.000Z");
+        assert_eq!(&*merged[0].message.sender_friendly_name, "Bob");
+        assert_eq!(merged[1].message.datetime, "2009-08-05T17:30:30.000Z");
+        assert_eq!(merged[4].sources, vec![0, 1]);
+        assert_eq!(merged[5].sources, vec![1]);
+        assert_eq!(merged[5].message.data, vec![Data::System("Alice is now offline".to_string())]);
+    }
+
+    #[test]
+    fn keep_repeated_messages_of_the_same_archive() {
+        let mut messages: Vec<Message> = Parser::new("test/alice1234.xml").unwrap().flatten().collect();
+        let mut repeated = messages[0].clone();
+        repeated.datetime = "2009-04-06T19:40:51.851Z".to_string();
+        messages.insert(1, repeated);
+        let path = std::env::temp_dir().join("keep_repeated_messages_of_the_same_archive.xml");
+        XmlWriter::new(File::create(&path).unwrap()).write(messages).unwrap();
+
+        let parsers = vec![
+            Parser::new(path.to_str().unwrap()).unwrap(),
+            Parser::new("test/alice1234.xml").unwrap(),
+        ];
+        let merged: Vec<MergedMessage> = Merger::new(parsers).merge().unwrap().collect();
+        let sources: Vec<Vec<usize>> = merged.iter().map(|m| m.sources.clone()).collect();
+        assert_eq!(sources, vec![vec![0, 1], vec![0], vec![0, 1]]);
+        assert_eq!(merged[1].message.datetime, "2009-04-06T19:40:51.851Z");
+    }
+}
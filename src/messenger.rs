@@ -1,13 +1,47 @@
 pub mod common;
+pub mod date_format;
+pub mod emoticon;
+pub mod event_log;
+pub mod export;
+pub mod filter;
+pub mod formatting;
 pub mod messenger_plus_parser;
+pub mod messenger_plus_writer;
+pub mod session_index;
+pub mod source;
+pub mod timezone;
 pub mod xml_parser;
+pub mod xml_writer;
 
-pub trait MessengerArchive: Iterator {
+use crate::messenger::date_format::DateFormat;
+use crate::messenger::filter::MessageFilter;
+use std::error::Error;
+use std::sync::Arc;
+
+pub trait MessengerArchive: Iterator<Item = Result<Message, Box<dyn Error>>> {
     fn details(&self) -> Option<&ArchiveDetails>;
+
+    /// Only returns the messages matching the `filter`. The parsers skip the other messages as
+    /// early as possible; by default, nothing is filtered.
+    fn set_filter(&mut self, _filter: MessageFilter) {}
+
+    /// Uses the given format to read the local dates of the messages, instead of detecting it.
+    /// Only used by the WLM archives; by default, it is ignored.
+    fn set_date_format(&mut self, _format: DateFormat) {}
+
+    /// Keeps the unrecognised elements found inside the messages as `Data::Unknown` parts, instead
+    /// of dropping them. By default, they are ignored.
+    fn set_raw(&mut self, _raw: bool) {}
+
+    /// Reads the next message into `message`, reusing the memory of its texts (see
+    /// `Parser::next_ref`). By default, the next message replaces it.
+    fn next_into(&mut self, message: &mut Message) -> Option<Result<(), Box<dyn Error>>> {
+        Some(self.next()?.map(|next| *message = next))
+    }
 }
 
 /// Some general details about the current archive.
-#[derive(Default, PartialEq, Debug)]
+#[derive(Default, PartialEq, Debug, Clone)]
 pub struct ArchiveDetails {
     /// Standard archive or Messenger Plus!
     pub file_type: FileType,
@@ -18,10 +52,23 @@ pub struct ArchiveDetails {
     pub last_session_id: String,
     /// The ID of the user exchanging with the one owning the archive.
     pub recipient_id: String,
+    /// The participants listed at the start of each session, with their e-mail address. Only
+    /// saved by Messenger Plus!
+    pub participants: Vec<SessionParticipant>,
+}
+
+/// A participant of a conversation session, as listed by Messenger Plus!
+#[derive(Default, PartialEq, Debug, Clone)]
+pub struct SessionParticipant {
+    pub session_id: String,
+    /// The nickname of the participant at the start of the session.
+    pub friendly_name: String,
+    /// The e-mail address of the participant's account.
+    pub email: String,
 }
 
 /// A message, exchanged by two messenger's users.
-#[derive(Default, PartialEq, Debug)]
+#[derive(Default, PartialEq, Debug, Clone)]
 pub struct Message {
     /// The date and time of the message. May be more or less precise, depending on what was saved
     /// in the archive: the minutes should always be present but the seconds may not be.
@@ -30,32 +77,50 @@ pub struct Message {
     /// The `Option` may be `None` if the timezone is unknown.
     pub timezone_offset: Option<i64>,
     /// An archive may be composed of multiple conversation sessions, this is the ID of the current
-    /// session.
-    pub session_id: String,
+    /// session. It is shared by the messages of the session read by a parser.
+    pub session_id: Arc<str>,
     /// The sending user may use a different nickname for each message, this is his nickname for the
-    /// current message.
-    pub sender_friendly_name: String,
+    /// current message. The nicknames repeated from one message to the other are shared.
+    pub sender_friendly_name: Arc<str>,
     /// The receiving user may use a different nickname for each message, this is his nickname for
     /// the current message.
-    pub receiver_friendly_name: String,
+    pub receiver_friendly_name: Arc<str>,
     /// The body of the message. May be composed of multiple parts (e.g. an image and a text).
     pub data: Vec<Data>,
 }
 
+impl Message {
+    /// Empties the message to read the next one, keeping the memory of its date and of the list
+    /// of its parts (the parts themselves are dropped).
+    pub(crate) fn clear(&mut self) {
+        self.datetime.clear();
+        self.timezone_offset = None;
+        self.session_id = Arc::default();
+        self.sender_friendly_name = Arc::default();
+        self.receiver_friendly_name = Arc::default();
+        self.data.clear();
+    }
+}
+
 /// The various type of parts of the message body.
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum Data {
     /// Some formatted text.
     Text(Text),
     /// An image.
     Image(Image),
+    /// A standard MSN emoticon, recognized in a text or an image (see `emoticon::recognize`).
+    Emoticon(Emoticon),
     /// Messenger Plus! plugin has also saved a message when the session's user was disconnected.
     /// These kind of messages are saved as `System`.
     System(String),
+    /// An element of the archive the parsers do not support, only kept in raw mode (see
+    /// `Parser::raw`).
+    Unknown(UnknownElement),
 }
 
 /// A part of a message body, containing some formatted text.
-#[derive(Default, PartialEq, Debug)]
+#[derive(Default, PartialEq, Debug, Clone)]
 pub struct Text {
     /// A CSS-like string indicating the style of the text.
     pub style: String,
@@ -64,7 +129,7 @@ pub struct Text {
 }
 
 /// A part of a message body, containing an image.
-#[derive(Default, PartialEq, Debug)]
+#[derive(Default, PartialEq, Debug, Clone)]
 pub struct Image {
     /// The path to the image file.
     pub src: String,
@@ -74,8 +139,44 @@ pub struct Image {
     pub content: Vec<u8>,
 }
 
+/// A part of a message body, containing a standard MSN emoticon.
+#[derive(Default, PartialEq, Debug, Clone)]
+pub struct Emoticon {
+    /// The identifier of the emoticon, e.g. `smile` (see `emoticon::EMOTICONS`).
+    pub id: String,
+    /// The shortcut typed by the user, e.g. `:-)`.
+    pub shortcut: String,
+    /// The image of the emoticon, when the archive saved one (e.g. Messenger Plus!).
+    pub image: Option<Image>,
+}
+
+/// A part of a message body, containing an element of the archive as it was saved.
+#[derive(Default, PartialEq, Debug, Clone)]
+pub struct UnknownElement {
+    /// The local name of the element, e.g. `br`.
+    pub name: String,
+    /// The attributes of the element, by local name, in the order of the archive.
+    pub attributes: Vec<(String, String)>,
+    /// The text directly inside the element.
+    pub text: String,
+    /// The elements inside the element, in the order of the archive. They are written back after
+    /// its text.
+    pub children: Vec<UnknownElement>,
+}
+
+impl UnknownElement {
+    /// The text of the element and of its children.
+    pub fn text_content(&self) -> String {
+        let mut text = self.text.clone();
+        for child in &self.children {
+            text.push_str(&child.text_content());
+        }
+        text
+    }
+}
+
 /// Indicates the type of archive
-#[derive(Default, PartialEq, Debug)]
+#[derive(Default, PartialEq, Debug, Clone, Copy)]
 pub enum FileType {
     #[default]
     /// A standard Windows Live Messenger XML archive.
@@ -83,3 +184,15 @@ pub enum FileType {
     /// A Messenger PLus! plugin HTML archive.
     MessengerPlus,
 }
+
+impl FileType {
+    /// The type of the archive at `path`, according to its extension: the `.xml` files are
+    /// standard archives, the others are Messenger Plus! ones.
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".xml") {
+            FileType::XML
+        } else {
+            FileType::MessengerPlus
+        }
+    }
+}
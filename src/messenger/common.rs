@@ -1,20 +1,267 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io;
-use std::io::BufReader;
+use crate::messenger::source::ArchiveSource;
+use crate::messenger::UnknownElement;
+use chrono::NaiveDateTime;
+use std::error::Error;
+use std::io::{BufReader, Cursor, Read};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use xml::attribute::OwnedAttribute;
 use xml::EventReader;
 
-pub fn parse_attributes(attributes: &Vec<OwnedAttribute>) -> HashMap<&str, &str> {
-    let mut hash: HashMap<&str, &str> = HashMap::new();
-    for attribute in attributes {
-        hash.insert(&*attribute.name.local_name, &*attribute.value);
+/// The attributes of an element, by local name. The elements have a few attributes: looking them
+/// up one after the other is faster than hashing them.
+#[derive(Clone, Copy)]
+pub struct Attributes<'a>(&'a [OwnedAttribute]);
+
+impl<'a> Attributes<'a> {
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.0
+            .iter()
+            .find(|attribute| attribute.name.local_name == name)
+            .map(|attribute| attribute.value.as_str())
+    }
+
+    /// Checks the attribute `name` has the given `value`.
+    pub fn has(&self, name: &str, value: &str) -> bool {
+        self.get(name) == Some(value)
+    }
+
+    /// All the attributes, as `(local name, value)` pairs.
+    pub fn to_vec(self) -> Vec<(String, String)> {
+        self.0
+            .iter()
+            .map(|attribute| (attribute.name.local_name.clone(), attribute.value.clone()))
+            .collect()
+    }
+}
+
+pub fn parse_attributes(attributes: &[OwnedAttribute]) -> Attributes<'_> {
+    Attributes(attributes)
+}
+
+/// The unrecognised elements being read in raw mode, from the outermost one, with their depth.
+/// The elements inside them are read as their children, whatever their name.
+#[derive(Default)]
+pub struct UnknownElements(Vec<(usize, UnknownElement)>);
+
+impl UnknownElements {
+    /// Checks an unrecognised element is being read: the following elements are inside it.
+    pub fn is_reading(&self) -> bool {
+        !self.0.is_empty()
     }
-    hash
+
+    /// Starts an unrecognised element, at `depth` from the root.
+    pub fn start(&mut self, depth: usize, name: &str, attributes: Attributes) {
+        self.0.push((
+            depth,
+            UnknownElement {
+                name: name.to_string(),
+                attributes: attributes.to_vec(),
+                ..UnknownElement::default()
+            },
+        ));
+    }
+
+    /// Adds text to the innermost element. Returns false if no element is being read.
+    pub fn text(&mut self, data: &str) -> bool {
+        match self.0.last_mut() {
+            Some((_, element)) => {
+                element.text.push_str(data);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Ends the element at `depth`, if it is an unrecognised one. Returns it if it is the
+    /// outermost one, the inner ones are added to their parent.
+    pub fn end(&mut self, depth: usize) -> Option<UnknownElement> {
+        if self.0.last().is_none_or(|(start, _)| *start != depth) {
+            return None;
+        }
+        let (_, element) = self.0.pop()?;
+        match self.0.last_mut() {
+            Some((_, parent)) => {
+                parent.children.push(element);
+                None
+            }
+            None => Some(element),
+        }
+    }
+}
+
+/// The XML reader of an archive, whatever it is read from.
+pub type XmlReader = EventReader<BufReader<Box<dyn Read + Send>>>;
+
+pub fn get_parser(source: &ArchiveSource) -> Result<XmlReader, Box<dyn Error>> {
+    Ok(get_reader_parser(source.reader()?))
+}
+
+/// The XML reader of an archive read from `reader`.
+pub fn get_reader_parser(reader: Box<dyn Read + Send>) -> XmlReader {
+    EventReader::new(BufReader::new(reader))
+}
+
+/// The XML reader of a content already in memory.
+pub fn get_content_parser(content: Vec<u8>) -> XmlReader {
+    EventReader::new(BufReader::new(Box::new(Cursor::new(content))))
+}
+
+/// The milliseconds since the Unix epoch, e.g. to save the modification date of an archive.
+pub fn milliseconds(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH).ok().map(|duration| duration.as_millis() as u64)
+}
+
+/// Replaces the content of `target` by `value`, reusing its memory.
+/// The count of values kept by an `Interner`.
+const MAX_INTERNED: usize = 64;
+
+/// Shares the session IDs and the nicknames repeated from one message to the other, instead of
+/// allocating them for each message. Only the last values read are kept.
+#[derive(Default)]
+pub struct Interner {
+    values: Vec<Arc<str>>,
+}
+
+impl Interner {
+    /// Returns the shared value equal to `value`, adding it if it is not known yet.
+    pub fn intern(&mut self, value: &str) -> Arc<str> {
+        if let Some(shared) = self.values.iter().find(|shared| ***shared == *value) {
+            return shared.clone();
+        }
+        if self.values.len() == MAX_INTERNED {
+            self.values.clear();
+        }
+        let shared: Arc<str> = value.into();
+        self.values.push(shared.clone());
+        shared
+    }
+}
+
+pub fn assign(target: &mut String, value: &str) {
+    target.clear();
+    target.push_str(value);
+}
+
+/// Parses the date and time of a message, whatever its precision: the XML archives store
+/// `2009-04-06T19:40:41.851Z` while the Messenger Plus! ones store `2009-08-05T19:30:21` or
+/// `2009-08-05T19:30`.
+pub fn parse_datetime(datetime: &str) -> Option<NaiveDateTime> {
+    let datetime = datetime.trim_end_matches('Z');
+    NaiveDateTime::parse_from_str(datetime, "%Y-%m-%dT%H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(datetime, "%Y-%m-%dT%H:%M"))
+        .ok()
+}
+
+/// Decodes the content of an archive as text, whatever its encoding (UTF-8 or UTF-16).
+pub fn decode_text(content: &[u8]) -> String {
+    let encoding = Encoding::detect(content);
+    encoding.decode(&content[encoding.bom_length()..])
+}
+
+/// The encodings of the archives, detected from their byte order mark: WLM saves them in UTF-8,
+/// Messenger Plus! in UTF-16.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl Encoding {
+    pub fn detect(content: &[u8]) -> Self {
+        match content {
+            [0xFF, 0xFE, ..] => Encoding::Utf16Le,
+            [0xFE, 0xFF, ..] => Encoding::Utf16Be,
+            _ => Encoding::Utf8,
+        }
+    }
+
+    /// The length of the byte order mark removed before decoding (the UTF-8 one is kept, as a
+    /// character of the text).
+    pub fn bom_length(self) -> usize {
+        match self {
+            Encoding::Utf8 => 0,
+            Encoding::Utf16Le | Encoding::Utf16Be => 2,
+        }
+    }
+
+    /// Decodes a part of an archive, starting at a character.
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).to_string(),
+            Encoding::Utf16Le => decode_utf16(bytes, u16::from_le_bytes),
+            Encoding::Utf16Be => decode_utf16(bytes, u16::from_be_bytes),
+        }
+    }
+
+    /// Converts positions in `text`, decoded from `content` with `decode_text`, to positions in
+    /// `content`. The `positions` must be sorted.
+    pub fn raw_offsets(self, content: &[u8], text: &str, positions: &[usize]) -> Vec<u64> {
+        // the parts of the text with the same length in the content, as (text, raw) positions
+        let mut parts: Vec<(usize, usize, usize)> = vec![];
+        match self {
+            Encoding::Utf8 => {
+                let (mut decoded, mut raw) = (0, 0);
+                for chunk in content.utf8_chunks() {
+                    let valid = chunk.valid().len();
+                    parts.push((decoded, raw, valid));
+                    decoded += valid;
+                    raw += valid;
+                    if !chunk.invalid().is_empty() {
+                        decoded += char::REPLACEMENT_CHARACTER.len_utf8();
+                        raw += chunk.invalid().len();
+                    }
+                }
+                parts.push((decoded, raw, 0));
+            }
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let mut raw = self.bom_length();
+                let mut chars = text.char_indices().peekable();
+                for &position in positions {
+                    while let Some((_, c)) = chars.next_if(|(index, _)| *index < position) {
+                        raw += 2 * c.len_utf16();
+                    }
+                    parts.push((position, raw, 0));
+                }
+            }
+        }
+        positions
+            .iter()
+            .map(|&position| {
+                let part = parts.partition_point(|(decoded, _, _)| *decoded <= position).saturating_sub(1);
+                let (decoded, raw, length) = parts.get(part).copied().unwrap_or_default();
+                (raw + (position - decoded).min(length)) as u64
+            })
+            .collect()
+    }
+}
+
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| from_bytes([pair[0], pair[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// Finds the start tags of the elements `name` in the text of an archive, without parsing it.
+/// Returns the position of each tag, with its content (e.g. `<Message Date="06/04/2009"`, or
+/// `<Message` for a tag without attributes).
+pub fn start_tags<'a>(text: &'a str, name: &str) -> Vec<(usize, &'a str)> {
+    text.match_indices(&format!("<{}", name))
+        .filter_map(|(position, _)| {
+            let tag = &text[position..];
+            let tag = &tag[..tag.find('>')?];
+            let rest = &tag[name.len() + 1..];
+            (rest.is_empty() || rest.starts_with(char::is_whitespace) || rest.starts_with('/')).then_some((position, tag))
+        })
+        .collect()
 }
 
-pub fn get_parser(path: &str) -> Result<EventReader<BufReader<File>>, io::Error> {
-    let file = File::open(&path)?;
-    let file = BufReader::new(file);
-    Ok(EventReader::new(file))
+/// Reads the value of an attribute in the content of a start tag.
+pub fn tag_attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let length = tag[start..].find('"')?;
+    Some(&tag[start..start + length])
 }
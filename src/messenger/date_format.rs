@@ -0,0 +1,191 @@
+use crate::messenger::common;
+use crate::messenger::source::ArchiveSource;
+use chrono::{NaiveDate, NaiveDateTime};
+use std::error::Error;
+
+/// The order of the fields of the local dates saved by WLM in the `Date` attribute. It depends on
+/// the regional settings of Windows (e.g. `06/04/2009` or `04/06/2009`).
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum DateFormat {
+    /// e.g. `dd/MM/yyyy` or `dd.MM.yyyy`
+    DayMonthYear,
+    /// e.g. `MM/dd/yyyy`
+    MonthDayYear,
+    /// e.g. `yyyy-MM-dd`
+    YearMonthDay,
+}
+
+/// The formats tried when detecting the format of an archive, in order of preference.
+const FORMATS: [DateFormat; 3] = [
+    DateFormat::YearMonthDay,
+    DateFormat::DayMonthYear,
+    DateFormat::MonthDayYear,
+];
+
+impl DateFormat {
+    /// Parses a local date with this format, whatever the separators are.
+    pub fn parse(&self, date: &str) -> Option<NaiveDate> {
+        let fields: Vec<i32> = date
+            .split(|c: char| !c.is_ascii_digit())
+            .filter(|field| !field.is_empty())
+            .map(|field| field.parse())
+            .collect::<Result<_, _>>()
+            .ok()?;
+        let [a, b, c] = fields[..] else {
+            return None;
+        };
+        let (year, month, day) = match self {
+            DateFormat::DayMonthYear => (c, b, a),
+            DateFormat::MonthDayYear => (c, a, b),
+            DateFormat::YearMonthDay => (a, b, c),
+        };
+        // two-digit years, with some old regional settings
+        let year = if year < 100 { year + 2000 } else { year };
+        NaiveDate::from_ymd_opt(year, month.try_into().ok()?, day.try_into().ok()?)
+    }
+
+    /// Formats a local date with this format, as written by WLM with the default separators of
+    /// the regional settings (e.g. `06/04/2009`, `04/06/2009` or `2009-04-06`).
+    pub fn format(&self, date: NaiveDate) -> String {
+        let pattern = match self {
+            DateFormat::DayMonthYear => "%d/%m/%Y",
+            DateFormat::MonthDayYear => "%m/%d/%Y",
+            DateFormat::YearMonthDay => "%Y-%m-%d",
+        };
+        date.format(pattern).to_string()
+    }
+
+    /// Detects the format of the local dates of an archive. Each local date is given with the UTC
+    /// date and time of its message, when known: both must be at most one day apart.
+    /// Returns `None` if no format can parse all the dates.
+    pub fn detect<'a, I>(dates: I) -> Option<DateFormat>
+    where
+        I: IntoIterator<Item = (&'a str, Option<NaiveDateTime>)>,
+    {
+        let dates: Vec<(&str, Option<NaiveDateTime>)> = dates.into_iter().collect();
+        if dates.is_empty() {
+            return None;
+        }
+        FORMATS
+            .iter()
+            .filter_map(|format| {
+                let mut matching = 0;
+                for (date, utc) in &dates {
+                    let local = format.parse(date)?;
+                    if utc.is_none_or(|utc| (local - utc.date()).num_days().abs() <= 1) {
+                        matching += 1;
+                    }
+                }
+                Some((format, matching))
+            })
+            .rev()
+            .max_by_key(|(_, matching)| *matching)
+            .map(|(format, _)| *format)
+    }
+
+    /// Checks `date` can be read with this format and is at most one day apart from the UTC date
+    /// of its message, when known.
+    fn matches(&self, date: &str, utc: Option<NaiveDateTime>) -> bool {
+        self.parse(date)
+            .is_some_and(|local| utc.is_none_or(|utc| (local - utc.date()).num_days().abs() <= 1))
+    }
+
+    /// Detects the format of the local dates of a WLM archive, reading all its messages.
+    pub fn detect_in_file(path: &str) -> Result<Option<DateFormat>, Box<dyn Error>> {
+        Ok(Self::detect_in_content(&ArchiveSource::open(path)?.read()?))
+    }
+
+    /// Detects the format of the local dates of a WLM archive already read.
+    pub fn detect_in_content(content: &[u8]) -> Option<DateFormat> {
+        let content = common::decode_text(content);
+        let dates = common::start_tags(&content, "Message").into_iter().filter_map(|(_, message)| {
+            let date = common::tag_attribute(message, "Date")?;
+            let utc = common::tag_attribute(message, "DateTime").and_then(common::parse_datetime);
+            Some((date, utc))
+        });
+        Self::detect(dates)
+    }
+}
+
+/// Detects the format of the local dates while the messages are read (see `XmlParser`): it is
+/// decided as soon as a single format matches all the dates read, otherwise with the dates read
+/// when the parser stops waiting (see `finish` and `DateFormat::detect`).
+#[derive(Default)]
+pub struct DateFormatDetector {
+    dates: Vec<(String, Option<NaiveDateTime>)>,
+    /// The formats not matching one of the dates read, in the order of `FORMATS`.
+    rejected: [bool; 3],
+}
+
+impl DateFormatDetector {
+    /// Adds the local date of a message, with its UTC date and time if known.
+    pub fn add(&mut self, date: &str, utc: Option<NaiveDateTime>) {
+        for (format, rejected) in FORMATS.iter().zip(self.rejected.iter_mut()) {
+            *rejected = *rejected || !format.matches(date, utc);
+        }
+        self.dates.push((date.to_string(), utc));
+    }
+
+    /// The format, once only one matches all the dates read.
+    pub fn decided(&self) -> Option<DateFormat> {
+        let mut matching = FORMATS
+            .iter()
+            .zip(self.rejected)
+            .filter(|(_, rejected)| !rejected);
+        match (matching.next(), matching.next()) {
+            (Some((format, _)), None) => Some(*format),
+            _ => None,
+        }
+    }
+
+    /// The format best matching the dates read, `None` if no date was read.
+    pub fn finish(&self) -> Option<DateFormat> {
+        self.decided()
+            .or_else(|| DateFormat::detect(self.dates.iter().map(|(date, utc)| (date.as_str(), *utc))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_dates() {
+        let date = NaiveDate::from_ymd_opt(2009, 4, 6);
+        assert_eq!(DateFormat::DayMonthYear.parse("06/04/2009"), date);
+        assert_eq!(DateFormat::DayMonthYear.parse("6.4.09"), date);
+        assert_eq!(DateFormat::MonthDayYear.parse("04/06/2009"), date);
+        assert_eq!(DateFormat::YearMonthDay.parse("2009-04-06"), date);
+        assert_eq!(DateFormat::MonthDayYear.parse("13/04/2009"), None);
+    }
+
+    #[test]
+    fn detect_formats() {
+        assert_eq!(
+            DateFormat::detect_in_file("test/alice1234.xml").unwrap(),
+            Some(DateFormat::DayMonthYear)
+        );
+        let utc = common::parse_datetime("2009-04-06T19:40:41.851Z");
+        assert_eq!(
+            DateFormat::detect([("04/06/2009", utc), ("04/07/2009", None)]),
+            Some(DateFormat::MonthDayYear)
+        );
+        // without the UTC dates, the days after the 12th tell the format
+        assert_eq!(
+            DateFormat::detect([("04/06/2009", None), ("12/31/2009", None)]),
+            Some(DateFormat::MonthDayYear)
+        );
+        assert_eq!(DateFormat::detect([("31/31/2009", None)]), None);
+
+        let mut detector = DateFormatDetector::default();
+        detector.add("04/04/2009", common::parse_datetime("2009-04-04T10:00:00"));
+        assert_eq!(detector.decided(), None);
+        detector.add("04/06/2009", utc);
+        assert_eq!(detector.decided(), Some(DateFormat::MonthDayYear));
+
+        let mut detector = DateFormatDetector::default();
+        detector.add("04/05/2009", None);
+        assert_eq!(detector.decided(), None);
+        assert_eq!(detector.finish(), Some(DateFormat::DayMonthYear));
+    }
+}
@@ -0,0 +1,250 @@
+use crate::messenger::{Data, Emoticon, Image, Message, Text};
+
+/// The standard MSN emoticons: the shortcuts typed by the users, with the identifier of the
+/// emoticon. The shortcuts are case insensitive.
+pub const EMOTICONS: &[(&str, &str)] = &[
+    (":)", "smile"),
+    (":-)", "smile"),
+    (":D", "open-mouthed"),
+    (":-D", "open-mouthed"),
+    (":>", "open-mouthed"),
+    (";)", "wink"),
+    (";-)", "wink"),
+    (":O", "surprised"),
+    (":-O", "surprised"),
+    (":P", "tongue"),
+    (":-P", "tongue"),
+    ("(H)", "hot"),
+    (":@", "angry"),
+    (":-@", "angry"),
+    (":$", "embarrassed"),
+    (":-$", "embarrassed"),
+    (":S", "confused"),
+    (":-S", "confused"),
+    (":(", "sad"),
+    (":-(", "sad"),
+    (":'(", "crying"),
+    (":|", "disappointed"),
+    (":-|", "disappointed"),
+    ("(6)", "devil"),
+    ("(A)", "angel"),
+    ("(L)", "red-heart"),
+    ("(U)", "broken-heart"),
+    ("(M)", "messenger"),
+    ("(@)", "cat"),
+    ("(&)", "dog"),
+    ("(S)", "sleeping-half-moon"),
+    ("(*)", "star"),
+    ("(~)", "filmstrip"),
+    ("(8)", "note"),
+    ("(E)", "email"),
+    ("(F)", "rose"),
+    ("(W)", "wilted-rose"),
+    ("(O)", "clock"),
+    ("(K)", "red-lips"),
+    ("(G)", "gift"),
+    ("(^)", "birthday-cake"),
+    ("(P)", "camera"),
+    ("(I)", "light-bulb"),
+    ("(C)", "coffee-cup"),
+    ("(T)", "telephone"),
+    ("({)", "left-hug"),
+    ("(})", "right-hug"),
+    ("(B)", "beer-mug"),
+    ("(D)", "martini-glass"),
+    ("(Z)", "boy"),
+    ("(X)", "girl"),
+    ("(Y)", "thumbs-up"),
+    ("(N)", "thumbs-down"),
+    (":[", "vampire-bat"),
+    (":-[", "vampire-bat"),
+    ("(#)", "sun"),
+    ("(R)", "rainbow"),
+    (":-#", "dont-tell-anyone"),
+    ("8o|", "baring-teeth"),
+    ("8-|", "nerd"),
+    ("^o)", "sarcastic"),
+    (":-*", "secret-telling"),
+    ("+o(", "sick"),
+    (":^)", "i-dont-know"),
+    ("*-)", "thinking"),
+    ("<:o)", "party"),
+    ("8-)", "eye-rolling"),
+    ("|-)", "sleepy"),
+    ("(sn)", "snail"),
+    ("(tu)", "turtle"),
+    ("(pl)", "plate"),
+    ("(||)", "bowl"),
+    ("(pi)", "pizza"),
+    ("(so)", "soccer-ball"),
+    ("(au)", "auto"),
+    ("(ap)", "airplane"),
+    ("(um)", "umbrella"),
+    ("(ip)", "island"),
+    ("(co)", "computer"),
+    ("(mp)", "mobile-phone"),
+    ("(st)", "stormy-cloud"),
+    ("(li)", "lightning"),
+    ("(mo)", "money"),
+    ("(h5)", "high-five"),
+    ("(yn)", "fingers-crossed"),
+    ("(brb)", "be-right-back"),
+];
+
+/// Returns the emoticon matching the given shortcut, e.g. `smile` for `:-)`.
+pub fn find(shortcut: &str) -> Option<Emoticon> {
+    EMOTICONS
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(shortcut))
+        .map(|(_, id)| Emoticon {
+            id: id.to_string(),
+            shortcut: shortcut.to_string(),
+            image: None,
+        })
+}
+
+/// Splits a text into text parts (with the same style) and emoticon parts.
+pub fn tokenize(text: &Text) -> Vec<Data> {
+    let mut parts = vec![];
+    let content = &text.content;
+    let mut start = 0;
+    let mut position = 0;
+    while position < content.len() {
+        let found = EMOTICONS
+            .iter()
+            .filter(|(shortcut, _)| {
+                content
+                    .get(position..position + shortcut.len())
+                    .is_some_and(|candidate| candidate.eq_ignore_ascii_case(shortcut))
+            })
+            .max_by_key(|(shortcut, _)| shortcut.len());
+        match found {
+            Some((shortcut, id)) => {
+                if start < position {
+                    parts.push(Data::Text(Text {
+                        style: text.style.clone(),
+                        content: content[start..position].to_string(),
+                    }));
+                }
+                let end = position + shortcut.len();
+                parts.push(Data::Emoticon(Emoticon {
+                    id: id.to_string(),
+                    shortcut: content[position..end].to_string(),
+                    image: None,
+                }));
+                start = end;
+                position = end;
+            }
+            None => {
+                position += content[position..].chars().next().map_or(1, char::len_utf8);
+            }
+        }
+    }
+    if start < content.len() || parts.is_empty() {
+        parts.push(Data::Text(Text {
+            style: text.style.clone(),
+            content: content[start..].to_string(),
+        }));
+    }
+    parts
+}
+
+/// Returns the emoticon displayed by an image, according to its alternative text. The image is
+/// kept in the emoticon.
+pub fn from_image(image: &Image) -> Option<Emoticon> {
+    let emoticon = find(image.alt.trim())?;
+    Some(Emoticon {
+        image: Some(image.clone()),
+        ..emoticon
+    })
+}
+
+/// Replaces the emoticon shortcuts of the texts and the emoticon images of the message by
+/// `Data::Emoticon` parts, keeping their images.
+pub fn recognize(mut message: Message) -> Message {
+    message.data = message
+        .data
+        .into_iter()
+        .flat_map(|d| match d {
+            Data::Text(text) => tokenize(&text),
+            Data::Image(image) => match from_image(&image) {
+                Some(emoticon) => vec![Data::Emoticon(emoticon)],
+                None => vec![Data::Image(image)],
+            },
+            other => vec![other],
+        })
+        .collect();
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn emoticon(id: &str, shortcut: &str) -> Data {
+        Data::Emoticon(Emoticon {
+            id: id.to_string(),
+            shortcut: shortcut.to_string(),
+            image: None,
+        })
+    }
+
+    #[test]
+    fn tokenize_text() {
+        let text = Text {
+            style: "color:#004000;".to_string(),
+            content: "Hi :-) (h)(brb) é:(".to_string(),
+        };
+        assert_eq!(
+            tokenize(&text),
+            vec![
+                Data::Text(Text {
+                    style: "color:#004000;".to_string(),
+                    content: "Hi ".to_string(),
+                }),
+                emoticon("smile", ":-)"),
+                Data::Text(Text {
+                    style: "color:#004000;".to_string(),
+                    content: " ".to_string(),
+                }),
+                emoticon("hot", "(h)"),
+                emoticon("be-right-back", "(brb)"),
+                Data::Text(Text {
+                    style: "color:#004000;".to_string(),
+                    content: " é".to_string(),
+                }),
+                emoticon("sad", ":("),
+            ]
+        );
+    }
+
+    #[test]
+    fn recognize_images() {
+        let message = Message {
+            data: vec![
+                Data::Image(Image {
+                    alt: ":)".to_string(),
+                    ..Image::default()
+                }),
+                Data::Image(Image {
+                    alt: "holidays.png".to_string(),
+                    ..Image::default()
+                }),
+            ],
+            ..Message::default()
+        };
+        let message = recognize(message);
+        assert_eq!(
+            message.data[0],
+            Data::Emoticon(Emoticon {
+                id: "smile".to_string(),
+                shortcut: ":)".to_string(),
+                image: Some(Image {
+                    alt: ":)".to_string(),
+                    ..Image::default()
+                }),
+            })
+        );
+        assert!(matches!(message.data[1], Data::Image(_)));
+    }
+}
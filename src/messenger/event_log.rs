@@ -0,0 +1,278 @@
+use crate::messenger::messenger_plus_parser::MessengerPlusParser;
+use crate::messenger::{common, Data, Message};
+use chrono::NaiveDateTime;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+
+/// The availability of a contact, as written by Messenger Plus!
+#[derive(PartialEq, Debug, Clone)]
+pub enum Status {
+    Online,
+    Busy,
+    Away,
+    BeRightBack,
+    OnThePhone,
+    OutToLunch,
+    Offline,
+    /// A status not known by the library, as written in the log.
+    Other(String),
+}
+
+impl Status {
+    fn parse(status: &str) -> Self {
+        match status.trim().to_lowercase().as_str() {
+            "online" | "available" => Status::Online,
+            "busy" => Status::Busy,
+            "away" | "idle" => Status::Away,
+            "be right back" => Status::BeRightBack,
+            "on the phone" => Status::OnThePhone,
+            "out to lunch" => Status::OutToLunch,
+            "offline" | "appear offline" => Status::Offline,
+            _ => Status::Other(status.trim().to_string()),
+        }
+    }
+}
+
+/// What happened to a contact.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum EventKind {
+    SignedIn,
+    SignedOut,
+    StatusChanged,
+    NicknameChanged,
+}
+
+/// An event of a contact, read from a Messenger Plus! event log or from the system messages of a
+/// conversation (e.g. `Alice is now offline`).
+#[derive(PartialEq, Debug, Clone)]
+pub struct ContactEvent {
+    /// The local date and time of the event.
+    pub datetime: NaiveDateTime,
+    pub session_id: String,
+    pub kind: EventKind,
+    /// The nickname of the contact, after the event.
+    pub contact: String,
+    /// The status of the contact before the event, if a previous event gave it.
+    pub old_status: Option<Status>,
+    /// The status of the contact after the event, none for the nickname changes.
+    pub new_status: Option<Status>,
+    pub old_nickname: Option<String>,
+    pub new_nickname: Option<String>,
+}
+
+/// A parser of the Messenger Plus! event logs, where the contacts signing in and out, changing of
+/// status or of nickname are saved. The event logs are read with the layout of the conversation
+/// archives (see `test/events.html`): a session per day, one system row per event. Any `Message`
+/// iterator can be read for events:
+/// ```
+/// use lib_messenger_archive::{EventKind, EventLogParser, Parser, Status};
+///
+/// let events: Vec<_> = EventLogParser::from_messages(Parser::new("test/alice@example.com.html").unwrap())
+///     .flatten()
+///     .collect();
+/// assert_eq!(events[0].kind, EventKind::SignedOut);
+/// assert_eq!(events[0].contact, "Alice");
+/// assert_eq!(events[0].new_status, Some(Status::Offline));
+/// ```
+/// The English sentences of Messenger Plus! are recognised: `<name> has signed in`,
+/// `<name> has signed out`, `<name> is now <status>` and `<name> has changed his name to <name>`
+/// (or `is now known as`). The other messages are skipped.
+pub struct EventLogParser<I> {
+    messages: I,
+    /// The last status of each contact, by nickname.
+    statuses: HashMap<String, Status>,
+}
+
+impl EventLogParser<MessengerPlusParser> {
+    /// Reads the event log at `path`.
+    pub fn new(path: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::from_messages(MessengerPlusParser::new(path)?))
+    }
+}
+
+impl<I: Iterator<Item = Result<Message, Box<dyn Error>>>> EventLogParser<I> {
+    /// Reads the events from the system messages of `messages` (e.g. a `Parser`).
+    pub fn from_messages(messages: I) -> Self {
+        EventLogParser {
+            messages,
+            statuses: HashMap::new(),
+        }
+    }
+
+    fn event(&mut self, message: &Message) -> Result<Option<ContactEvent>, Box<dyn Error>> {
+        if !message.sender_friendly_name.is_empty() {
+            return Ok(None);
+        }
+        let text: String = message
+            .data
+            .iter()
+            .filter_map(|data| match data {
+                Data::System(content) => Some(content.as_str()),
+                Data::Text(text) => Some(text.content.as_str()),
+                _ => None,
+            })
+            .collect();
+        let Some((kind, contact, status, nickname)) = parse_sentence(&text) else {
+            return Ok(None);
+        };
+        let datetime = common::parse_datetime(&message.datetime)
+            .ok_or_else(|| format!("Invalid date of event: {}", message.datetime))?;
+        let old_status = match &nickname {
+            Some(old) => self.statuses.remove(old),
+            None => self.statuses.get(&contact).cloned(),
+        };
+        // without a previous status, the contact may have been online already
+        let kind = match (kind, &old_status, &status) {
+            (EventKind::StatusChanged, Some(Status::Offline), Some(status)) if *status != Status::Offline => {
+                EventKind::SignedIn
+            }
+            _ => kind,
+        };
+        if let Some(status) = status.clone().or(old_status.clone()) {
+            self.statuses.insert(contact.clone(), status);
+        }
+        Ok(Some(ContactEvent {
+            datetime,
+            session_id: message.session_id.to_string(),
+            kind,
+            old_status: if nickname.is_some() { None } else { old_status },
+            new_status: status,
+            new_nickname: nickname.as_ref().map(|_| contact.clone()),
+            old_nickname: nickname,
+            contact,
+        }))
+    }
+}
+
+impl<I: Iterator<Item = Result<Message, Box<dyn Error>>>> Iterator for EventLogParser<I> {
+    type Item = Result<ContactEvent, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let event = match self.messages.next()? {
+                Ok(message) => self.event(&message),
+                Err(e) => return Some(Err(e)),
+            };
+            match event {
+                Ok(Some(event)) => return Some(Ok(event)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Reads an event sentence: its kind, the contact (its new nickname), its new status and its old
+/// nickname.
+fn parse_sentence(text: &str) -> Option<(EventKind, String, Option<Status>, Option<String>)> {
+    let text = text.trim().trim_end_matches('.');
+    if let Some(name) = text.strip_suffix(" has signed in") {
+        return Some((EventKind::SignedIn, name.to_string(), Some(Status::Online), None));
+    }
+    if let Some(name) = text.strip_suffix(" has signed out") {
+        return Some((EventKind::SignedOut, name.to_string(), Some(Status::Offline), None));
+    }
+    for separator in [
+        " has changed his name to ",
+        " has changed her name to ",
+        " has changed their name to ",
+        " is now known as ",
+    ] {
+        if let Some((old, new)) = text.split_once(separator) {
+            return Some((EventKind::NicknameChanged, new.to_string(), None, Some(old.to_string())));
+        }
+    }
+    let (name, status) = text.rsplit_once(" is now ")?;
+    let status = Status::parse(status);
+    let kind = if status == Status::Offline { EventKind::SignedOut } else { EventKind::StatusChanged };
+    Some((kind, name.to_string(), Some(status), None))
+}
+
+/// The statuses of each contact over time, built from their events. The history of a contact
+/// follows its nickname changes, it is kept under its latest nickname.
+#[derive(Default, PartialEq, Debug)]
+pub struct StatusHistory {
+    contacts: BTreeMap<String, Vec<(NaiveDateTime, Status)>>,
+}
+
+impl StatusHistory {
+    /// Builds the history from events in chronological order.
+    pub fn from_events<'e, E: IntoIterator<Item = &'e ContactEvent>>(events: E) -> Self {
+        let mut history = StatusHistory::default();
+        for event in events {
+            if let Some(old) = &event.old_nickname {
+                if let Some(statuses) = history.contacts.remove(old) {
+                    history.contacts.entry(event.contact.clone()).or_default().extend(statuses);
+                }
+            }
+            if let Some(status) = &event.new_status {
+                history
+                    .contacts
+                    .entry(event.contact.clone())
+                    .or_default()
+                    .push((event.datetime, status.clone()));
+            }
+        }
+        history
+    }
+
+    /// The nicknames of the contacts.
+    pub fn contacts(&self) -> impl Iterator<Item = &str> {
+        self.contacts.keys().map(String::as_str)
+    }
+
+    /// The statuses of a contact, with the date they were set.
+    pub fn statuses(&self, contact: &str) -> &[(NaiveDateTime, Status)] {
+        self.contacts.get(contact).map_or(&[], Vec::as_slice)
+    }
+
+    /// The status of a contact at `datetime`, if an event gave it before.
+    pub fn status_at(&self, contact: &str, datetime: NaiveDateTime) -> Option<&Status> {
+        self.statuses(contact)
+            .iter()
+            .rev()
+            .find(|(date, _)| *date <= datetime)
+            .map(|(_, status)| status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn parse_event_log() {
+        let events: Vec<ContactEvent> = EventLogParser::new("test/events.html").unwrap().flatten().collect();
+        let kinds: Vec<EventKind> = events.iter().map(|event| event.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                EventKind::StatusChanged,
+                EventKind::SignedIn,
+                EventKind::StatusChanged,
+                EventKind::NicknameChanged,
+                EventKind::SignedOut,
+                EventKind::SignedIn,
+                EventKind::SignedOut
+            ]
+        );
+        assert_eq!(events[0].contact, "Carol");
+        assert_eq!(events[0].old_status, None);
+        assert_eq!(events[0].new_status, Some(Status::Busy));
+        assert_eq!(events[2].old_status, Some(Status::Online));
+        assert_eq!(events[2].new_status, Some(Status::Away));
+        assert_eq!(events[3].old_nickname.as_deref(), Some("Alice"));
+        assert_eq!(events[3].new_nickname.as_deref(), Some("Alicia"));
+        assert_eq!(events[4].old_status, Some(Status::Away));
+        assert_eq!(events[5].session_id, "Session_2009-08-06T09-02-47");
+        assert_eq!(events[6].old_status, Some(Status::Busy));
+
+        let history = StatusHistory::from_events(&events);
+        assert_eq!(history.contacts().collect::<Vec<_>>(), vec!["Alicia", "Carol"]);
+        assert_eq!(history.statuses("Alicia").len(), 4);
+        let date = NaiveDate::from_ymd_opt(2009, 8, 5).unwrap();
+        assert_eq!(history.status_at("Alicia", date.and_hms_opt(19, 36, 0).unwrap()), Some(&Status::Away));
+        assert_eq!(history.status_at("Alicia", date.and_hms_opt(19, 0, 0).unwrap()), None);
+    }
+}
@@ -0,0 +1,301 @@
+use crate::messenger::{Data, FileType, Message, Text, UnknownElement};
+use std::error::Error;
+use std::io::Write;
+
+/// Writes the messages as a JSON array. The image contents are not exported, only their `src` and
+/// `alt` attributes.
+pub fn write_json<W: Write>(writer: &mut W, messages: &[Message]) -> Result<(), Box<dyn Error>> {
+    writeln!(writer, "[")?;
+    for (index, message) in messages.iter().enumerate() {
+        let data: Vec<String> = message
+            .data
+            .iter()
+            .map(|d| match d {
+                Data::Text(text) => format!(
+                    "{{\"type\": \"text\", \"style\": {}, \"content\": {}}}",
+                    json_string(&text.style),
+                    json_string(&text.content)
+                ),
+                Data::Image(image) => format!(
+                    "{{\"type\": \"image\", \"src\": {}, \"alt\": {}}}",
+                    json_string(&image.src),
+                    json_string(&image.alt)
+                ),
+                Data::Emoticon(emoticon) => format!(
+                    "{{\"type\": \"emoticon\", \"id\": {}, \"shortcut\": {}}}",
+                    json_string(&emoticon.id),
+                    json_string(&emoticon.shortcut)
+                ),
+                Data::System(content) => {
+                    format!("{{\"type\": \"system\", \"content\": {}}}", json_string(content))
+                }
+                Data::Unknown(element) => format!("{{\"type\": \"unknown\", {}}}", json_element(element)),
+            })
+            .collect();
+        write!(
+            writer,
+            "  {{\"datetime\": {}, \"timezone_offset\": {}, \"session_id\": {}, \
+             \"sender_friendly_name\": {}, \"receiver_friendly_name\": {}, \"data\": [{}]}}",
+            json_string(&message.datetime),
+            message
+                .timezone_offset
+                .map_or("null".to_string(), |offset| offset.to_string()),
+            json_string(&message.session_id),
+            json_string(&message.sender_friendly_name),
+            json_string(&message.receiver_friendly_name),
+            data.join(", ")
+        )?;
+        writeln!(writer, "{}", if index + 1 < messages.len() { "," } else { "" })?;
+    }
+    writeln!(writer, "]")?;
+    Ok(())
+}
+
+/// Writes the messages of an archive of the given type as CSV, one message per line. The message
+/// body is flattened into a single `content` column, its lines being joined with spaces.
+pub fn write_csv<W: Write>(writer: &mut W, messages: &[Message], file_type: FileType) -> Result<(), Box<dyn Error>> {
+    writeln!(
+        writer,
+        "datetime,timezone_offset,session_id,sender_friendly_name,receiver_friendly_name,content"
+    )?;
+    for message in messages {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{}",
+            csv_field(&message.datetime),
+            message
+                .timezone_offset
+                .map_or(String::new(), |offset| offset.to_string()),
+            csv_field(&message.session_id),
+            csv_field(&message.sender_friendly_name),
+            csv_field(&message.receiver_friendly_name),
+            csv_field(&plain_text(message, file_type, " "))
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the messages of an archive of the given type as a simple standalone HTML page.
+pub fn write_html<W: Write>(writer: &mut W, messages: &[Message], file_type: FileType) -> Result<(), Box<dyn Error>> {
+    writeln!(writer, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\"/>")?;
+    writeln!(writer, "<title>Conversation history</title>\n</head>\n<body>\n<table>")?;
+    for message in messages {
+        let mut body = String::new();
+        for (d, new_line) in message.data.iter().zip(line_starts(message, file_type)) {
+            if new_line {
+                body.push_str("<br/>");
+            }
+            body.push_str(&match d {
+                Data::Text(text) => format!(
+                    "<span style=\"{}\">{}</span>",
+                    html_escape::encode_double_quoted_attribute(&text.style),
+                    html_escape::encode_text(&text.content)
+                ),
+                Data::Image(image) => format!(
+                    "<img src=\"{}\" alt=\"{}\"/>",
+                    html_escape::encode_double_quoted_attribute(&image.src),
+                    html_escape::encode_double_quoted_attribute(&image.alt)
+                ),
+                Data::Emoticon(emoticon) => format!(
+                    "<span class=\"emoticon {}\">{}</span>",
+                    html_escape::encode_double_quoted_attribute(&emoticon.id),
+                    html_escape::encode_text(&emoticon.shortcut)
+                ),
+                Data::System(content) => format!("<em>{}</em>", html_escape::encode_text(content)),
+                Data::Unknown(element) => format!(
+                    "<span class=\"unknown {}\">{}</span>",
+                    html_escape::encode_double_quoted_attribute(&element.name),
+                    html_escape::encode_text(&element.text_content())
+                ),
+            });
+        }
+        writeln!(
+            writer,
+            "<tr><th>{}</th><th>{}</th><td>{}</td></tr>",
+            html_escape::encode_text(&message.datetime),
+            html_escape::encode_text(&message.sender_friendly_name),
+            body
+        )?;
+    }
+    writeln!(writer, "</table>\n</body>\n</html>")?;
+    Ok(())
+}
+
+/// Writes a human readable transcript of the messages of an archive of the given type, one
+/// message per paragraph.
+pub fn write_transcript<W: Write>(writer: &mut W, messages: &[Message], file_type: FileType) -> Result<(), Box<dyn Error>> {
+    for message in messages {
+        write_transcript_message(writer, message, file_type)?;
+    }
+    Ok(())
+}
+
+/// Writes a message of a transcript (see `write_transcript`), to write the messages as they are
+/// parsed.
+pub fn write_transcript_message<W: Write>(writer: &mut W, message: &Message, file_type: FileType) -> Result<(), Box<dyn Error>> {
+    let text = plain_text(message, file_type, "\n");
+    if message.sender_friendly_name.is_empty() {
+        writeln!(writer, "[{}] * {}", message.datetime, text)?;
+    } else {
+        writeln!(
+            writer,
+            "[{}] {}: {}",
+            message.datetime,
+            message.sender_friendly_name,
+            text
+        )?;
+    }
+    Ok(())
+}
+
+/// The textual content of a message, the images being replaced by their alternative text and the
+/// emoticons by their shortcut. Its lines are joined with `separator`.
+fn plain_text(message: &Message, file_type: FileType, separator: &str) -> String {
+    let mut text = String::new();
+    for (d, new_line) in message.data.iter().zip(line_starts(message, file_type)) {
+        if new_line {
+            text.push_str(separator);
+        }
+        match d {
+            Data::Text(Text { content, .. }) | Data::System(content) => text.push_str(content),
+            Data::Image(image) => text.push_str(&image.alt),
+            Data::Emoticon(emoticon) => text.push_str(&emoticon.shortcut),
+            Data::Unknown(element) if element.name == "br" => {}
+            Data::Unknown(element) => text.push_str(&element.text_content()),
+        }
+    }
+    text
+}
+
+/// Tells whether each part of a message starts a new line. The texts of the Messenger Plus!
+/// messages are their lines, while the ones of WLM are pieces of a line with their own style. The
+/// line breaks kept in raw mode start a line too.
+fn line_starts(message: &Message, file_type: FileType) -> Vec<bool> {
+    let is_text = |d: &Data| matches!(d, Data::Text(_) | Data::System(_));
+    let mut starts = vec![false];
+    for pair in message.data.windows(2) {
+        starts.push(match pair {
+            [Data::Unknown(element), _] => element.name == "br",
+            [previous, next] => file_type == FileType::MessengerPlus && is_text(previous) && is_text(next),
+            _ => false,
+        });
+    }
+    starts
+}
+
+/// The fields of an element kept in raw mode, with its children.
+fn json_element(element: &UnknownElement) -> String {
+    let attributes: Vec<String> = element
+        .attributes
+        .iter()
+        .map(|(name, value)| format!("{}: {}", json_string(name), json_string(value)))
+        .collect();
+    let children: Vec<String> = element
+        .children
+        .iter()
+        .map(|child| format!("{{{}}}", json_element(child)))
+        .collect();
+    format!(
+        "\"name\": {}, \"attributes\": {{{}}}, \"text\": {}, \"children\": [{}]",
+        json_string(&element.name),
+        attributes.join(", "),
+        json_string(&element.text),
+        children.join(", ")
+    )
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messenger::xml_parser::XmlParser;
+    use crate::messenger::Image;
+
+    fn sample_messages() -> Vec<Message> {
+        XmlParser::new("test/alice1234.xml")
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn export_json() {
+        let mut output = Vec::new();
+        write_json(&mut output, &sample_messages()).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("[\n  {\"datetime\": \"2009-04-06T19:40:41.851Z\", \"timezone_offset\": 120"));
+        assert!(output.contains(
+            "\"data\": [{\"type\": \"text\", \"style\": \"font-family:Courier New; color:#004000; \", \"content\": \"Hello!\"}]},\n"
+        ));
+        assert!(output.ends_with("}]}\n]\n"));
+    }
+
+    #[test]
+    fn export_csv_and_transcript() {
+        let mut output = Vec::new();
+        write_csv(&mut output, &sample_messages(), FileType::XML).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.lines().nth(2), Some("2009-04-06T20:22:05.918Z,120,1,Bob,Alice,Hi Alice!"));
+
+        let mut output = Vec::new();
+        write_transcript(&mut output, &sample_messages(), FileType::XML).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.lines().next(), Some("[2009-04-06T19:40:41.851Z] Alice: Hello!"));
+
+        let text = |content: &str| {
+            Data::Text(Text {
+                style: String::new(),
+                content: content.to_string(),
+            })
+        };
+        let mut message = sample_messages().remove(0);
+        message.data = vec![
+            text("Hello Alice!"),
+            text("Look"),
+            Data::Image(Image {
+                alt: ":)".to_string(),
+                ..Image::default()
+            }),
+            text(" at this"),
+            Data::Unknown(UnknownElement {
+                name: "br".to_string(),
+                ..UnknownElement::default()
+            }),
+            text("Bye"),
+        ];
+        assert_eq!(plain_text(&message, FileType::MessengerPlus, "\n"), "Hello Alice!\nLook:) at this\nBye");
+        assert_eq!(plain_text(&message, FileType::XML, "\n"), "Hello Alice!Look:) at this\nBye");
+
+        let mut output = Vec::new();
+        write_csv(&mut output, &[message.clone()], FileType::MessengerPlus).unwrap();
+        assert!(String::from_utf8(output).unwrap().ends_with(",Hello Alice! Look:) at this Bye\n"));
+        let mut output = Vec::new();
+        write_html(&mut output, &[message], FileType::MessengerPlus).unwrap();
+        assert!(String::from_utf8(output).unwrap().contains("Hello Alice!</span><br/><span style=\"\">Look</span>"));
+    }
+}
@@ -0,0 +1,116 @@
+use crate::messenger::{common, Data, Message};
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+
+/// Some criteria to select the messages of an archive. The parsers check them as soon as possible
+/// to skip the unwanted messages cheaply (e.g. without loading their images).
+#[derive(Default, Clone, PartialEq, Debug)]
+pub struct MessageFilter {
+    /// A part of the sender friendly name (case insensitive).
+    pub sender: Option<String>,
+    /// The oldest date of the messages, compared with their local date: the date saved in the
+    /// archive with the timezone offset of the message, when known.
+    pub since: Option<NaiveDateTime>,
+    /// The newest date of the messages, compared with their local date (see `since`).
+    pub until: Option<NaiveDateTime>,
+    /// Some texts that must all be found in the message (case insensitive).
+    pub containing: Vec<String>,
+    /// Only keep the messages containing an image.
+    pub with_images: bool,
+}
+
+impl MessageFilter {
+    /// Returns `true` if the message matches all the criteria.
+    pub fn matches(&self, message: &Message) -> bool {
+        if !self.accepts_datetime(&message.datetime, message.timezone_offset) || !self.accepts_sender(&message.sender_friendly_name) {
+            return false;
+        }
+        if self.with_images && !message.data.iter().any(|d| matches!(d, Data::Image(_))) {
+            return false;
+        }
+        if self.containing.is_empty() {
+            return true;
+        }
+        let text: String = message
+            .data
+            .iter()
+            .filter_map(|d| match d {
+                Data::Text(text) => Some(text.content.to_lowercase()),
+                Data::System(content) => Some(content.to_lowercase()),
+                Data::Unknown(element) => Some(element.text_content().to_lowercase()),
+                Data::Image(_) | Data::Emoticon(_) => None,
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+        self.containing
+            .iter()
+            .all(|part| text.contains(&part.to_lowercase()))
+    }
+
+    /// Checks the local date of a message, before reading its content.
+    pub(crate) fn accepts_datetime(&self, datetime: &str, timezone_offset: Option<i64>) -> bool {
+        if self.since.is_none() && self.until.is_none() {
+            return true;
+        }
+        match common::parse_datetime(datetime)
+            .map(|datetime| datetime + Duration::minutes(timezone_offset.unwrap_or(0)))
+        {
+            Some(datetime) => {
+                self.since.is_none_or(|since| datetime >= since)
+                    && self.until.is_none_or(|until| datetime <= until)
+            }
+            None => false,
+        }
+    }
+
+    /// Checks the sender of a message, before reading its content.
+    pub(crate) fn accepts_sender(&self, sender: &str) -> bool {
+        self.sender
+            .as_ref()
+            .is_none_or(|part| sender.to_lowercase().contains(&part.to_lowercase()))
+    }
+
+    /// Checks if some messages sent during the given day may be accepted.
+    pub(crate) fn accepts_day(&self, day: NaiveDate) -> bool {
+        self.since.is_none_or(|since| day >= since.date())
+            && self.until.is_none_or(|until| day <= until.date())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messenger::{Image, Text};
+
+    #[test]
+    fn match_messages() {
+        let message = Message {
+            datetime: "2009-08-05T19:35".to_string(),
+            sender_friendly_name: "Bob".into(),
+            data: vec![
+                Data::Image(Image::default()),
+                Data::Text(Text {
+                    content: "Maybe you can call him?".to_string(),
+                    ..Text::default()
+                }),
+            ],
+            ..Message::default()
+        };
+        let day = NaiveDate::from_ymd_opt(2009, 8, 5).unwrap();
+        let filter = MessageFilter {
+            sender: Some("bo".to_string()),
+            since: day.and_hms_opt(19, 35, 0),
+            until: day.and_hms_opt(20, 0, 0),
+            containing: vec!["CALL HIM".to_string()],
+            with_images: true,
+        };
+        assert!(filter.matches(&message));
+        assert!(filter.accepts_day(day));
+        assert!(!filter.accepts_day(day.succ_opt().unwrap()));
+
+        let filter = MessageFilter {
+            since: day.and_hms_opt(19, 36, 0),
+            ..filter
+        };
+        assert!(!filter.matches(&message));
+    }
+}
@@ -0,0 +1,272 @@
+use crate::messenger::{Data, Message, Text};
+
+/// The colours of the Messenger Plus! palette, used by the `[c=N]`, `[a=N]` and `·$N` codes.
+/// Only the 16 first colours are known, the codes using the other ones are removed without
+/// changing the colour.
+pub const COLORS: [&str; 16] = [
+    "#FFFFFF", "#000000", "#00007F", "#009300", "#FF0000", "#7F0000", "#9C009C", "#FC7F00",
+    "#FFFF00", "#00FC00", "#009393", "#00FFFF", "#0000FC", "#FF00FF", "#7F7F7F", "#D2D2D2",
+];
+
+/// The character introducing the short formatting codes (e.g. `·$4` or `·#`).
+const CODE_PREFIX: char = '·';
+
+/// The current formatting, while decoding a text.
+#[derive(Default, Clone, PartialEq)]
+struct Format {
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    strikethrough: bool,
+    color: Option<String>,
+    background: Option<String>,
+}
+
+impl Format {
+    /// The CSS-like style of the format, as saved in the archives.
+    fn style(&self) -> String {
+        let mut style = String::new();
+        if self.bold {
+            style.push_str("font-weight:bold; ");
+        }
+        if self.italic {
+            style.push_str("font-style:italic; ");
+        }
+        match (self.underline, self.strikethrough) {
+            (true, true) => style.push_str("text-decoration:underline line-through; "),
+            (true, false) => style.push_str("text-decoration:underline; "),
+            (false, true) => style.push_str("text-decoration:line-through; "),
+            (false, false) => {}
+        }
+        if let Some(color) = &self.color {
+            style.push_str(&format!("color:{}; ", color));
+        }
+        if let Some(background) = &self.background {
+            style.push_str(&format!("background-color:{}; ", background));
+        }
+        style
+    }
+}
+
+/// Decodes the Messenger Plus! formatting codes of a text (a friendly name or a message) into
+/// styled runs. The supported codes are:
+/// - `[b]`, `[i]`, `[u]`, `[s]` and their closing tags, for bold, italic, underline and
+///   strikethrough;
+/// - `[c=N]` / `[c=#RRGGBB]` and `[/c]` (or `[/c=N]`, ending a gradient) for the text colour;
+/// - `[a=N]` / `[a=#RRGGBB]` and `[/a]` for the background colour;
+/// - `·$N`, `·$N,M` and `·$#RRGGBB` for the colours, `·#` (bold), `·&` (italic), `·@`
+///   (underline), `·'` (strikethrough) toggles and `·0` to reset the formatting.
+///
+/// The unknown codes are kept as they are.
+pub fn decode(text: &str) -> Vec<Text> {
+    let mut runs: Vec<Text> = vec![];
+    let mut format = Format::default();
+    let mut content = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let decoded = match c {
+            '[' => decode_tag(rest, &format),
+            CODE_PREFIX => decode_code(&rest[c.len_utf8()..], &format)
+                .map(|(length, format)| (length + c.len_utf8(), format)),
+            _ => None,
+        };
+        match decoded {
+            Some((length, next)) => {
+                if next != format {
+                    push_run(&mut runs, &format, &mut content);
+                    format = next;
+                }
+                rest = &rest[length..];
+            }
+            None => {
+                content.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    push_run(&mut runs, &format, &mut content);
+    runs
+}
+
+/// Removes the Messenger Plus! formatting codes of a text (see `decode`).
+pub fn strip(text: &str) -> String {
+    decode(text).into_iter().map(|run| run.content).collect()
+}
+
+/// Decodes the formatting codes of a text part. The styles of the runs are appended to the style
+/// of the part.
+pub fn decode_text(text: &Text) -> Vec<Text> {
+    let mut runs = decode(&text.content);
+    if runs.is_empty() {
+        runs.push(Text::default());
+    }
+    for run in &mut runs {
+        run.style = format!("{}{}", text.style, run.style);
+    }
+    runs
+}
+
+/// Strips the formatting codes of the friendly names and decodes the ones of the texts of the
+/// message.
+pub fn decode_message(mut message: Message) -> Message {
+    message.sender_friendly_name = strip(&message.sender_friendly_name).into();
+    message.receiver_friendly_name = strip(&message.receiver_friendly_name).into();
+    message.data = message
+        .data
+        .into_iter()
+        .flat_map(|d| match d {
+            Data::Text(text) => decode_text(&text).into_iter().map(Data::Text).collect(),
+            other => vec![other],
+        })
+        .collect();
+    message
+}
+
+fn push_run(runs: &mut Vec<Text>, format: &Format, content: &mut String) {
+    if content.is_empty() {
+        return;
+    }
+    let style = format.style();
+    match runs.last_mut() {
+        Some(last) if last.style == style => last.content.push_str(content),
+        _ => runs.push(Text {
+            style,
+            content: content.clone(),
+        }),
+    }
+    content.clear();
+}
+
+/// Decodes a tag code (e.g. `[b]` or `[c=4]`) at the start of `text`, returning its length and the
+/// resulting format.
+fn decode_tag(text: &str, format: &Format) -> Option<(usize, Format)> {
+    let end = text.find(']')?;
+    let tag = text[1..end].to_lowercase();
+    let mut next = format.clone();
+    match tag.as_str() {
+        "b" | "/b" => next.bold = tag == "b",
+        "i" | "/i" => next.italic = tag == "i",
+        "u" | "/u" => next.underline = tag == "u",
+        "s" | "/s" => next.strikethrough = tag == "s",
+        "/a" => next.background = None,
+        _ if tag == "/c" || tag.starts_with("/c=") => next.color = None,
+        _ => {
+            let (name, value) = tag.split_once('=')?;
+            let color = parse_color(value)?;
+            match name {
+                "c" => next.color = color.or(next.color),
+                "a" => next.background = color.or(next.background),
+                _ => return None,
+            }
+        }
+    }
+    Some((end + 1, next))
+}
+
+/// Decodes a short code (e.g. `$4` or `#`), following the `·` prefix at the start of `text`,
+/// returning its length and the resulting format.
+fn decode_code(text: &str, format: &Format) -> Option<(usize, Format)> {
+    let mut next = format.clone();
+    match text.chars().next()? {
+        '#' => next.bold = !next.bold,
+        '&' => next.italic = !next.italic,
+        '@' => next.underline = !next.underline,
+        '\'' => next.strikethrough = !next.strikethrough,
+        '0' => next = Format::default(),
+        '$' => {
+            let colors = &text[1..];
+            let (length, color) = color_prefix(colors)?;
+            next.color = color.or(next.color);
+            let mut total = 1 + length;
+            if let Some(background) = colors[length..].strip_prefix(',') {
+                if let Some((length, color)) = color_prefix(background) {
+                    next.background = color.or(next.background);
+                    total += 1 + length;
+                }
+            }
+            return Some((total, next));
+        }
+        _ => return None,
+    }
+    Some((1, next))
+}
+
+/// Reads a colour (`N` or `#RRGGBB`) at the start of `text`, returning its length and the colour
+/// (`None` if it is not in the known palette).
+fn color_prefix(text: &str) -> Option<(usize, Option<String>)> {
+    let length = if let Some(hex) = text.strip_prefix('#') {
+        let digits = hex.chars().take_while(char::is_ascii_hexdigit).count();
+        if digits < 6 {
+            return None;
+        }
+        7
+    } else {
+        text.chars().take(2).take_while(char::is_ascii_digit).count()
+    };
+    if length == 0 {
+        return None;
+    }
+    Some((length, parse_color(&text[..length])?))
+}
+
+/// Parses a palette index or a `#RRGGBB` colour.
+fn parse_color(value: &str) -> Option<Option<String>> {
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Some(Some(format!("#{}", hex.to_uppercase())));
+        }
+        return None;
+    }
+    let index: usize = value.parse().ok()?;
+    Some(COLORS.get(index).map(|color| color.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(style: &str, content: &str) -> Text {
+        Text {
+            style: style.to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn decode_formatting_codes() {
+        assert_eq!(
+            decode("[b]Bob[/b] [c=4]is [i]away[/i][/c=12] [x]"),
+            vec![
+                run("font-weight:bold; ", "Bob"),
+                run("", " "),
+                run("color:#FF0000; ", "is "),
+                run("font-style:italic; color:#FF0000; ", "away"),
+                run("", " [x]"),
+            ]
+        );
+        assert_eq!(
+            decode("·$4,1Alice·0 ·#·$#00ff00B·$99C"),
+            vec![
+                run("color:#FF0000; background-color:#000000; ", "Alice"),
+                run("", " "),
+                run("font-weight:bold; color:#00FF00; ", "BC"),
+            ]
+        );
+    }
+
+    #[test]
+    fn strip_formatting_codes() {
+        assert_eq!(strip("[c=#FF8000]Alice[/c] ·&(at work)·&"), "Alice (at work)");
+        assert_eq!(strip("[b]unclosed [c=4"), "unclosed [c=4");
+        let message = decode_message(Message {
+            sender_friendly_name: "[b]Bob[/b]".into(),
+            data: vec![Data::Text(run("color:#004000; ", "[u]Hi[/u]"))],
+            ..Message::default()
+        });
+        assert_eq!(&*message.sender_friendly_name, "Bob");
+        assert_eq!(
+            message.data,
+            vec![Data::Text(run("color:#004000; text-decoration:underline; ", "Hi"))]
+        );
+    }
+}
@@ -1,219 +1,351 @@
 use std::error::Error;
+use std::fmt::Write;
+use std::sync::Arc;
 use crate::messenger::common::parse_attributes;
-use crate::messenger::{common, Data, FileType, Image, Message, ArchiveDetails, Text, MessengerArchive};
+use crate::messenger::{common, Data, FileType, Image, Message, ArchiveDetails, SessionParticipant, Text, MessengerArchive};
+use crate::messenger::filter::MessageFilter;
+use crate::messenger::source::ArchiveSource;
 use chrono::{NaiveDateTime, NaiveTime, Timelike};
-use std::fs::File;
-use std::io::{BufReader, Read};
-use std::path::Path;
 use xml::attribute::OwnedAttribute;
-use xml::EventReader;
 use xml::reader::XmlEvent;
 
+/// The paths of the elements containing the messages of a session, their rows and their cells.
+const MESSAGES: &[&str] = &["html", "body", "div", "table", "tbody"];
+pub(crate) const MESSAGE: &[&str] = &["html", "body", "div", "table", "tbody", "tr"];
+const MESSAGE_CELL: &[&str] = &["html", "body", "div", "table", "tbody", "tr", "td"];
+const MESSAGE_HEADER: &[&str] = &["html", "body", "div", "table", "tbody", "tr", "th"];
+const MESSAGE_TIME: &[&str] = &["html", "body", "div", "table", "tbody", "tr", "th", "span"];
+/// The path of the parts of a message with their own style.
+const MESSAGE_PART: &[&str] = &["html", "body", "div", "table", "tbody", "tr", "td", "span"];
+/// The paths of the participants listed at the beginning of a session, and of their e-mails.
+const PARTICIPANT: &[&str] = &["html", "body", "div", "ul", "li"];
+const PARTICIPANT_EMAIL: &[&str] = &["html", "body", "div", "ul", "li", "span"];
 
-pub struct MessengerPlusParser<'a> {
+/// The elements of the archives, by path from the root, with the attributes read by the parser
+/// or written by Messenger Plus!. The other elements are ignored.
+pub(crate) const ELEMENTS: &[(&[&str], &[&str])] = &[
+    (&["html"], &[]),
+    (&["html", "head"], &[]),
+    (&["html", "head", "title"], &[]),
+    (&["html", "head", "meta"], &["http-equiv", "content"]),
+    (&["html", "head", "style"], &["type"]),
+    (&["html", "head", "link"], &["rel", "type", "href", "media"]),
+    (&["html", "body"], &[]),
+    (&["html", "body", "h1"], &[]),
+    (&["html", "body", "div"], &["class", "id"]),
+    (&["html", "body", "div", "h2"], &[]),
+    (&["html", "body", "div", "ul"], &[]),
+    (PARTICIPANT, &["class"]),
+    (PARTICIPANT_EMAIL, &[]),
+    (&["html", "body", "div", "table"], &["cellspacing"]),
+    (MESSAGES, &[]),
+    (MESSAGE, &["class"]),
+    (MESSAGE_HEADER, &[]),
+    (MESSAGE_TIME, &["class"]),
+    (MESSAGE_CELL, &["style"]),
+    (MESSAGE_PART, &["style"]),
+    (&["html", "body", "div", "table", "tbody", "tr", "td", "img"], &["src", "alt", "class"]),
+    (&["html", "body", "div", "table", "tbody", "tr", "td", "br"], &[]),
+];
+
+pub struct MessengerPlusParser {
     details: ArchiveDetails,
-    reader: EventReader<BufReader<File>>,
-    parents: (String, Vec<OwnedAttribute>),
+    reader: common::XmlReader,
+    /// The names of the elements containing the current position, from the root.
+    parents: Vec<String>,
+    /// The attributes of the last element started.
+    attributes: Vec<OwnedAttribute>,
     session: MsgPlusSession,
-    directory: &'a Path,
+    /// Where the archive is read from, to load its images.
+    source: ArchiveSource,
     first_message: bool,
+    filter: MessageFilter,
+    /// The current session was rejected by the filter, its messages are ignored.
+    skip_session: bool,
+    /// Keeps the unrecognised elements of the messages (see `MessengerArchive::set_raw`).
+    raw: bool,
+    /// The unrecognised elements being read.
+    unknown: common::UnknownElements,
 }
 
 #[derive(Default)]
 struct MsgPlusSession {
     date: NaiveDateTime,
-    id: String,
-    owner: String,
-    recipient: String,
+    /// The ID and the participants of the session, shared by its messages.
+    id: Arc<str>,
+    owner: Arc<str>,
+    recipient: Arc<str>,
     message_style: String,
+    /// The last participant read in the session header is the owner.
+    owner_listed: bool,
 }
 
 
-impl<'a> MessengerPlusParser<'a> {
-    pub fn new(path: &'a str) -> Result<Self, Box<dyn Error>> {
-        let path_t = Path::new(path);
-        Ok(MessengerPlusParser {
+impl MessengerPlusParser {
+    pub fn new(path: &str) -> Result<Self, Box<dyn Error>> {
+        Self::from_source(ArchiveSource::open(path)?)
+    }
+
+    pub fn from_source(source: ArchiveSource) -> Result<Self, Box<dyn Error>> {
+        let reader = common::get_parser(&source)?;
+        Ok(Self::with_reader(source, reader))
+    }
+
+    /// Parses the archive `source` while it is read from `reader` (e.g. a stream).
+    pub fn from_reader(source: ArchiveSource, reader: Box<dyn std::io::Read + Send>) -> Self {
+        Self::with_reader(source, common::get_reader_parser(reader))
+    }
+
+    /// Parses a part of the archive `source` (see `SessionIndex`): the header of the page, then
+    /// the sessions from a given one. Only the first message of the archive has its seconds, so
+    /// `first_session` tells whether the part starts at the first session.
+    pub fn resume(source: ArchiveSource, content: Vec<u8>, first_session: bool) -> Self {
+        let mut parser = Self::with_reader(source, common::get_content_parser(content));
+        parser.first_message = first_session;
+        parser
+    }
+
+    fn with_reader(source: ArchiveSource, reader: common::XmlReader) -> Self {
+        MessengerPlusParser {
             details: ArchiveDetails {
-                recipient_id: path_t
-                    .file_stem()
-                    .unwrap_or_default()
-                    .to_str()
-                    .unwrap_or_default()
-                    .to_string(),
+                recipient_id: source.file_stem(),
                 file_type: FileType::MessengerPlus,
                 ..ArchiveDetails::default()
             },
-            reader:  common::get_parser(path)?,
-            parents: ("".to_string(), vec![]),
+            reader,
+            parents: vec![],
+            attributes: vec![],
             session: MsgPlusSession::default(),
-            directory: path_t
-                .parent().ok_or("The file must be somewhere in a directory")?,
+            source,
             first_message: true,
-        })
+            filter: MessageFilter::default(),
+            skip_session: false,
+            raw: false,
+            unknown: common::UnknownElements::default(),
+        }
     }
 
-    fn parse_node(&mut self, name: &str, attributes: &Vec<OwnedAttribute>, message: &mut Message) -> Result<(), Box<dyn Error>> {
+    fn parse_node(&mut self, name: &str, attributes: &[OwnedAttribute], message: &mut Message) -> Result<(), Box<dyn Error>> {
         let attributes = parse_attributes(attributes);
+        if self.skip_session && name != "div" {
+            return Ok(());
+        }
+        if self.unknown.is_reading() {
+            self.unknown.start(self.parents.len() + 1, name, attributes);
+            return Ok(());
+        }
         match name {
-            "div" => {
-                if self.parents.0.ends_with("html.body")
-                    && attributes
-                    .iter()
-                    .any(|(attr, val)| attr.eq(&"class") && val.eq(&"mplsession"))
-                {
-                    if let Some(id) = attributes.get("id") {
-                        self.session.id = id.to_string();
-                        self.session.date = NaiveDateTime::parse_from_str(id, "Session_%Y-%m-%dT%H-%M-%S")?;
-                        if self.details.first_session_id.is_empty() {
-                            self.details.first_session_id = id.to_string();
-                        }
+            "div" if self.inside(&["html", "body"]) && attributes.has("class", "mplsession") => {
+                if let Some(id) = attributes.get("id") {
+                    self.session.id = id.into();
+                    self.session.date = NaiveDateTime::parse_from_str(id, "Session_%Y-%m-%dT%H-%M-%S")?;
+                    if self.details.first_session_id.is_empty() {
+                        self.details.first_session_id = id.to_string();
+                    }
+                    self.skip_session = !self.filter.accepts_day(self.session.date.date());
+                    if self.skip_session {
+                        self.first_message = false;
                     }
                 }
             }
-            "td" => {
-                if self.parents.0.ends_with("html.body.div.table.tbody.tr")
-                    && attributes.iter().any(|(attr, ..)| attr.eq(&"style"))
-                {
-                    if let Some(style) = attributes.get("style") {
-                        self.session.message_style =
-                            html_escape::decode_html_entities(style).trim().to_string()
-                    }
+            "td" if self.inside(MESSAGE) => {
+                if let Some(style) = attributes.get("style") {
+                    self.session.message_style =
+                        html_escape::decode_html_entities(style).trim().to_string()
                 }
             }
-            "tr" => {
-                if self.parents.0.ends_with("html.body.div.table.tbody") {
-                    message.session_id = self.session.id.to_string();
-                    if attributes
-                        .iter()
-                        .any(|(attr, val)| attr.eq(&"class") && val.eq(&"msgplus"))
-                    {
-                        message.data = vec![Data::System("".to_string())];
-                    }
+            "tr" if self.inside(MESSAGES) => {
+                message.session_id.clone_from(&self.session.id);
+                if attributes.has("class", "msgplus") {
+                    message.data.clear();
+                    message.data.push(Data::System(String::new()));
                 }
             }
-            "img" => {
-                if self.parents.0.ends_with("html.body.div.table.tbody.tr.td")
-                    && attributes.iter().any(|(attr, ..)| attr.eq(&"src"))
-                {
-                    let mut img = Image::default();
-
-                    if let Some(alt) = attributes.get("alt") {
-                        img.alt = alt.trim().to_string();
-                    }
-                    if let Some(src) = attributes.get("src") {
-                        img.src = src.trim().to_string();
-                        let mut buffer = Vec::new();
-                        File::open(self.directory.join(src))?.read_to_end(&mut buffer)?;
-                        img.content = buffer;
-                    }
-                    message.data.push(Data::Image(img));
+            "img" if self.inside(MESSAGE_CELL) => {
+                if let Some(src) = attributes.get("src") {
+                    // the image content is loaded only if the message is kept by the filter
+                    message.data.push(Data::Image(Image {
+                        src: src.trim().to_string(),
+                        alt: attributes.get("alt").unwrap_or("").trim().to_string(),
+                        ..Image::default()
+                    }));
                 }
             }
+            "th" if self.inside(MESSAGE) => {}
+            "span" if self.inside(MESSAGE_HEADER) || self.inside(MESSAGE_CELL) => {}
+            _ if self.raw && self.in_message() => {
+                self.unknown.start(self.parents.len() + 1, name, attributes);
+            }
             _ => {}
         }
         Ok(())
     }
 
     fn parse_text(&mut self, data: &str, message: &mut Message) -> Result<(), Box<dyn Error>> {
-        match self.parents.0.as_str() {
-            ".html.body.div.ul.li" => {
-                let attributes = parse_attributes(&self.parents.1);
-                if attributes
-                    .iter()
-                    .any(|(attr, val)| attr.eq(&"class") && val.eq(&"in"))
-                {
-                    self.session.owner = data.trim().to_string();
-                } else {
-                    self.session.recipient = data.trim().to_string();
-                }
+        if self.skip_session {
+            return Ok(());
+        }
+        if self.unknown.text(data) {
+            return Ok(());
+        }
+        if self.at(PARTICIPANT) {
+            if parse_attributes(&self.attributes).has("class", "in") {
+                self.session.owner = data.trim().into();
+                self.session.owner_listed = true;
+            } else {
+                self.session.recipient = data.trim().into();
+                self.session.owner_listed = false;
             }
-            ".html.body.div.table.tbody.tr.th.span" => {
-                if self.first_message {
-                    let datetime = NaiveDateTime::new(
-                        self.session.date.date(),
-                        NaiveTime::parse_from_str(
-                            format!("{}:{}", data, self.session.date.second()).as_str(),
-                            "(%H:%M):%S",
-                        )?,
-                    );
-                    message.datetime = datetime.format("%Y-%m-%dT%H:%M:%S").to_string();
-                    self.first_message = false;
-                } else {
-                    let datetime = NaiveDateTime::new(
-                        self.session.date.date(),
-                        NaiveTime::parse_from_str(data, "(%H:%M)")?,
-                    );
-                    message.datetime = datetime.format("%Y-%m-%dT%H:%M").to_string();
-                };
+        } else if self.at(PARTICIPANT_EMAIL) {
+            let friendly_name = if self.session.owner_listed {
+                &self.session.owner
+            } else {
+                &self.session.recipient
+            };
+            self.details.participants.push(SessionParticipant {
+                session_id: self.session.id.to_string(),
+                friendly_name: friendly_name.to_string(),
+                email: data.trim().trim_start_matches('(').trim_end_matches(')').to_string(),
+            });
+        } else if self.at(MESSAGE_TIME) {
+            if self.first_message {
+                let datetime = NaiveDateTime::new(
+                    self.session.date.date(),
+                    NaiveTime::parse_from_str(
+                        format!("{}:{}", data, self.session.date.second()).as_str(),
+                        "(%H:%M):%S",
+                    )?,
+                );
+                message.datetime.clear();
+                write!(message.datetime, "{}", datetime.format("%Y-%m-%dT%H:%M:%S"))?;
+                self.first_message = false;
+            } else {
+                let datetime = NaiveDateTime::new(
+                    self.session.date.date(),
+                    NaiveTime::parse_from_str(data, "(%H:%M)")?,
+                );
+                message.datetime.clear();
+                write!(message.datetime, "{}", datetime.format("%Y-%m-%dT%H:%M"))?;
+            };
+        } else if self.at(MESSAGE_HEADER) {
+            if data.contains(&*self.session.owner) {
+                message.sender_friendly_name.clone_from(&self.session.owner);
+                message.receiver_friendly_name.clone_from(&self.session.recipient);
+            } else {
+                message.sender_friendly_name.clone_from(&self.session.recipient);
+                message.receiver_friendly_name.clone_from(&self.session.owner);
             }
-            ".html.body.div.table.tbody.tr.th" => {
-                if data.matches(&self.session.owner).count() > 0 {
-                    message.sender_friendly_name = self.session.owner.to_string();
-                    message.receiver_friendly_name = self.session.recipient.to_string();
-                } else {
-                    message.sender_friendly_name = self.session.recipient.to_string();
-                    message.receiver_friendly_name = self.session.owner.to_string();
-                }
+        } else if self.at(MESSAGE_PART) {
+            let style = match parse_attributes(&self.attributes).get("style") {
+                None => self.session.message_style.clone(),
+                Some(style) => style.trim().to_string(),
+            };
+            message.data.push(Data::Text(Text {
+                style,
+                content: data.to_string(),
+            }));
+        } else if self.at(MESSAGE_CELL) {
+            if let Some(Data::System(_)) = message.data.first() {
+                message.data.push(Data::System(data.to_string()));
+                message.data.swap_remove(0);
+            } else {
+                let style = match parse_attributes(&self.attributes).get("style") {
+                    None => self.session.message_style.clone(),
+                    Some(style) => style.trim().to_string(),
+                };
+                message.data.push(Data::Text(Text {
+                    style,
+                    content: data.to_string(),
+                }));
             }
-            ".html.body.div.table.tbody.tr.td" => {
-                let attributes = parse_attributes(&self.parents.1);
-                if let Some(Data::System(_)) = message.data.first() {
-                    message.data.push(Data::System(data.to_string()));
-                    message.data.swap_remove(0);
-                } else {
-                    let mut txt = Text {
-                        content: data.to_string(),
-                        ..Text::default()
-                    };
-                    match attributes.get("style") {
-                        None => {
-                            txt.style = self.session.message_style.clone();
-                        }
-                        Some(style) => {
-                            txt.style = style.trim().to_string();
-                        }
-                    };
-                    message.data.push(Data::Text(txt));
+        }
+        Ok(())
+    }
+
+    /// Checks the current element is inside elements ending with `path`.
+    fn inside(&self, path: &[&str]) -> bool {
+        self.parents.len() >= path.len()
+            && self.parents[self.parents.len() - path.len()..]
+                .iter()
+                .zip(path)
+                .all(|(parent, name)| parent == name)
+    }
+
+    /// Checks the current element is inside a message row.
+    fn in_message(&self) -> bool {
+        self.parents.len() >= MESSAGE.len()
+            && self.parents.iter().zip(MESSAGE).all(|(parent, name)| parent == name)
+    }
+
+    /// Checks the current element is at `path`, from the root.
+    fn at(&self, path: &[&str]) -> bool {
+        self.parents.len() == path.len() && self.inside(path)
+    }
+
+    fn load_images(&self, message: &mut Message) -> Result<(), Box<dyn Error>> {
+        if let ArchiveSource::Memory { .. } | ArchiveSource::Stream { .. } = self.source {
+            // the archive has no folder: the images are loaded by whoever read it (e.g. `AsyncParser`)
+            return Ok(());
+        }
+        for data in message.data.iter_mut() {
+            if let Data::Image(img) = data {
+                if !img.src.is_empty() {
+                    img.content = self.source.resource(&img.src)?;
                 }
             }
-            _ => {}
         }
         Ok(())
     }
 }
 
-impl<'a> Iterator for MessengerPlusParser<'a>  {
+impl Iterator for MessengerPlusParser {
     type Item = Result<Message, Box<dyn Error>>;
 
     fn next(&mut self) -> Option<Self::Item> {
         let mut message = Message::default();
+        Some(self.next_into(&mut message)?.map(|()| message))
+    }
+}
+
+impl MessengerArchive for MessengerPlusParser {
+    fn next_into(&mut self, message: &mut Message) -> Option<Result<(), Box<dyn Error>>> {
+        message.clear();
         loop {
             let e = self.reader.next();
             match e {
                 Ok(XmlEvent::StartElement {
                        name, attributes, ..
                    }) => {
-                    let res = self.parse_node(&name.local_name, &attributes, &mut message);
+                    let res = self.parse_node(&name.local_name, &attributes, message);
                     if let Err(e) = res { return Some(Err(e)); }
-                    self.parents.0 = format!("{}.{}", self.parents.0, name.local_name);
-                    self.parents.1 = attributes;
+                    self.parents.push(name.local_name);
+                    self.attributes = attributes;
                 }
                 Ok(XmlEvent::Characters(data)) => {
-                    let res = self.parse_text(&data, &mut message);
+                    let res = self.parse_text(&data, message);
                     if let Err(e) = res { return Some(Err(e)); }
                 }
-                Ok(XmlEvent::EndElement { name }) => {
-                    let new_selector = match self.parents.0.rfind('.') {
-                        Some(pos) => &self.parents.0[0..pos],
-                        None => "",
-                    };
-                    self.parents.0 = new_selector.to_string();
-                    if name.local_name.eq("tr") && self.parents.0.ends_with("html.body.div.table.tbody") {
-                        return Some(Ok(message));
+                Ok(XmlEvent::EndElement { .. }) => {
+                    if let Some(element) = self.unknown.end(self.parents.len()) {
+                        message.data.push(Data::Unknown(element));
+                    }
+                    if self.at(MESSAGE_PART) {
+                        // the text following the part has the style of the cell
+                        self.attributes.clear();
+                    }
+                    let row = self.inside(MESSAGE);
+                    self.parents.pop();
+                    if row {
+                        if self.skip_session || !self.filter.matches(message) {
+                            message.clear();
+                            continue;
+                        }
+                        if let Err(e) = self.load_images(message) { return Some(Err(e)); }
+                        return Some(Ok(()));
                     }
                 }
                 Ok(XmlEvent::EndDocument) => {
-                    self.details.last_session_id = self.session.id.clone();
+                    self.details.last_session_id = self.session.id.to_string();
                     return None;
                 }
                 Err(e) => { return Some(Err(Box::new(e))) },
@@ -221,9 +353,7 @@ impl<'a> Iterator for MessengerPlusParser<'a>  {
             }
         }
     }
-}
 
-impl<'a> MessengerArchive for MessengerPlusParser<'a> {
     fn details(&self) -> Option<&ArchiveDetails> {
         if self.details.last_session_id.is_empty() {
             None
@@ -231,15 +361,25 @@ impl<'a> MessengerArchive for MessengerPlusParser<'a> {
             Some(&self.details)
         }
     }
+
+    fn set_filter(&mut self, filter: MessageFilter) {
+        self.filter = filter;
+    }
+
+    fn set_raw(&mut self, raw: bool) {
+        self.raw = raw;
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::messenger::UnknownElement;
     use std::fs::File;
     use std::io::Read;
 
     #[test]
+    #[allow(clippy::useless_vec)]
     fn parse_sample_file() {
         let path = "test/alice@example.com.html";
         let mut parser = MessengerPlusParser::new(path).unwrap();
@@ -253,14 +393,26 @@ mod tests {
             recipient_id: "alice@example.com".to_string(),
             first_session_id: "Session_2009-08-05T19-30-21".to_string(),
             last_session_id: "Session_2009-08-05T19-30-21".to_string(),
+            participants: vec![
+                SessionParticipant {
+                    session_id: "Session_2009-08-05T19-30-21".to_string(),
+                    friendly_name: "Bob".to_string(),
+                    email: "bob@example.com".to_string(),
+                },
+                SessionParticipant {
+                    session_id: "Session_2009-08-05T19-30-21".to_string(),
+                    friendly_name: "Alice".to_string(),
+                    email: "alice@example.com".to_string(),
+                },
+            ],
         };
         let messages = vec![
             Message {
                 datetime: "2009-08-05T19:30:21".to_string(),
                 timezone_offset: None,
-                session_id: "Session_2009-08-05T19-30-21".to_string(),
-                sender_friendly_name: "Bob".to_string(),
-                receiver_friendly_name: "Alice".to_string(),
+                session_id: "Session_2009-08-05T19-30-21".into(),
+                sender_friendly_name: "Bob".into(),
+                receiver_friendly_name: "Alice".into(),
                 data: vec![
                     Data::Text(Text {
                         style: "font-family:\"Courier New\";color:#004000;".to_string(),
@@ -275,9 +427,9 @@ mod tests {
             Message {
                 datetime: "2009-08-05T19:30".to_string(),
                 timezone_offset: None,
-                session_id: "Session_2009-08-05T19-30-21".to_string(),
-                sender_friendly_name: "Alice".to_string(),
-                receiver_friendly_name: "Bob".to_string(),
+                session_id: "Session_2009-08-05T19-30-21".into(),
+                sender_friendly_name: "Alice".into(),
+                receiver_friendly_name: "Bob".into(),
                 data: vec![
                     Data::Text(Text {
                         style: "font-family:\"Segoe UI\";".to_string(),
@@ -296,9 +448,9 @@ mod tests {
             Message {
                 datetime: "2009-08-05T19:31".to_string(),
                 timezone_offset: None,
-                session_id: "Session_2009-08-05T19-30-21".to_string(),
-                sender_friendly_name: "Bob".to_string(),
-                receiver_friendly_name: "Alice".to_string(),
+                session_id: "Session_2009-08-05T19-30-21".into(),
+                sender_friendly_name: "Bob".into(),
+                receiver_friendly_name: "Alice".into(),
                 data: vec![
                     Data::Text(Text {
                         style: "font-family:\"Courier New\";color:#004000;".to_string(),
@@ -313,9 +465,9 @@ mod tests {
             Message {
                 datetime: "2009-08-05T19:31".to_string(),
                 timezone_offset: None,
-                session_id: "Session_2009-08-05T19-30-21".to_string(),
-                sender_friendly_name: "Alice".to_string(),
-                receiver_friendly_name: "Bob".to_string(),
+                session_id: "Session_2009-08-05T19-30-21".into(),
+                sender_friendly_name: "Alice".into(),
+                receiver_friendly_name: "Bob".into(),
                 data: vec![Data::Text(Text {
                     style: "font-family:\"Segoe UI\";".to_string(),
                     content: "He didn't!".to_string(),
@@ -324,9 +476,9 @@ mod tests {
             Message {
                 datetime: "2009-08-05T19:35".to_string(),
                 timezone_offset: None,
-                session_id: "Session_2009-08-05T19-30-21".to_string(),
-                sender_friendly_name: "Bob".to_string(),
-                receiver_friendly_name: "Alice".to_string(),
+                session_id: "Session_2009-08-05T19-30-21".into(),
+                sender_friendly_name: "Bob".into(),
+                receiver_friendly_name: "Alice".into(),
                 data: vec![
                     Data::Image(Image {
                         src: "./Images/MsgPlus_Img0663.png".to_string(),
@@ -342,9 +494,9 @@ mod tests {
             Message {
                 datetime: "2009-08-05T19:44".to_string(),
                 timezone_offset: None,
-                session_id: "Session_2009-08-05T19-30-21".to_string(),
-                sender_friendly_name: "".to_string(),
-                receiver_friendly_name: "".to_string(),
+                session_id: "Session_2009-08-05T19-30-21".into(),
+                sender_friendly_name: "".into(),
+                receiver_friendly_name: "".into(),
                 data: vec![Data::System("Alice is now offline".to_string())],
             },
         ];
@@ -357,4 +509,27 @@ mod tests {
         assert!(parser.next().is_none());
         assert_eq!(parser.details(), Some(&details));
     }
+
+    #[test]
+    fn keep_unknown_elements() {
+        let path = "test/alice@example.com.html";
+        let mut parser = MessengerPlusParser::new(path).unwrap();
+        parser.set_raw(true);
+        let raw: Vec<Message> = parser.flatten().collect();
+        let line_break = Data::Unknown(UnknownElement {
+            name: "br".to_string(),
+            ..UnknownElement::default()
+        });
+        assert_eq!(raw[0].data[1], line_break);
+
+        let messages: Vec<Message> = MessengerPlusParser::new(path).unwrap().flatten().collect();
+        let known: Vec<Message> = raw
+            .into_iter()
+            .map(|mut message| {
+                message.data.retain(|data| !matches!(data, Data::Unknown(_)));
+                message
+            })
+            .collect();
+        assert_eq!(known, messages);
+    }
 }
@@ -0,0 +1,461 @@
+use crate::messenger::{common, Data, Emoticon, Image, Message, Text};
+use chrono::{Duration, NaiveDateTime};
+use std::error::Error;
+use std::fs;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+const HEADER: &str = r#"<?xml version="1.0" encoding="UTF-16LE"?>
+<!DOCTYPE html PUBLIC "-//W3C//DTD XHTML 1.0 Transitional//EN" "http://www.w3.org/TR/xhtml1/DTD/xhtml1-transitional.dtd">
+<html xmlns="http://www.w3.org/1999/xhtml">
+<head>
+	<title>Messenger Plus! Conversation History</title>
+	<meta http-equiv="Content-Type" content="text/html; charset=utf-16"/>
+	<style type="text/css">
+	body {background-color: white; padding:0; margin:10px 10px 20px 10px; color:black; font-size: 0.8em;font-family: "MS Shell Dlg", sans-serif;}
+	img {vertical-align: text-bottom;}
+	h1,h2 {font-family: "Segoe UI",Tahoma, Verdana, sans-serif; margin:0; padding:0;}
+	h1 {color:#3aba07; letter-spacing:-1px; font-weight:normal;font-size: 1.8em; margin:0;padding:0;}
+	h2 {color:#1987cc; font-size: 1.2em; margin:10px 0 2px 0;padding:3px 0;border-top:1px solid #dcf7fd;}
+	.mplsession	{padding: 0 0 20px 0;}
+	.mplsession	img {vertical-align:top;}
+	.mplsession ul {list-style-type:none;margin:0;padding:0;border-top: 1px solid #6ed6f3;border-bottom:4px solid #f7feef;font-family: "Segoe UI",Tahoma, Verdana, sans-serif;font-size:0.95em;color:#333333;}
+	.mplsession ul li {padding:0 7px 2px 7px;background:#dcf7fd;border-top:2px solid #edfbfe;border-bottom:1px solid #6ed6f3;}
+	.mplsession ul li.in {background:#edfbfe;border-top:2px solid #fff;}
+	.mplsession table{font-size: 1em;margin:5px 0 10px 0;padding:0;width:100%;}
+	.mplsession table th {text-align:left;font-weight:bold;padding:1px 2px 3px 6px;padding-right:1em;vertical-align:top;white-space:nowrap;}
+	.mplsession table th span.time {color:#949494;font-weight:normal;}
+	.mplsession table td {padding:1px 2px;width:100%;vertical-align:top;}
+	.mplsession table tr.msgplus td,
+	.mplsession table tr.msgplus th{background:#ffffe6;padding-top:2px;padding-bottom:2px;border:1px dashed #e9e9e9;}
+	</style>
+	<link rel="stylesheet" type="text/css" href="custom.css" media="all" />
+</head>
+<body>
+	<h1>Messenger Plus! Conversation History</h1>
+"#;
+
+const FOOTER: &str = "</body>\n</html>\n";
+
+/// Writes messages as a Messenger Plus! HTML archive, readable by `MessengerPlusParser`.
+/// The images are saved in an `Images` folder, next to the archive.
+pub struct MessengerPlusWriter<'a> {
+    path: &'a Path,
+    images: usize,
+}
+
+impl<'a> MessengerPlusWriter<'a> {
+    pub fn new(path: &'a str) -> Self {
+        MessengerPlusWriter {
+            path: Path::new(path),
+            images: 0,
+        }
+    }
+
+    /// Writes the whole archive. Consecutive messages sharing the same `session_id` are grouped in
+    /// a session; the first sender of each session is considered as the owner of the archive.
+    pub fn write<I>(mut self, messages: I) -> Result<(), Box<dyn Error>>
+    where
+        I: IntoIterator<Item = Message>,
+    {
+        let mut html = String::from(HEADER);
+        let mut session: Vec<Message> = vec![];
+        for message in messages {
+            if session.first().is_some_and(|first| first.session_id != message.session_id) {
+                self.write_session(&mut html, &session)?;
+                session.clear();
+            }
+            session.push(message);
+        }
+        if !session.is_empty() {
+            self.write_session(&mut html, &session)?;
+        }
+        html.push_str(FOOTER);
+
+        let mut file = File::create(self.path)?;
+        let mut buffer = vec![0xFF, 0xFE];
+        for unit in html.replace('\n', "\r\n").encode_utf16() {
+            buffer.extend_from_slice(&unit.to_le_bytes());
+        }
+        file.write_all(&buffer)?;
+        Ok(())
+    }
+
+    fn write_session(&mut self, html: &mut String, messages: &[Message]) -> Result<(), Box<dyn Error>> {
+        let first = &messages[0];
+        let date = Self::local_datetime(first).unwrap_or_default();
+        let id = match NaiveDateTime::parse_from_str(&first.session_id, "Session_%Y-%m-%dT%H-%M-%S") {
+            Ok(_) => first.session_id.to_string(),
+            Err(_) => date.format("Session_%Y-%m-%dT%H-%M-%S").to_string(),
+        };
+        let speaker = messages
+            .iter()
+            .find(|message| !message.sender_friendly_name.is_empty())
+            .unwrap_or(first);
+        let owner = &speaker.sender_friendly_name;
+
+        html.push_str(&format!("\t<div class=\"mplsession\" id=\"{}\">\n", id));
+        html.push_str(&format!("\t<h2>Session start : {}</h2>\n", date.format("%A %B %-d, %Y")));
+        html.push_str("\t<ul>\n");
+        html.push_str(&format!("\t\t<li class=\"in\">{}</li>\n", html_escape::encode_text(owner)));
+        html.push_str(&format!(
+            "\t\t<li>{}</li>\n",
+            html_escape::encode_text(&speaker.receiver_friendly_name)
+        ));
+        html.push_str("\t</ul>\n\t<table cellspacing=\"0\">\n\t<tbody>\n");
+        for message in messages {
+            self.write_message(html, message)?;
+        }
+        html.push_str("\t</tbody>\n\t</table>\n\t</div>\n");
+        Ok(())
+    }
+
+    fn write_message(&mut self, html: &mut String, message: &Message) -> Result<(), Box<dyn Error>> {
+        let time = Self::local_datetime(message)
+            .unwrap_or_default()
+            .format("(%H:%M)");
+        let system: Vec<&str> = message
+            .data
+            .iter()
+            .filter_map(|d| match d {
+                Data::System(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        if !system.is_empty() {
+            html.push_str(&format!(
+                "\t\t<tr class=\"msgplus\"><th><span class=\"time\">{}</span></th><td>{}</td></tr>\n",
+                time,
+                html_escape::encode_text(&system.join(" "))
+            ));
+            return Ok(());
+        }
+
+        let style = message
+            .data
+            .iter()
+            .find_map(|d| match d {
+                Data::Text(Text { style, .. }) => Some(style.as_str()),
+                _ => None,
+            })
+            .unwrap_or_default();
+        let mut parts: Vec<String> = vec![];
+        // the emoticons are written on the same line as the texts around them
+        let mut inline = false;
+        for data in &message.data {
+            match data {
+                Data::Text(text) => {
+                    // the parts with another style than the first one are written in their own span
+                    let mut lines = text.content.lines().filter(|line| !line.is_empty()).map(|line| {
+                        let line = html_escape::encode_text(line);
+                        if text.style == style {
+                            line.to_string()
+                        } else {
+                            format!(
+                                "<span style=\"{}\">{}</span>",
+                                html_escape::encode_double_quoted_attribute(&text.style),
+                                line
+                            )
+                        }
+                    });
+                    if let (true, Some(last)) = (inline, parts.last_mut()) {
+                        last.push_str(&lines.next().unwrap_or_default());
+                    }
+                    parts.extend(lines);
+                    inline = false;
+                }
+                Data::Image(image) => {
+                    parts.push(self.write_image(image)?);
+                    inline = false;
+                }
+                Data::Emoticon(Emoticon { image: Some(image), .. }) => {
+                    parts.push(self.write_image(image)?);
+                    inline = false;
+                }
+                Data::Emoticon(emoticon) => {
+                    let shortcut = html_escape::encode_text(&emoticon.shortcut);
+                    match parts.last_mut() {
+                        Some(last) if !last.starts_with("<img") => last.push_str(&shortcut),
+                        _ => parts.push(shortcut.to_string()),
+                    }
+                    inline = true;
+                }
+                // the elements of the archives are written back only in their own format
+                Data::System(_) | Data::Unknown(_) => {}
+            }
+        }
+        html.push_str(&format!(
+            "\t\t<tr><th><span class=\"time\">{}</span> {} :</th><td style=\"{}\">{}</td></tr>\n",
+            time,
+            html_escape::encode_text(&message.sender_friendly_name),
+            html_escape::encode_double_quoted_attribute(style),
+            parts.join("<br/>")
+        ));
+        Ok(())
+    }
+
+    /// Saves the image content in the `Images` folder and returns the matching HTML tag. The
+    /// images without content whose file is not already in this folder (e.g. removed by
+    /// `Anonymizer`, or read from an archive in memory) are written as their alternative text, so
+    /// that the archive can be read again.
+    fn write_image(&mut self, image: &Image) -> Result<String, Box<dyn Error>> {
+        let directory = self
+            .path
+            .parent()
+            .ok_or("The file must be somewhere in a directory")?
+            .join("Images");
+        // the sources may be Windows paths
+        let source_name = image.src.rsplit(['/', '\\']).next().filter(|name| !name.is_empty());
+        let name = match source_name {
+            _ if !image.content.is_empty() => {
+                fs::create_dir_all(&directory)?;
+                self.save_image(&directory, source_name, &image.content)?
+            }
+            Some(name) if directory.join(name).is_file() => name.to_string(),
+            _ => return Ok(html_escape::encode_text(&image.alt).to_string()),
+        };
+        Ok(format!(
+            "<img src=\"./Images/{}\" alt=\"{}\"/>",
+            html_escape::encode_double_quoted_attribute(&name),
+            html_escape::encode_double_quoted_attribute(&image.alt)
+        ))
+    }
+
+    /// Saves an image in `directory`, named after its source or `MsgPlus_Img0001.png` (with the
+    /// extension of its format). The images of other archives written to the same folder are
+    /// kept: a file with the same name is reused only if it has the same content, otherwise
+    /// another name is chosen. Returns the name of the file.
+    fn save_image(&mut self, directory: &Path, source_name: Option<&str>, content: &[u8]) -> io::Result<String> {
+        let source_name = source_name.map(Path::new);
+        let stem = source_name.and_then(Path::file_stem).and_then(|stem| stem.to_str());
+        let extension = source_name
+            .and_then(Path::extension)
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_else(|| image_extension(content));
+        let mut copy = 1;
+        loop {
+            let name = match stem {
+                Some(stem) if copy == 1 => format!("{}.{}", stem, extension),
+                Some(stem) => format!("{}_{}.{}", stem, copy, extension),
+                None => {
+                    self.images += 1;
+                    format!("MsgPlus_Img{:04}.{}", self.images, extension)
+                }
+            };
+            let path = directory.join(&name);
+            match fs::read(&path) {
+                Ok(existing) if existing == content => return Ok(name),
+                Ok(_) => copy += 1,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    fs::write(&path, content)?;
+                    return Ok(name);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Messenger Plus! archives are saved in local time.
+    fn local_datetime(message: &Message) -> Option<NaiveDateTime> {
+        common::parse_datetime(&message.datetime)
+            .map(|datetime| datetime + Duration::minutes(message.timezone_offset.unwrap_or(0)))
+    }
+}
+
+/// The extension of the files of an image, from the signature of its format.
+fn image_extension(content: &[u8]) -> &'static str {
+    if content.starts_with(b"GIF8") {
+        "gif"
+    } else if content.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        "jpg"
+    } else if content.starts_with(b"BM") {
+        "bmp"
+    } else {
+        "png"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messenger::messenger_plus_parser::MessengerPlusParser;
+    use crate::messenger::xml_parser::XmlParser;
+    use std::path::PathBuf;
+
+    /// Creates an empty temporary folder, unique to the test and to the process.
+    fn temp_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn round_trip_sample_file() {
+        let directory = temp_directory("messenger_plus_round_trip");
+        let path = directory.join("alice@example.com.html");
+        let path = path.to_str().unwrap();
+
+        let messages: Vec<Message> = MessengerPlusParser::new("test/alice@example.com.html")
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        MessengerPlusWriter::new(path).write(messages.clone()).unwrap();
+
+        let mut original = MessengerPlusParser::new("test/alice@example.com.html").unwrap();
+        let mut written = MessengerPlusParser::new(path).unwrap();
+        for message in original.by_ref() {
+            assert_eq!(written.next().unwrap().unwrap(), message.unwrap());
+        }
+        assert!(written.next().is_none());
+
+        // the images of the recognized emoticons are written back
+        let recognized: Vec<Message> = messages.into_iter().map(crate::emoticon::recognize).collect();
+        MessengerPlusWriter::new(path).write(recognized.clone()).unwrap();
+        let written: Vec<Message> = MessengerPlusParser::new(path)
+            .unwrap()
+            .map(|message| crate::emoticon::recognize(message.unwrap()))
+            .collect();
+        assert_eq!(written, recognized);
+    }
+
+    #[test]
+    fn convert_xml_file() {
+        let directory = temp_directory("messenger_plus_convert");
+        let path = directory.join("alice1234.html");
+        let path = path.to_str().unwrap();
+
+        let messages: Vec<Message> = XmlParser::new("test/alice1234.xml")
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        MessengerPlusWriter::new(path).write(messages).unwrap();
+
+        let mut written = MessengerPlusParser::new(path).unwrap();
+        let message = written.next().unwrap().unwrap();
+        assert_eq!(&*message.session_id, "Session_2009-04-06T21-40-41");
+        assert_eq!(message.datetime, "2009-04-06T21:40:41");
+        assert_eq!(&*message.sender_friendly_name, "Alice");
+        let message = written.next().unwrap().unwrap();
+        assert_eq!(message.datetime, "2009-04-06T22:22");
+        assert_eq!(&*message.sender_friendly_name, "Bob");
+        assert_eq!(message.data.len(), 2);
+        assert!(written.next().is_none());
+    }
+
+    #[test]
+    fn write_styled_parts_and_unresolved_images() {
+        let directory = temp_directory("messenger_plus_styled_parts");
+        let path = directory.join("alice@example.com.html");
+        let path = path.to_str().unwrap();
+        let text = |style: &str, content: &str| {
+            Data::Text(Text {
+                style: style.to_string(),
+                content: content.to_string(),
+            })
+        };
+        let message = Message {
+            session_id: "Session_2009-08-05T19-30-21".into(),
+            datetime: "2009-08-05T19:30:21".to_string(),
+            sender_friendly_name: "Alice".into(),
+            receiver_friendly_name: "Bob".into(),
+            data: vec![
+                text("font-family:\"Segoe UI\";", "Hello "),
+                text("color:#ff0000;", "Bob"),
+                Data::Image(Image {
+                    src: "C:\\Pictures\\holidays.jpg".to_string(),
+                    alt: "holidays".to_string(),
+                    ..Image::default()
+                }),
+            ],
+            ..Message::default()
+        };
+        MessengerPlusWriter::new(path).write(vec![message]).unwrap();
+
+        // the image cannot be found, so only its alternative text is written
+        let written = MessengerPlusParser::new(path).unwrap().next().unwrap().unwrap();
+        assert_eq!(
+            written.data,
+            vec![
+                text("font-family:\"Segoe UI\";", "Hello "),
+                text("color:#ff0000;", "Bob"),
+                text("font-family:\"Segoe UI\";", "holidays"),
+            ]
+        );
+        assert!(!directory.join("Images").exists());
+    }
+
+    #[test]
+    fn keep_the_images_of_other_archives() {
+        let directory = temp_directory("messenger_plus_shared_images");
+        let image = |src: &str, content: &[u8]| {
+            Data::Image(Image {
+                src: src.to_string(),
+                alt: "image".to_string(),
+                content: content.to_vec(),
+            })
+        };
+        let message = |data: Vec<Data>| Message {
+            session_id: "Session_2009-08-05T19-30-21".into(),
+            datetime: "2009-08-05T19:30:21".to_string(),
+            sender_friendly_name: "Alice".into(),
+            receiver_friendly_name: "Bob".into(),
+            data,
+            ..Message::default()
+        };
+        let archives = [
+            (
+                "alice@example.com.html",
+                vec![image("", b"\x89PNG first"), image("./Images/photo.jpg", b"\xFF\xD8\xFF first")],
+            ),
+            (
+                "bob@example.com.html",
+                vec![
+                    image("", b"\x89PNG second"),
+                    image("C:\\Pictures\\photo.jpg", b"\xFF\xD8\xFF second"),
+                    image("", b"GIF89a"),
+                    // the file of the same image is reused
+                    image("./Images/photo.jpg", b"\xFF\xD8\xFF first"),
+                ],
+            ),
+        ];
+        for (name, data) in &archives {
+            let path = directory.join(name);
+            MessengerPlusWriter::new(path.to_str().unwrap()).write(vec![message(data.clone())]).unwrap();
+        }
+
+        for (name, data) in archives {
+            let written = MessengerPlusParser::new(directory.join(name).to_str().unwrap())
+                .unwrap()
+                .next()
+                .unwrap()
+                .unwrap();
+            let contents: Vec<&[u8]> = written
+                .data
+                .iter()
+                .filter_map(|data| match data {
+                    Data::Image(image) => Some(image.content.as_slice()),
+                    _ => None,
+                })
+                .collect();
+            let expected: Vec<&[u8]> = data
+                .iter()
+                .filter_map(|data| match data {
+                    Data::Image(image) => Some(image.content.as_slice()),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(contents, expected);
+        }
+        let mut files: Vec<String> = fs::read_dir(directory.join("Images"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec!["MsgPlus_Img0001.png", "MsgPlus_Img0002.png", "MsgPlus_Img0003.gif", "photo.jpg", "photo_2.jpg"]
+        );
+    }
+}
@@ -0,0 +1,386 @@
+use crate::messenger::common::{self, Encoding};
+use crate::messenger::source::ArchiveSource;
+use crate::messenger::FileType;
+use chrono::NaiveDateTime;
+use std::error::Error;
+use std::fs;
+
+/// The format of the dates saved in the index file.
+const DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+/// The start of a session in an archive.
+#[derive(PartialEq, Debug, Clone)]
+pub struct IndexedSession {
+    pub id: String,
+    /// The date of the first message (WLM, in UTC) or of the session (Messenger Plus!, local).
+    pub date: Option<NaiveDateTime>,
+    /// The position of the session in the archive file, in bytes.
+    pub offset: u64,
+}
+
+/// The positions of the sessions of an archive, to resume the parsing at a given session or date
+/// without reading the previous ones (see `Parser::seek_session` and `Parser::seek_date`). Only
+/// the beginning of the archive, the sessions read and its end are then read from the file.
+///
+/// The index can be saved next to the archive and loaded back, it is checked against the size and
+/// the modification date of the archive before seeking.
+#[derive(PartialEq, Debug, Clone)]
+pub struct SessionIndex {
+    file_type: FileType,
+    encoding: Encoding,
+    /// The size of the archive, in bytes.
+    size: u64,
+    /// The modification date of the archive, in milliseconds since the epoch, if known.
+    modified: Option<u64>,
+    /// The position of the root element (`Log` or `html`).
+    root: u64,
+    /// The position of the closing tag of the element containing the sessions (`Log` or `body`).
+    end: u64,
+    sessions: Vec<IndexedSession>,
+}
+
+impl SessionIndex {
+    /// Indexes the sessions of the archive at `path`. Its type is given by its extension, like
+    /// `Parser::new`.
+    pub fn build(path: &str) -> Result<Self, Box<dyn Error>> {
+        Self::from_source(&ArchiveSource::open(path)?, FileType::from_path(path))
+    }
+
+    /// Indexes the sessions of an archive of the given type, read from `source`.
+    pub fn from_source(source: &ArchiveSource, file_type: FileType) -> Result<Self, Box<dyn Error>> {
+        let (_, modified) = source.stamp()?;
+        let mut index = Self::from_content(&source.read()?, file_type);
+        index.modified = modified.and_then(common::milliseconds);
+        Ok(index)
+    }
+
+    /// Indexes the sessions of an archive already read. Its modification date is unknown, only its
+    /// size is checked before seeking.
+    pub fn from_content(content: &[u8], file_type: FileType) -> Self {
+        let text = common::decode_text(content);
+        let root = text
+            .match_indices('<')
+            .map(|(position, _)| position)
+            .find(|position| !text[position + 1..].starts_with(['?', '!']))
+            .unwrap_or_default();
+        let closing = match file_type {
+            FileType::XML => "</Log",
+            FileType::MessengerPlus => "</body",
+        };
+        let end = text.rfind(closing).unwrap_or(text.len());
+        let mut sessions: Vec<(usize, IndexedSession)> = vec![];
+        match file_type {
+            FileType::XML => {
+                for (position, tag) in common::start_tags(&text, "Message") {
+                    let id = common::tag_attribute(tag, "SessionID").unwrap_or("0");
+                    if sessions.last().is_none_or(|(_, session)| session.id != id) {
+                        sessions.push((
+                            position,
+                            IndexedSession {
+                                id: id.to_string(),
+                                date: common::tag_attribute(tag, "DateTime").and_then(common::parse_datetime),
+                                offset: 0,
+                            },
+                        ));
+                    }
+                }
+            }
+            FileType::MessengerPlus => {
+                for (position, tag) in common::start_tags(&text, "div") {
+                    if common::tag_attribute(tag, "class") != Some("mplsession") {
+                        continue;
+                    }
+                    if let Some(id) = common::tag_attribute(tag, "id") {
+                        sessions.push((
+                            position,
+                            IndexedSession {
+                                id: id.to_string(),
+                                date: NaiveDateTime::parse_from_str(id, "Session_%Y-%m-%dT%H-%M-%S").ok(),
+                                offset: 0,
+                            },
+                        ));
+                    }
+                }
+            }
+        }
+        let encoding = Encoding::detect(content);
+        let positions: Vec<usize> = std::iter::once(root)
+            .chain(sessions.iter().map(|(position, _)| *position))
+            .chain(std::iter::once(end))
+            .collect();
+        let offsets = encoding.raw_offsets(content, &text, &positions);
+        SessionIndex {
+            file_type,
+            encoding,
+            size: content.len() as u64,
+            modified: None,
+            root: offsets[0],
+            end: offsets[offsets.len() - 1],
+            sessions: sessions
+                .into_iter()
+                .zip(&offsets[1..])
+                .map(|((_, session), offset)| IndexedSession { offset: *offset, ..session })
+                .collect(),
+        }
+    }
+
+    /// The sessions, in the order of the archive.
+    pub fn sessions(&self) -> &[IndexedSession] {
+        &self.sessions
+    }
+
+    /// The first session with the given `id`.
+    pub fn session(&self, id: &str) -> Option<&IndexedSession> {
+        self.sessions.iter().find(|session| session.id == id)
+    }
+
+    /// The session containing `datetime`: the last one started at or before it.
+    pub fn session_at(&self, datetime: NaiveDateTime) -> Option<&IndexedSession> {
+        self.sessions
+            .iter()
+            .filter(|session| session.date.is_some_and(|date| date <= datetime))
+            .max_by_key(|session| session.date)
+    }
+
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    /// The part of the archive to parse to resume at `session`: the beginning of the root element,
+    /// followed by the archive from the session.
+    pub fn resume(&self, source: &ArchiveSource, session: &IndexedSession) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.part(source, session.offset, self.end)
+    }
+
+    /// The part of the archive to parse to read only `session`: the beginning of the root element,
+    /// the session and the end of the archive.
+    pub fn session_content(&self, source: &ArchiveSource, session: &IndexedSession) -> Result<Vec<u8>, Box<dyn Error>> {
+        let (header, footer) = self.frame(source)?;
+        Ok([header, self.session_text(source, session)?, footer].concat().into_bytes())
+    }
+
+    /// The position of the session following `session`, or of the end of the sessions.
+    fn next_offset(&self, session: &IndexedSession) -> u64 {
+        self.sessions
+            .iter()
+            .map(|other| other.offset)
+            .find(|offset| *offset > session.offset)
+            .unwrap_or(self.end)
+    }
+
+    /// The archive with only the sessions between `start` and `end`, as UTF-8.
+    fn part(&self, source: &ArchiveSource, start: u64, end: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+        let (header, footer) = self.frame(source)?;
+        let sessions = self.read_text(source, start, end)?;
+        Ok([header, sessions, footer].concat().into_bytes())
+    }
+
+    /// The beginning of the root element until the first session and the end of the archive after
+    /// the sessions, to parse the sessions within. The archive is checked first.
+    pub(crate) fn frame(&self, source: &ArchiveSource) -> Result<(String, String), Box<dyn Error>> {
+        self.check(source)?;
+        let header_end = self.sessions.first().map_or(self.end, |first| first.offset);
+        Ok((self.read_text(source, self.root, header_end)?, self.read_text(source, self.end, self.size)?))
+    }
+
+    /// The text of `session` only, until the next session (see `frame`).
+    pub(crate) fn session_text(&self, source: &ArchiveSource, session: &IndexedSession) -> Result<String, Box<dyn Error>> {
+        self.read_text(source, session.offset, self.next_offset(session))
+    }
+
+    /// Checks the archive did not change since it was indexed.
+    fn check(&self, source: &ArchiveSource) -> Result<(), Box<dyn Error>> {
+        let (size, modified) = source.stamp()?;
+        let modified = modified.and_then(common::milliseconds);
+        if size != self.size || (self.modified.is_some() && modified.is_some() && modified != self.modified) {
+            return Err("The index does not match the archive, it must be built again".into());
+        }
+        Ok(())
+    }
+
+    /// Reads and decodes the bytes of the archive from `start` to `end`.
+    fn read_text(&self, source: &ArchiveSource, start: u64, end: u64) -> Result<String, Box<dyn Error>> {
+        if start > end || end > self.size {
+            return Err("The index does not match the archive, it must be built again".into());
+        }
+        Ok(self.encoding.decode(&source.read_range(start, end)?))
+    }
+
+    /// Checks `session` is the first one of the archive.
+    pub fn is_first(&self, session: &IndexedSession) -> bool {
+        self.sessions.first().is_some_and(|first| first.offset == session.offset)
+    }
+
+    /// Saves the index in a text file.
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let file_type = match self.file_type {
+            FileType::XML => "xml",
+            FileType::MessengerPlus => "mplus",
+        };
+        let encoding = match self.encoding {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+        };
+        let modified = self.modified.map_or("-".to_string(), |modified| modified.to_string());
+        let mut content = format!(
+            "archive\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            file_type, encoding, self.size, modified, self.root, self.end
+        );
+        for session in &self.sessions {
+            let date = session
+                .date
+                .map_or("-".to_string(), |date| date.format(DATE_FORMAT).to_string());
+            content.push_str(&format!("session\t{}\t{}\t{}\n", session.offset, date, session.id));
+        }
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// Loads an index previously saved with `save`.
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let content = fs::read_to_string(path)?;
+        let mut lines = content.lines();
+        let header = lines.next().unwrap_or_default();
+        let mut index = match header.split('\t').collect::<Vec<&str>>()[..] {
+            ["archive", file_type, encoding, size, modified, root, end] => SessionIndex {
+                file_type: match file_type {
+                    "xml" => FileType::XML,
+                    "mplus" => FileType::MessengerPlus,
+                    _ => return Err(format!("Invalid archive type: {}", file_type).into()),
+                },
+                encoding: match encoding {
+                    "utf-8" => Encoding::Utf8,
+                    "utf-16le" => Encoding::Utf16Le,
+                    "utf-16be" => Encoding::Utf16Be,
+                    _ => return Err(format!("Invalid archive encoding: {}", encoding).into()),
+                },
+                size: size.parse()?,
+                modified: match modified {
+                    "-" => None,
+                    modified => Some(modified.parse()?),
+                },
+                root: root.parse()?,
+                end: end.parse()?,
+                sessions: vec![],
+            },
+            _ => return Err(format!("Invalid index line: {}", header).into()),
+        };
+        for line in lines {
+            match line.splitn(4, '\t').collect::<Vec<&str>>()[..] {
+                ["session", offset, date, id] => index.sessions.push(IndexedSession {
+                    id: id.to_string(),
+                    date: NaiveDateTime::parse_from_str(date, DATE_FORMAT).ok(),
+                    offset: offset.parse()?,
+                }),
+                _ => return Err(format!("Invalid index line: {}", line).into()),
+            }
+        }
+        Ok(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Message, MessengerPlusWriter, Parser, XmlWriter};
+    use chrono::{Duration, NaiveDate};
+
+    /// Copies the messages of `path` in three sessions, a day apart.
+    fn three_sessions(path: &str) -> Vec<Message> {
+        let messages: Vec<Message> = Parser::new(path).unwrap().flatten().collect();
+        (0..3)
+            .flat_map(|session| {
+                messages.iter().cloned().map(move |mut message| {
+                    let datetime = common::parse_datetime(&message.datetime).unwrap() + Duration::days(session);
+                    let format = if message.datetime.len() > 16 { "%Y-%m-%dT%H:%M:%S" } else { "%Y-%m-%dT%H:%M" };
+                    message.datetime = datetime.format(format).to_string();
+                    message.session_id = (session + 1).to_string().into();
+                    message
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn seek_in_xml_archive() {
+        let path = std::env::temp_dir().join("seek_in_xml_archive.xml");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, XmlWriter::new(vec![]).write(three_sessions("test/alice1234.xml")).unwrap()).unwrap();
+        let messages: Vec<Message> = Parser::new(path).unwrap().flatten().collect();
+
+        let index = SessionIndex::build(path).unwrap();
+        let ids: Vec<&str> = index.sessions().iter().map(|session| session.id.as_str()).collect();
+        assert_eq!(ids, vec!["1", "2", "3"]);
+        let index_path = format!("{}.index", path);
+        index.save(&index_path).unwrap();
+        assert_eq!(SessionIndex::load(&index_path).unwrap(), index);
+
+        let mut parser = Parser::new(path).unwrap().session_index(SessionIndex::load(&index_path).unwrap());
+        parser.seek_session("2").unwrap();
+        assert_eq!(parser.flatten().collect::<Vec<_>>(), messages[2..]);
+
+        let mut parser = Parser::new(path).unwrap();
+        let date = NaiveDate::from_ymd_opt(2009, 4, 8).unwrap().and_hms_opt(20, 0, 0).unwrap();
+        parser.seek_date(date).unwrap();
+        assert_eq!(parser.flatten().collect::<Vec<_>>(), messages[4..]);
+        assert!(Parser::new(path).unwrap().seek_session("4").is_err());
+
+        let mut content = std::fs::read(path).unwrap();
+        content.extend_from_slice(b"\n");
+        std::fs::write(path, content).unwrap();
+        let mut parser = Parser::new(path).unwrap().session_index(index);
+        assert!(parser.seek_session("2").is_err());
+        std::fs::write(&index_path, "archive\txml\t1234\t0\t1200\n").unwrap();
+        assert!(SessionIndex::load(&index_path).is_err());
+    }
+
+    #[test]
+    fn index_messages_without_attributes() {
+        let content = "<Log><Message/><Message>1</Message><MessageX SessionID=\"9\"/><Message SessionID=\"2\">2</Message></Log>";
+        let index = SessionIndex::from_content(content.as_bytes(), FileType::XML);
+        let sessions: Vec<(&str, usize)> = index
+            .sessions()
+            .iter()
+            .map(|session| (session.id.as_str(), session.offset as usize))
+            .collect();
+        assert_eq!(sessions, vec![("0", content.find("<Message/>").unwrap()), ("2", content.rfind("<Message").unwrap())]);
+    }
+
+    #[test]
+    fn index_invalid_utf8() {
+        let content = b"<?xml version=\"1.0\"?><Log>\xE9<Message SessionID=\"1\"></Message>\
+            \xFF\xFE<Message SessionID=\"2\"></Message></Log>";
+        let index = SessionIndex::from_content(content, FileType::XML);
+        let offsets: Vec<u64> = index.sessions().iter().map(|session| session.offset).collect();
+        assert_eq!(offsets, vec![27, 62]);
+        assert_eq!(&content[62..76], b"<Message Sessi");
+    }
+
+    #[test]
+    fn seek_in_messenger_plus_archive() {
+        let directory = std::env::temp_dir().join("seek_in_messenger_plus_archive");
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("alice@example.com.html");
+        let path = path.to_str().unwrap();
+        MessengerPlusWriter::new(path)
+            .write(three_sessions("test/alice@example.com.html"))
+            .unwrap();
+        let messages: Vec<Message> = Parser::new(path).unwrap().flatten().collect();
+        let reversed: Vec<Message> = Parser::new(path).unwrap().reverse().flatten().collect();
+        assert_eq!(reversed, messages.iter().rev().cloned().collect::<Vec<_>>());
+
+        let index = SessionIndex::build(path).unwrap();
+        assert_eq!(index.sessions().len(), 3);
+        let start = ArchiveSource::open(path).unwrap().read_range(index.sessions()[1].offset, index.sessions()[1].offset + 8).unwrap();
+        assert_eq!(Encoding::Utf16Le.decode(&start), "<div");
+        let mut parser = Parser::new(path).unwrap();
+        parser.seek_session(&index.sessions()[1].id).unwrap();
+        let expected: Vec<Message> = messages
+            .into_iter()
+            .filter(|message| *message.session_id != *index.sessions()[0].id)
+            .collect();
+        assert_eq!(expected.len(), 12);
+        assert_eq!(parser.flatten().collect::<Vec<_>>(), expected);
+    }
+}
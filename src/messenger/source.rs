@@ -0,0 +1,540 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
+
+/// The kinds of backup files containing archives.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ContainerKind {
+    Zip,
+    Tar,
+    /// A tar file compressed with gzip (`.tar.gz` or `.tgz`).
+    TarGz,
+}
+
+impl ContainerKind {
+    /// Detects the kind of container from the name of the file.
+    pub fn detect(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        if name.ends_with(".zip") {
+            Some(ContainerKind::Zip)
+        } else if name.ends_with(".tar") {
+            Some(ContainerKind::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ContainerKind::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// Where an archive is read from: a file, an entry of a zip / tar container, a content already
+/// in memory or a stream. The entries are given as paths through the container, e.g.
+/// `backup.zip/History/alice1234.xml`, and are read in memory without being extracted.
+#[derive(PartialEq, Debug, Clone)]
+pub enum ArchiveSource {
+    File(PathBuf),
+    Entry {
+        container: PathBuf,
+        kind: ContainerKind,
+        /// The path of the archive inside the container, with `/` separators.
+        name: String,
+    },
+    /// An archive already read. It has no folder, so the files it references cannot be read: the
+    /// images of the Messenger Plus! archives keep an empty content.
+    Memory {
+        /// The name of the archive file.
+        name: String,
+        content: Arc<[u8]>,
+    },
+    /// An archive parsed while it is read from a stream (see `Parser::from_reader`). It can be read
+    /// only once, by its parser, and has no folder like `Memory`.
+    Stream {
+        /// The name of the archive file.
+        name: String,
+    },
+}
+
+impl ArchiveSource {
+    /// Finds the archive at `path`: a file, or an entry of a container if one of the parents of
+    /// `path` is a container file.
+    pub fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        let path = Path::new(path);
+        if !path.is_file() {
+            for container in path.ancestors().skip(1) {
+                if let Some(kind) = ContainerKind::detect(container).filter(|_| container.is_file()) {
+                    let name = path
+                        .strip_prefix(container)?
+                        .components()
+                        .map(|component| component.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/");
+                    return Ok(ArchiveSource::Entry {
+                        container: container.to_path_buf(),
+                        kind,
+                        name,
+                    });
+                }
+            }
+        }
+        Ok(ArchiveSource::File(path.to_path_buf()))
+    }
+
+    /// The name of the archive, without its extension.
+    pub fn file_stem(&self) -> String {
+        let stem = match self {
+            ArchiveSource::File(path) => path.file_stem(),
+            ArchiveSource::Entry { name, .. }
+            | ArchiveSource::Memory { name, .. }
+            | ArchiveSource::Stream { name } => Path::new(name).file_stem(),
+        };
+        stem.unwrap_or_default().to_str().unwrap_or_default().to_string()
+    }
+
+    /// Opens the archive for reading.
+    pub fn reader(&self) -> Result<Box<dyn Read + Send>, Box<dyn Error>> {
+        match self {
+            ArchiveSource::File(path) => Ok(Box::new(File::open(path)?)),
+            ArchiveSource::Entry { .. } => Ok(Box::new(Cursor::new(self.read()?))),
+            ArchiveSource::Memory { content, .. } => Ok(Box::new(Cursor::new(content.clone()))),
+            ArchiveSource::Stream { name } => Err(read_once(name)),
+        }
+    }
+
+    /// Reads the whole archive.
+    pub fn read(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        match self {
+            ArchiveSource::File(path) => Ok(fs::read(path)?),
+            ArchiveSource::Entry { container, kind, name } => read_entry(container, *kind, name, 0, u64::MAX),
+            ArchiveSource::Memory { content, .. } => Ok(content.to_vec()),
+            ArchiveSource::Stream { name } => Err(read_once(name)),
+        }
+    }
+
+    /// Reads the bytes of the archive from `start` to `end` (excluded). Only this part is read,
+    /// except in the compressed containers: the archive is decompressed whole once, and kept for
+    /// the next reads of this archive (e.g. by a parser reading it backward).
+    pub fn read_range(&self, start: u64, end: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+        let length = end.saturating_sub(start);
+        match self {
+            ArchiveSource::File(path) => {
+                let mut file = File::open(path)?;
+                file.seek(SeekFrom::Start(start))?;
+                let mut buffer = Vec::with_capacity(length as usize);
+                file.take(length).read_to_end(&mut buffer)?;
+                Ok(buffer)
+            }
+            ArchiveSource::Entry { container, kind: ContainerKind::TarGz, name } => {
+                Ok(slice(&decompressed_entry(container, name)?, start, end))
+            }
+            ArchiveSource::Entry { container, kind, name } => read_entry(container, *kind, name, start, end),
+            ArchiveSource::Memory { content, .. } => Ok(slice(content, start, end)),
+            ArchiveSource::Stream { name } => Err(read_once(name)),
+        }
+    }
+
+    /// The size of the archive, in bytes, and the date it was last modified (the one of the
+    /// container for the entries), to check whether it changed.
+    pub fn stamp(&self) -> Result<(u64, Option<SystemTime>), Box<dyn Error>> {
+        match self {
+            ArchiveSource::File(path) => {
+                let metadata = fs::metadata(path)?;
+                Ok((metadata.len(), metadata.modified().ok()))
+            }
+            ArchiveSource::Entry { container, kind, name } => {
+                let modified = fs::metadata(container)?.modified().ok();
+                Ok((entry(container, *kind, name)?.size, modified))
+            }
+            ArchiveSource::Memory { content, .. } => Ok((content.len() as u64, None)),
+            ArchiveSource::Stream { name } => Err(read_once(name)),
+        }
+    }
+
+    /// Reads a file referenced by the archive (e.g. the `./Images/...` of Messenger Plus!),
+    /// relative to the folder containing the archive.
+    pub fn resource(&self, relative: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        match self {
+            ArchiveSource::File(path) => {
+                let directory = path.parent().ok_or("The file must be somewhere in a directory")?;
+                Ok(fs::read(directory.join(relative))?)
+            }
+            ArchiveSource::Entry { container, kind, name } => {
+                let directory = name.rsplit_once('/').map_or("", |(directory, _)| directory);
+                read_entry(container, *kind, &join(directory, relative), 0, u64::MAX)
+            }
+            ArchiveSource::Memory { name, .. } | ArchiveSource::Stream { name } => {
+                Err(format!("{} is not in a directory, {} cannot be read", name, relative).into())
+            }
+        }
+    }
+}
+
+/// The bytes of `content` from `start` to `end` (excluded), within its bounds.
+fn slice(content: &[u8], start: u64, end: u64) -> Vec<u8> {
+    let start = (start as usize).min(content.len());
+    let end = (end as usize).clamp(start, content.len());
+    content[start..end].to_vec()
+}
+
+fn read_once(name: &str) -> Box<dyn Error> {
+    format!("{} is read from a stream, it cannot be read again", name).into()
+}
+
+/// Where the files of a container are, to read them without going through the container again.
+struct ContainerIndex {
+    /// The size and the modification date of the container, to check it did not change.
+    stamp: (u64, Option<SystemTime>),
+    /// The files, by path with `/` separators.
+    entries: BTreeMap<String, IndexedEntry>,
+    /// The last file read by parts from a compressed container, decompressed once.
+    decompressed: Mutex<Option<(String, Arc<[u8]>)>>,
+}
+
+#[derive(Clone, Copy)]
+struct IndexedEntry {
+    /// The number of the file in a zip container, or the position of its content in a tar one
+    /// (once decompressed).
+    #[cfg_attr(not(any(feature = "zip", feature = "tar")), allow(dead_code))]
+    position: u64,
+    size: u64,
+}
+
+/// The maximum number of containers indexed at once.
+const MAX_INDEXES: usize = 8;
+
+/// The indexes of the last containers read, from the least recently used. Each is built when its
+/// container is first read, and built again when the container changed.
+static INDEXES: Mutex<Vec<(PathBuf, Arc<ContainerIndex>)>> = Mutex::new(Vec::new());
+
+/// The first bytes of some files of a container, by path with `/` separators.
+pub type Headers = Vec<(String, Vec<u8>)>;
+
+/// Lists the files of a container and reads the first `size` bytes of the ones `wanted`, going
+/// through the container once.
+pub fn read_headers<F: Fn(&str) -> bool>(
+    container: &Path,
+    kind: ContainerKind,
+    size: u64,
+    wanted: F,
+) -> Result<Headers, Box<dyn Error>> {
+    Ok(build_index(container, kind, size, &wanted)?.1)
+}
+
+/// The index of `container`, built again if the container changed.
+fn index(container: &Path, kind: ContainerKind) -> Result<Arc<ContainerIndex>, Box<dyn Error>> {
+    let stamp = container_stamp(container)?;
+    let mut indexes = INDEXES.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(position) = indexes
+        .iter()
+        .position(|(path, index)| path == container && index.stamp == stamp)
+    {
+        let used = indexes.remove(position);
+        indexes.push(used.clone());
+        return Ok(used.1);
+    }
+    drop(indexes);
+    Ok(build_index(container, kind, 0, &|_| false)?.0)
+}
+
+/// Keeps the index of `container`, in place of its previous one, forgetting the least recently
+/// used ones beyond `MAX_INDEXES`.
+fn keep_index(container: &Path, index: Arc<ContainerIndex>) {
+    let mut indexes = INDEXES.lock().unwrap_or_else(PoisonError::into_inner);
+    indexes.retain(|(path, _)| path != container);
+    indexes.push((container.to_path_buf(), index));
+    let excess = indexes.len().saturating_sub(MAX_INDEXES);
+    indexes.drain(..excess);
+}
+
+/// Indexes `container`, reading the first `size` bytes of the files `wanted` on the way.
+fn build_index(
+    container: &Path,
+    kind: ContainerKind,
+    size: u64,
+    wanted: &dyn Fn(&str) -> bool,
+) -> Result<(Arc<ContainerIndex>, Headers), Box<dyn Error>> {
+    let stamp = container_stamp(container)?;
+    let mut entries = BTreeMap::new();
+    let mut headers = vec![];
+    let mut add = |name: String, entry: IndexedEntry, reader: &mut dyn Read| -> io::Result<()> {
+        if wanted(&name) {
+            headers.push((name.clone(), read_part(reader, 0, size)?));
+        }
+        entries.insert(name, entry);
+        Ok(())
+    };
+    match kind {
+        ContainerKind::Zip => index_zip(container, &mut add)?,
+        ContainerKind::Tar | ContainerKind::TarGz => index_tar(container, kind, &mut add)?,
+    }
+    let index = Arc::new(ContainerIndex {
+        stamp,
+        entries,
+        decompressed: Mutex::new(None),
+    });
+    keep_index(container, index.clone());
+    Ok((index, headers))
+}
+
+fn container_stamp(container: &Path) -> Result<(u64, Option<SystemTime>), Box<dyn Error>> {
+    let metadata = fs::metadata(container)?;
+    Ok((metadata.len(), metadata.modified().ok()))
+}
+
+/// Calls `add` with each file of a container, its location and a reader of its content.
+type AddEntry<'a> = dyn FnMut(String, IndexedEntry, &mut dyn Read) -> io::Result<()> + 'a;
+
+/// Reads the bytes of the file `name` of a container from `start` to `end` (excluded).
+fn read_entry(container: &Path, kind: ContainerKind, name: &str, start: u64, end: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+    let entry = entry(container, kind, name)?;
+    let end = end.min(entry.size);
+    let start = start.min(end);
+    match kind {
+        ContainerKind::Zip => read_zip(container, entry, start, end),
+        ContainerKind::Tar | ContainerKind::TarGz => read_tar(container, kind, entry, start, end),
+    }
+}
+
+/// The whole content of the file `name` of a compressed tar container, decompressed only if it is
+/// not the last file read this way.
+fn decompressed_entry(container: &Path, name: &str) -> Result<Arc<[u8]>, Box<dyn Error>> {
+    let index = index(container, ContainerKind::TarGz)?;
+    let mut decompressed = index.decompressed.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some((_, content)) = decompressed.as_ref().filter(|(last, _)| last == name) {
+        return Ok(content.clone());
+    }
+    let content: Arc<[u8]> = read_entry(container, ContainerKind::TarGz, name, 0, u64::MAX)?.into();
+    *decompressed = Some((name.to_string(), content.clone()));
+    Ok(content)
+}
+
+fn entry(container: &Path, kind: ContainerKind, name: &str) -> Result<IndexedEntry, Box<dyn Error>> {
+    index(container, kind)?
+        .entries
+        .get(name)
+        .copied()
+        .ok_or_else(|| format!("{} not found in {}", name, container.display()).into())
+}
+
+/// Reads the bytes of `reader` from `start` to `end` (excluded).
+fn read_part(reader: &mut dyn Read, start: u64, end: u64) -> io::Result<Vec<u8>> {
+    io::copy(&mut reader.take(start), &mut io::sink())?;
+    let mut buffer = vec![];
+    reader.take(end.saturating_sub(start)).read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// Joins a relative path to a directory of a container, resolving the `.` and `..` parts.
+fn join(directory: &str, relative: &str) -> String {
+    let mut parts: Vec<&str> = directory.split('/').filter(|part| !part.is_empty()).collect();
+    for part in relative.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+#[cfg(feature = "zip")]
+fn index_zip(container: &Path, add: &mut AddEntry) -> Result<(), Box<dyn Error>> {
+    let mut zip = zip::ZipArchive::new(File::open(container)?)?;
+    for position in 0..zip.len() {
+        let mut file = zip.by_index(position)?;
+        if file.is_file() {
+            let entry = IndexedEntry {
+                position: position as u64,
+                size: file.size(),
+            };
+            // the names are normalized like the tar ones, e.g. with the `\` of some Windows tools
+            add(join("", file.name()), entry, &mut file)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "zip")]
+fn read_zip(container: &Path, entry: IndexedEntry, start: u64, end: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut zip = zip::ZipArchive::new(File::open(container)?)?;
+    let mut file = zip.by_index(entry.position as usize)?;
+    Ok(read_part(&mut file, start, end)?)
+}
+
+#[cfg(not(feature = "zip"))]
+fn index_zip(_container: &Path, _add: &mut AddEntry) -> Result<(), Box<dyn Error>> {
+    Err("The zip files are not supported, enable the `zip` feature".into())
+}
+
+#[cfg(not(feature = "zip"))]
+fn read_zip(_container: &Path, _entry: IndexedEntry, _start: u64, _end: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+    Err("The zip files are not supported, enable the `zip` feature".into())
+}
+
+#[cfg(feature = "tar")]
+fn open_tar(container: &Path, kind: ContainerKind) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    let file = File::open(container)?;
+    Ok(match kind {
+        ContainerKind::TarGz => Box::new(flate2::read::GzDecoder::new(file)),
+        _ => Box::new(file),
+    })
+}
+
+#[cfg(feature = "tar")]
+fn index_tar(container: &Path, kind: ContainerKind, add: &mut AddEntry) -> Result<(), Box<dyn Error>> {
+    let mut archive = tar::Archive::new(open_tar(container, kind)?);
+    for file in archive.entries()? {
+        let mut file = file?;
+        if file.header().entry_type().is_file() {
+            let entry = IndexedEntry {
+                position: file.raw_file_position(),
+                size: file.size(),
+            };
+            add(join("", &file.path()?.to_string_lossy()), entry, &mut file)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads a part of a file of a tar container. The compressed containers are decompressed up to
+/// the end of the part, the other ones are read from its position.
+#[cfg(feature = "tar")]
+fn read_tar(container: &Path, kind: ContainerKind, entry: IndexedEntry, start: u64, end: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut reader = open_tar(container, kind)?;
+    if kind == ContainerKind::TarGz {
+        return Ok(read_part(&mut reader, entry.position + start, entry.position + end)?);
+    }
+    let mut file = File::open(container)?;
+    file.seek(SeekFrom::Start(entry.position + start))?;
+    Ok(read_part(&mut file, 0, end - start)?)
+}
+
+#[cfg(not(feature = "tar"))]
+fn index_tar(_container: &Path, _kind: ContainerKind, _add: &mut AddEntry) -> Result<(), Box<dyn Error>> {
+    Err("The tar files are not supported, enable the `tar` feature".into())
+}
+
+#[cfg(not(feature = "tar"))]
+fn read_tar(_container: &Path, _kind: ContainerKind, _entry: IndexedEntry, _start: u64, _end: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+    Err("The tar files are not supported, enable the `tar` feature".into())
+}
+
+#[cfg(all(test, feature = "zip", feature = "tar"))]
+mod tests {
+    use super::*;
+    use crate::{ArchiveCollection, Data, Parser};
+
+    const FILES: [(&str, &str); 3] = [
+        ("test/alice1234.xml", "bob/History/alice1234.xml"),
+        ("test/alice@example.com.html", "bob/alice@example.com.html"),
+        ("test/Images/MsgPlus_Img0663.png", "bob/Images/MsgPlus_Img0663.png"),
+    ];
+
+    #[test]
+    fn parse_archives_in_zip() {
+        let path = std::env::temp_dir().join("parse_archives_in_zip.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        for (file, name) in FILES {
+            zip.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+            std::io::Write::write_all(&mut zip, &fs::read(file).unwrap()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let html = path.join("bob/alice@example.com.html");
+        let messages: Vec<_> = Parser::new(html.to_str().unwrap()).unwrap().flatten().collect();
+        let image = messages
+            .iter()
+            .flat_map(|message| &message.data)
+            .find_map(|d| match d {
+                Data::Image(image) => Some(image),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(image.content, fs::read("test/Images/MsgPlus_Img0663.png").unwrap());
+        assert_eq!(messages, Parser::new("test/alice@example.com.html").unwrap().flatten().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn scan_tar_gz_backup() {
+        let root = std::env::temp_dir().join("scan_tar_gz_backup");
+        fs::create_dir_all(&root).unwrap();
+        let path = root.join("backup.tar.gz");
+        let encoder = flate2::write::GzEncoder::new(File::create(&path).unwrap(), flate2::Compression::default());
+        let mut tar = tar::Builder::new(encoder);
+        for (file, name) in FILES {
+            tar.append_path_with_name(file, name).unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap();
+
+        let collection = ArchiveCollection::scan(root.to_str().unwrap()).unwrap();
+        let entries: Vec<(&str, &str)> = collection
+            .entries()
+            .iter()
+            .map(|entry| (entry.account.as_str(), entry.contact.as_str()))
+            .collect();
+        assert_eq!(entries, vec![("bob", "alice1234"), ("bob", "alice@example.com")]);
+        assert_eq!(collection.messages().filter(|(_, m)| m.is_ok()).count(), 8);
+        assert_eq!(
+            ArchiveSource::open(&collection.entries()[1].path).unwrap().resource("./Images/../Images/MsgPlus_Img0663.png").unwrap(),
+            fs::read("test/Images/MsgPlus_Img0663.png").unwrap()
+        );
+    }
+
+    #[test]
+    fn read_entries_through_the_index() {
+        let path = std::env::temp_dir().join("read_entries_through_the_index.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        zip.start_file(".\\bob\\History\\alice1234.xml", zip::write::SimpleFileOptions::default()).unwrap();
+        std::io::Write::write_all(&mut zip, &fs::read("test/alice1234.xml").unwrap()).unwrap();
+        zip.finish().unwrap();
+        let zip = ArchiveSource::open(path.join("bob/History/alice1234.xml").to_str().unwrap()).unwrap();
+        assert_eq!(zip.read().unwrap(), fs::read("test/alice1234.xml").unwrap());
+
+        let path = std::env::temp_dir().join("read_entries_through_the_index.tar");
+        let build = |files: &[(&str, &str)]| {
+            let mut tar = tar::Builder::new(File::create(&path).unwrap());
+            for (file, name) in files {
+                tar.append_path_with_name(file, name).unwrap();
+            }
+            tar.finish().unwrap();
+        };
+        build(&FILES);
+        let tar = ArchiveSource::open(path.join("bob/History/alice1234.xml").to_str().unwrap()).unwrap();
+        assert_eq!(tar.read_range(2, 7).unwrap(), b"xml v");
+        assert_eq!(tar.stamp().unwrap().0, fs::metadata("test/alice1234.xml").unwrap().len());
+
+        // the index is built again once the container changed
+        build(&[FILES[2], FILES[0]]);
+        assert_eq!(tar.read().unwrap(), fs::read("test/alice1234.xml").unwrap());
+    }
+
+    #[test]
+    fn read_parts_of_tar_gz_entries_once() {
+        let path = std::env::temp_dir().join("read_parts_of_tar_gz_entries_once.tgz");
+        let encoder = flate2::write::GzEncoder::new(File::create(&path).unwrap(), flate2::Compression::default());
+        let mut tar = tar::Builder::new(encoder);
+        tar.append_path_with_name(FILES[0].0, FILES[0].1).unwrap();
+        tar.into_inner().unwrap().finish().unwrap();
+
+        let source = ArchiveSource::open(path.join(FILES[0].1).to_str().unwrap()).unwrap();
+        let content = fs::read(FILES[0].0).unwrap();
+        assert_eq!(source.read_range(2, 7).unwrap(), b"xml v");
+        let index = index(&path, ContainerKind::TarGz).unwrap();
+        let decompressed = index.decompressed.lock().unwrap().clone().unwrap();
+        assert_eq!((decompressed.0.as_str(), &*decompressed.1), (FILES[0].1, content.as_slice()));
+        assert_eq!(source.read_range(10, 20).unwrap(), &content[10..20]);
+
+        // only the last containers read are kept
+        for copy in 0..=MAX_INDEXES {
+            let copy = std::env::temp_dir().join(format!("read_parts_of_tar_gz_entries_once_{}.tgz", copy));
+            fs::copy(&path, &copy).unwrap();
+            ArchiveSource::open(copy.join(FILES[0].1).to_str().unwrap()).unwrap().stamp().unwrap();
+        }
+        assert!(INDEXES.lock().unwrap().len() <= MAX_INDEXES);
+    }
+}
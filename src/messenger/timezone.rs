@@ -0,0 +1,197 @@
+use crate::messenger::{common, Message};
+use chrono::{Duration, NaiveDateTime, NaiveTime, Offset, TimeZone};
+use chrono_tz::Tz;
+use std::error::Error;
+use std::sync::Arc;
+
+/// The minutes in a day.
+const DAY: i64 = 24 * 60;
+/// The offsets of the timezones are between UTC-12:00 and UTC+14:00.
+const MIN_OFFSET: i64 = -12 * 60;
+const MAX_OFFSET: i64 = 14 * 60;
+
+/// The timezone of an archive, given by the caller when it is not saved in the archive (e.g. with
+/// Messenger Plus!).
+#[derive(Clone, PartialEq, Debug)]
+pub enum TimeZoneSpec {
+    /// A fixed difference between the local time and UTC, in minutes.
+    Fixed(i64),
+    /// An IANA timezone (e.g. `Europe/Paris`), with its daylight saving times.
+    Named(Tz),
+}
+
+impl TimeZoneSpec {
+    /// Parses a fixed offset (e.g. `+02:00`, `-0530` or `UTC`) or an IANA timezone name.
+    pub fn parse(spec: &str) -> Result<Self, Box<dyn Error>> {
+        let spec = spec.trim();
+        if spec.eq_ignore_ascii_case("UTC") || spec == "Z" {
+            return Ok(TimeZoneSpec::Fixed(0));
+        }
+        if let Some(sign) = spec.chars().next().filter(|c| *c == '+' || *c == '-') {
+            let digits: String = spec[1..].chars().filter(|c| *c != ':').collect();
+            if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+                return Err(format!("Invalid timezone offset: {}", spec).into());
+            }
+            let minutes = digits[..2].parse::<i64>()? * 60 + digits[2..].parse::<i64>()?;
+            let minutes = if sign == '-' { -minutes } else { minutes };
+            if !(MIN_OFFSET..=MAX_OFFSET).contains(&minutes) {
+                return Err(format!("Invalid timezone offset: {}", spec).into());
+            }
+            return Ok(TimeZoneSpec::Fixed(minutes));
+        }
+        Ok(TimeZoneSpec::Named(spec.parse::<Tz>()?))
+    }
+
+    /// The offset (in minutes) of the timezone at the given local date and time. For the ambiguous
+    /// local times (when the clocks go back), the earliest one is used.
+    pub fn offset_at_local(&self, local: NaiveDateTime) -> Option<i64> {
+        match self {
+            TimeZoneSpec::Fixed(minutes) => Some(*minutes),
+            TimeZoneSpec::Named(tz) => {
+                let datetime = tz.from_local_datetime(&local).earliest().or_else(|| {
+                    // the local time was skipped when the clocks went forward
+                    tz.from_local_datetime(&(local + Duration::hours(1))).earliest()
+                })?;
+                Some(datetime.offset().fix().local_minus_utc() as i64 / 60)
+            }
+        }
+    }
+
+    /// The offset (in minutes) of the timezone at the given UTC date and time.
+    pub fn offset_at_utc(&self, utc: NaiveDateTime) -> i64 {
+        match self {
+            TimeZoneSpec::Fixed(minutes) => *minutes,
+            TimeZoneSpec::Named(tz) => tz.offset_from_utc_datetime(&utc).fix().local_minus_utc() as i64 / 60,
+        }
+    }
+}
+
+/// Computes the timezone offset from the local time of a message and its UTC date and time. The
+/// local date is unknown: the offset is taken in the range of the real timezones, so the local
+/// and UTC times may fall on different days.
+pub fn offset_from_time(local: NaiveTime, utc: NaiveDateTime) -> i64 {
+    let seconds = (local - utc.time()).num_seconds();
+    // rounded to the minute, as the local time may be saved without the fractions of second
+    let mut minutes = (seconds as f64 / 60.0).round() as i64;
+    if minutes < MIN_OFFSET {
+        minutes += DAY;
+    } else if minutes > MAX_OFFSET {
+        minutes -= DAY;
+    }
+    minutes
+}
+
+/// Computes the timezone offset from the local date and time of a message and its UTC date and
+/// time. Returns `None` if the dates are too far apart to be the same instant.
+pub fn offset_from_datetime(local: NaiveDateTime, utc: NaiveDateTime) -> Option<i64> {
+    let minutes = ((local - utc).num_seconds() as f64 / 60.0).round() as i64;
+    (MIN_OFFSET..=MAX_OFFSET).contains(&minutes).then_some(minutes)
+}
+
+/// Completes the timezones of the messages of an archive and optionally converts their dates to
+/// UTC.
+///
+/// The messages without timezone get the offset of the previous message of the same session, or
+/// the offset of the given `TimeZoneSpec`.
+#[derive(Default, Debug)]
+pub struct Normalizer {
+    timezone: Option<TimeZoneSpec>,
+    utc: bool,
+    /// The session and the offset of the last message having a timezone.
+    last: Option<(Arc<str>, i64)>,
+}
+
+impl Normalizer {
+    pub fn new(timezone: Option<TimeZoneSpec>, utc: bool) -> Self {
+        Normalizer {
+            timezone,
+            utc,
+            last: None,
+        }
+    }
+
+    /// Whether the dates are converted to UTC.
+    pub fn utc(&self) -> bool {
+        self.utc
+    }
+
+    /// The timezone used for the messages saved without one.
+    pub fn into_timezone(self) -> Option<TimeZoneSpec> {
+        self.timezone
+    }
+
+    /// Completes the timezone of the message and converts its date if needed.
+    pub fn normalize(&mut self, mut message: Message) -> Message {
+        let is_utc = message.datetime.ends_with('Z');
+        let Some(datetime) = common::parse_datetime(&message.datetime) else {
+            return message;
+        };
+        if message.timezone_offset.is_none() {
+            message.timezone_offset = match (&self.last, &self.timezone) {
+                (Some((session, offset)), _) if *session == message.session_id => Some(*offset),
+                (_, Some(timezone)) if is_utc => Some(timezone.offset_at_utc(datetime)),
+                (_, Some(timezone)) => timezone.offset_at_local(datetime),
+                _ => None,
+            };
+        }
+        if let Some(offset) = message.timezone_offset {
+            self.last = Some((message.session_id.clone(), offset));
+            if self.utc && !is_utc {
+                message.datetime = to_utc(datetime, offset);
+            }
+        }
+        message
+    }
+}
+
+/// Formats the UTC date of a local date saved in an archive, as WLM saves its `DateTime`
+/// attributes (whatever the precision of the local date).
+fn to_utc(local: NaiveDateTime, offset: i64) -> String {
+    (local - Duration::minutes(offset))
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datetime(value: &str) -> NaiveDateTime {
+        common::parse_datetime(value).unwrap()
+    }
+
+    #[test]
+    fn compute_offsets_across_days() {
+        let utc = datetime("2009-04-07T01:30:12.345Z");
+        assert_eq!(offset_from_time(NaiveTime::from_hms_opt(23, 30, 12).unwrap(), utc), -120);
+        let utc = datetime("2009-04-06T23:00:00.000Z");
+        assert_eq!(offset_from_time(NaiveTime::from_hms_opt(4, 30, 0).unwrap(), utc), 330);
+        assert_eq!(offset_from_time(NaiveTime::from_hms_opt(0, 30, 0).unwrap(), utc), 90);
+        assert_eq!(offset_from_time(NaiveTime::from_hms_opt(18, 0, 0).unwrap(), utc), -300);
+        let local = datetime("2009-04-07T13:00:00");
+        assert_eq!(offset_from_datetime(local, utc), Some(840));
+        assert_eq!(offset_from_datetime(local + Duration::days(1), utc), None);
+    }
+
+    #[test]
+    fn normalize_messenger_plus_messages() {
+        let paris = TimeZoneSpec::parse("Europe/Paris").unwrap();
+        assert_eq!(TimeZoneSpec::parse("-05:30").unwrap(), TimeZoneSpec::Fixed(-330));
+        assert!(TimeZoneSpec::parse("Mars/Olympus").is_err());
+
+        let mut normalizer = Normalizer::new(Some(paris), true);
+        let summer = normalizer.normalize(Message {
+            datetime: "2009-08-05T19:30".to_string(),
+            ..Message::default()
+        });
+        assert_eq!(summer.datetime, "2009-08-05T17:30:00.000Z");
+        assert_eq!(summer.timezone_offset, Some(120));
+        let winter = normalizer.normalize(Message {
+            datetime: "2009-12-24T00:30:21".to_string(),
+            session_id: "Session_2009-12-24T00-30-21".into(),
+            ..Message::default()
+        });
+        assert_eq!(winter.datetime, "2009-12-23T23:30:21.000Z");
+        assert_eq!(winter.timezone_offset, Some(60));
+    }
+}
@@ -1,83 +1,208 @@
-use crate::messenger::{common, Data, Message, ArchiveDetails, Text, MessengerArchive};
+use crate::messenger::{common, Data, Message, ArchiveDetails, Text, MessengerArchive, UnknownElement};
+use crate::messenger::date_format::{DateFormat, DateFormatDetector};
+use crate::messenger::filter::MessageFilter;
+use crate::messenger::source::ArchiveSource;
+use crate::messenger::timezone;
 use chrono::NaiveTime;
-use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::error;
-use std::fs::File;
-use std::io::BufReader;
-use std::path::Path;
 use std::str::FromStr;
 use xml::attribute::OwnedAttribute;
-use xml::EventReader;
 use xml::reader::XmlEvent;
 
+/// The count of messages kept while the format of the dates is not decided. Past it, the format
+/// is decided with the dates read (see `DateFormatDetector::finish`), so that the archive is still
+/// read as a stream when its dates are all ambiguous.
+const MAX_PENDING: usize = 32;
+
+/// The paths of the root element and of the messages.
+pub(crate) const LOG: &[&str] = &["Log"];
+pub(crate) const MESSAGE: &[&str] = &["Log", "Message"];
+
+/// The elements of the archives, by path from the root, with the attributes read by the parser.
+/// The other elements are ignored, or kept as `Data::Unknown` by a raw parser (see `Parser::raw`).
+pub(crate) const ELEMENTS: &[(&[&str], &[&str])] = &[
+    (LOG, &["FirstSessionID", "LastSessionID"]),
+    (MESSAGE, &["Date", "Time", "DateTime", "SessionID"]),
+    (&["Log", "Message", "From"], &[]),
+    (&["Log", "Message", "From", "User"], &["FriendlyName"]),
+    (&["Log", "Message", "To"], &[]),
+    (&["Log", "Message", "To", "User"], &["FriendlyName"]),
+    (&["Log", "Message", "Text"], &["Style"]),
+];
+
 pub struct XmlParser {
     details: ArchiveDetails,
-    reader: EventReader<BufReader<File>>,
+    reader: common::XmlReader,
     parents: Vec<String>,
     done: bool,
+    filter: MessageFilter,
+    /// The current message was rejected by the filter, its content is ignored.
+    skipping: bool,
+    /// The format of the `Date` attributes, detected from the first messages.
+    date_format: Option<DateFormat>,
+    /// Detects the format of the dates, until it is decided.
+    detector: Option<DateFormatDetector>,
+    /// The messages read while the format of the dates is not decided, with their local date
+    /// and time.
+    pending: VecDeque<(Message, String, String)>,
+    /// The error met while messages were pending, returned after them.
+    error: Option<xml::reader::Error>,
+    /// The `Date` and `Time` attributes of the current message.
+    local_date: String,
+    local_time: String,
+    /// Keeps the unrecognised elements of the messages (see `MessengerArchive::set_raw`).
+    raw: bool,
+    /// The session IDs and nicknames shared by the messages.
+    names: common::Interner,
+    /// The unrecognised elements being read.
+    unknown: common::UnknownElements,
+    /// The position of the `Text` element being read in the message.
+    text: Option<usize>,
 }
 
 impl XmlParser {
     pub fn new(path: &str) -> Result<Self, Box<dyn error::Error>> {
-        Ok(XmlParser {
+        Self::from_source(ArchiveSource::open(path)?)
+    }
+
+    pub fn from_source(source: ArchiveSource) -> Result<Self, Box<dyn error::Error>> {
+        let reader = common::get_parser(&source)?;
+        Ok(Self::with_reader(source, reader))
+    }
+
+    /// Parses the archive `source` while it is read from `reader` (e.g. a stream).
+    pub fn from_reader(source: ArchiveSource, reader: Box<dyn std::io::Read + Send>) -> Self {
+        Self::with_reader(source, common::get_reader_parser(reader))
+    }
+
+    /// Parses a part of the archive `source` (see `SessionIndex`): the root element, then the
+    /// messages from a given session.
+    pub fn resume(source: ArchiveSource, content: Vec<u8>) -> Self {
+        let reader = common::get_content_parser(content);
+        Self::with_reader(source, reader)
+    }
+
+    fn with_reader(source: ArchiveSource, reader: common::XmlReader) -> Self {
+        XmlParser {
             details: ArchiveDetails {
-                recipient_id: Path::new(path)
-                    .file_stem()
-                    .unwrap_or_default()
-                    .to_str()
-                    .unwrap_or_default()
-                    .to_string(),
+                recipient_id: source.file_stem(),
                 ..ArchiveDetails::default()
             },
-            reader: common::get_parser(path)?,
+            reader,
             parents: vec![],
             done: false,
-        })
+            filter: MessageFilter::default(),
+            skipping: false,
+            date_format: None,
+            detector: Some(DateFormatDetector::default()),
+            pending: VecDeque::new(),
+            error: None,
+            local_date: String::new(),
+            local_time: String::new(),
+            raw: false,
+            names: common::Interner::default(),
+            unknown: common::UnknownElements::default(),
+            text: None,
+        }
     }
 
 
-    fn parse_node(&mut self, name: &str, message: &mut Message, attributes: &Vec<OwnedAttribute>) {
+    fn parse_node(&mut self, name: &str, message: &mut Message, attributes: &[OwnedAttribute]) {
         let attributes = common::parse_attributes(attributes);
+        let depth = self.parents.len() + 1;
+        if self.unknown.is_reading() {
+            self.unknown.start(depth, name, attributes);
+            return;
+        }
 
         match name {
+            // the other elements of the log (e.g. `Invitation`) are kept whole, as messages
+            _ if self.raw && depth == 2 && name != "Message" => {
+                message.session_id = self.names.intern(attributes.get("SessionID").unwrap_or("0"));
+                self.handle_message_datetime(message, &attributes);
+                self.skipping = !self.accepts_datetime(message);
+                self.unknown.start(depth, name, attributes);
+            }
             "Log" => {
-                self.details.first_session_id = attributes.get("FirstSessionID").unwrap_or(&"0").to_string();
-                self.details.last_session_id = attributes.get("LastSessionID").unwrap_or(&"0").to_string();
+                self.details.first_session_id = attributes.get("FirstSessionID").unwrap_or("0").to_string();
+                self.details.last_session_id = attributes.get("LastSessionID").unwrap_or("0").to_string();
             }
             "Message" => {
-                message.session_id = attributes.get("SessionID").unwrap_or(&"0").to_string();
+                message.session_id = self.names.intern(attributes.get("SessionID").unwrap_or("0"));
                 self.handle_message_datetime(message, &attributes);
+                self.skipping = !self.accepts_datetime(message);
             }
             "User" => {
-                if self.parents.contains(&"From".to_string()) {
-                    message.sender_friendly_name =
-                        attributes.get("FriendlyName").unwrap_or(&"").to_string();
-                } else if self.parents.contains(&"To".to_string()) {
-                    message.receiver_friendly_name =
-                        attributes.get("FriendlyName").unwrap_or(&"").to_string();
+                if self.parents.iter().any(|parent| parent == "From") {
+                    message.sender_friendly_name = self.names.intern(attributes.get("FriendlyName").unwrap_or(""));
+                    self.skipping |= !self.filter.accepts_sender(&message.sender_friendly_name);
+                } else if self.parents.iter().any(|parent| parent == "To") {
+                    message.receiver_friendly_name = self.names.intern(attributes.get("FriendlyName").unwrap_or(""));
                 }
             }
             "Text" => {
                 let text = Text {
-                    style: attributes.get("Style").unwrap_or(&"").to_string(),
+                    style: attributes.get("Style").unwrap_or("").to_string(),
                     ..Text::default()
                 };
 
+                self.text = Some(message.data.len());
                 message.data.push(Data::Text(text));
             }
+            "From" | "To" => {}
+            _ if self.raw && self.parents.iter().any(|parent| parent == "Message") => {
+                self.unknown.start(depth, name, attributes);
+            }
             _ => {}
         }
     }
 
-    fn handle_message_datetime(&mut self, message: &mut Message, attributes: &HashMap<&str, &str>) {
-        message.datetime = attributes.get("DateTime").unwrap_or(&"").to_string();
+    fn handle_message_datetime(&mut self, message: &mut Message, attributes: &common::Attributes) {
+        common::assign(&mut message.datetime, attributes.get("DateTime").unwrap_or(""));
+        common::assign(&mut self.local_date, attributes.get("Date").unwrap_or(""));
+        common::assign(&mut self.local_time, attributes.get("Time").unwrap_or(""));
+        match (&mut self.detector, attributes.get("Date")) {
+            (Some(detector), Some(date)) => detector.add(date, common::parse_datetime(&message.datetime)),
+            // without a local date, the format is decided with the dates read so far
+            (Some(_), None) => self.finish_detection(),
+            (None, _) => {}
+        }
+    }
 
-        let utc_time = NaiveTime::parse_and_remainder(&message.datetime, "%Y-%m-%dT%H:%M:%S");
-        if let Ok(utc_time) = utc_time {
-            let local_time = NaiveTime::from_str(attributes.get("Time").unwrap_or(&""));
-            if let Ok(local_time) = local_time {
-                message.timezone_offset = Some((local_time - utc_time.0).num_minutes());
+    /// Checks the local date of a message, with the timezone offset given by its local time.
+    fn accepts_datetime(&self, message: &Message) -> bool {
+        let offset = NaiveTime::from_str(&self.local_time)
+            .ok()
+            .zip(common::parse_datetime(&message.datetime))
+            .map(|(local_time, utc)| timezone::offset_from_time(local_time, utc));
+        self.filter.accepts_datetime(&message.datetime, offset)
+    }
+
+    /// Completes the date of a message with its local date and time, once the format of the
+    /// dates is known.
+    fn apply_local_datetime(&self, message: &mut Message, date: &str, time: &str) {
+        let utc = common::parse_datetime(&message.datetime);
+        let local_date = self.date_format.and_then(|format| format.parse(date));
+        let Ok(local_time) = NaiveTime::from_str(time) else {
+            return;
+        };
+        match (utc, local_date) {
+            (Some(utc), Some(local_date)) => {
+                // the local date may not match the UTC date, if the format was wrongly detected
+                message.timezone_offset = timezone::offset_from_datetime(local_date.and_time(local_time), utc)
+                    .or(Some(timezone::offset_from_time(local_time, utc)));
             }
+            (Some(utc), None) => {
+                message.timezone_offset = Some(timezone::offset_from_time(local_time, utc));
+            }
+            (None, Some(local_date)) => {
+                message.datetime = local_date
+                    .and_time(local_time)
+                    .format("%Y-%m-%dT%H:%M:%S")
+                    .to_string();
+            }
+            (None, None) => {}
         }
     }
 }
@@ -87,41 +212,134 @@ impl Iterator for XmlParser {
 
     fn next(&mut self) -> Option<Self::Item> {
         let mut message = Message::default();
+        Some(self.next_into(&mut message)?.map(|()| message))
+    }
+}
+
+impl XmlParser {
+    /// Decides the format of the dates with the dates read, to return the pending messages.
+    fn finish_detection(&mut self) {
+        if let Some(detector) = self.detector.take() {
+            self.date_format = detector.finish();
+        }
+    }
+}
+
+/// The friendly name of the user of `element` (`From` or `To`) inside an element kept whole.
+fn friendly_name<'a>(element: &'a UnknownElement, user: &str) -> &'a str {
+    element
+        .children
+        .iter()
+        .filter(|child| child.name == user)
+        .flat_map(|child| &child.children)
+        .find(|child| child.name == "User")
+        .and_then(|child| child.attributes.iter().find(|(name, _)| name == "FriendlyName"))
+        .map_or("", |(_, value)| value.as_str())
+}
+
+impl MessengerArchive for XmlParser {
+    fn next_into(&mut self, message: &mut Message) -> Option<Result<(), Box<dyn error::Error>>> {
+        message.clear();
         loop {
+            if self.detector.is_none() {
+                if let Some((mut pending, date, time)) = self.pending.pop_front() {
+                    self.apply_local_datetime(&mut pending, &date, &time);
+                    if self.filter.matches(&pending) {
+                        *message = pending;
+                        return Some(Ok(()));
+                    }
+                    continue;
+                }
+                if let Some(e) = self.error.take() {
+                    return Some(Err(Box::new(e)));
+                }
+            }
+            if self.done {
+                return None;
+            }
             let e = self.reader.next();
             match e {
                 Ok(XmlEvent::StartElement {
                        name, attributes, ..
                    }) => {
-                    self.parse_node(&name.local_name, &mut message, &attributes);
-                    self.parents.push(name.local_name.clone());
+                    if !self.skipping {
+                        self.parse_node(&name.local_name, message, &attributes);
+                    }
+                    self.parents.push(name.local_name);
                 }
-                Ok(XmlEvent::Characters(data)) => {
-                    if self.parents.ends_with(&["Message".to_string(), "Text".to_string()]) {
-                        let msg_data = message.data.last_mut()?;
-                        if let Data::Text(text) = msg_data {
-                            text.content = data;
-                        }
+                Ok(XmlEvent::Characters(data)) if !self.skipping && self.unknown.text(&data) => {}
+                Ok(XmlEvent::Characters(data))
+                    if !self.skipping
+                        && matches!(self.parents.as_slice(), [.., message, text] if message == "Message" && text == "Text") => {
+                    // the text may be split by unrecognised elements
+                    if let Some(Data::Text(text)) = self.text.and_then(|position| message.data.get_mut(position)) {
+                        text.content.push_str(&data);
                     }
                 }
                 Ok(XmlEvent::EndElement { name }) => {
+                    let depth = self.parents.len();
                     self.parents.pop();
-                    if name.local_name.eq("Message") {
-                        return Some(Ok(message));
+                    let log_element = match self.unknown.end(depth) {
+                        Some(element) if depth == 2 => {
+                            message.sender_friendly_name = self.names.intern(friendly_name(&element, "From"));
+                            message.receiver_friendly_name = self.names.intern(friendly_name(&element, "To"));
+                            message.data.push(Data::Unknown(element));
+                            true
+                        }
+                        Some(element) => {
+                            message.data.push(Data::Unknown(element));
+                            false
+                        }
+                        None => false,
+                    };
+                    if name.local_name == "Text" {
+                        self.text = None;
+                    }
+                    if log_element || name.local_name.eq("Message") {
+                        if self.skipping {
+                            self.skipping = false;
+                            message.clear();
+                            continue;
+                        }
+                        if let Some(detector) = &self.detector {
+                            self.date_format = detector.decided();
+                            if self.date_format.is_some() {
+                                self.detector = None;
+                            }
+                        }
+                        if self.detector.is_some() || !self.pending.is_empty() {
+                            // the message is returned once the format of its local date is known,
+                            // after the pending ones
+                            let date = std::mem::take(&mut self.local_date);
+                            let time = std::mem::take(&mut self.local_time);
+                            self.pending.push_back((std::mem::take(message), date, time));
+                            if self.pending.len() >= MAX_PENDING {
+                                self.finish_detection();
+                            }
+                            continue;
+                        }
+                        self.apply_local_datetime(message, &self.local_date, &self.local_time);
+                        if !self.filter.matches(message) {
+                            message.clear();
+                            continue;
+                        }
+                        return Some(Ok(()));
                     }
                 }
                 Ok(XmlEvent::EndDocument) => {
                     self.done = true;
-                    return None;
+                    self.finish_detection();
+                }
+                Err(e) if !self.pending.is_empty() => {
+                    self.error = Some(e);
+                    self.finish_detection();
                 }
                 Err(e) => { return Some(Err(Box::new(e))) },
                 _ => {}
             }
         }
     }
-}
 
-impl MessengerArchive for XmlParser {
     fn details(&self) -> Option<&ArchiveDetails> {
         if self.done {
             Some(&self.details)
@@ -129,6 +347,19 @@ impl MessengerArchive for XmlParser {
             None
         }
     }
+
+    fn set_filter(&mut self, filter: MessageFilter) {
+        self.filter = filter;
+    }
+
+    fn set_date_format(&mut self, format: DateFormat) {
+        self.date_format = Some(format);
+        self.detector = None;
+    }
+
+    fn set_raw(&mut self, raw: bool) {
+        self.raw = raw;
+    }
 }
 
 #[cfg(test)]
@@ -137,6 +368,7 @@ mod tests {
     use crate::messenger::FileType;
 
     #[test]
+    #[allow(clippy::useless_vec)]
     fn parse_sample_file() {
         let path = "test/alice1234.xml";
         let mut parser = XmlParser::new(path).unwrap();
@@ -145,14 +377,15 @@ mod tests {
             first_session_id: "1".to_string(),
             last_session_id: "1".to_string(),
             recipient_id: "alice1234".to_string(),
+            participants: vec![],
         };
         let messages = vec![
             Message {
                 datetime: "2009-04-06T19:40:41.851Z".to_string(),
                 timezone_offset: Some(120),
-                session_id: "1".to_string(),
-                sender_friendly_name: "Alice".to_string(),
-                receiver_friendly_name: "Bob".to_string(),
+                session_id: "1".into(),
+                sender_friendly_name: "Alice".into(),
+                receiver_friendly_name: "Bob".into(),
                 data: vec![Data::Text(Text {
                     style: "font-family:Courier New; color:#004000; ".to_string(),
                     content: "Hello!".to_string(),
@@ -161,9 +394,9 @@ mod tests {
             Message {
                 datetime: "2009-04-06T20:22:05.918Z".to_string(),
                 timezone_offset: Some(120),
-                session_id: "1".to_string(),
-                sender_friendly_name: "Bob".to_string(),
-                receiver_friendly_name: "Alice".to_string(),
+                session_id: "1".into(),
+                sender_friendly_name: "Bob".into(),
+                receiver_friendly_name: "Alice".into(),
                 data: vec![
                     Data::Text(Text {
                         style: "font-family:Courier New; color:#004000; ".to_string(),
@@ -192,9 +425,169 @@ mod tests {
             first_session_id: "0".to_string(),
             last_session_id: "0".to_string(),
             recipient_id: "scrappy".to_string(),
+            participants: vec![],
         };
         assert!(parser.next().is_none());
         assert_eq!(parser.details(), Some(&expected));
         
     }
+
+    #[test]
+    fn keep_unknown_elements() {
+        let content = concat!(
+            "<?xml version=\"1.0\"?>\n<Log FirstSessionID=\"1\" LastSessionID=\"1\">",
+            "<Message Date=\"06/04/2009\" Time=\"21:40:41\" DateTime=\"2009-04-06T19:40:41.851Z\" SessionID=\"1\">",
+            "<From><User FriendlyName=\"Alice\"/></From><To><User FriendlyName=\"Bob\"/></To>",
+            "<Text Style=\"color:#004000; \">Hello <b>Bob</b>!</Text></Message>",
+            "<Invitation Date=\"06/04/2009\" Time=\"21:41:02\" DateTime=\"2009-04-06T19:41:02.125Z\" SessionID=\"1\">",
+            "<From><User FriendlyName=\"Alice\"/></From><File>C:\\Pictures\\holidays.jpg</File>",
+            "<Text Style=\"color:#545454; \">Alice sends holidays.jpg</Text></Invitation></Log>"
+        );
+        let path = std::env::temp_dir().join("keep_unknown_elements.xml");
+        std::fs::write(&path, content).unwrap();
+        let path = path.to_str().unwrap();
+
+        let element = |name: &str, text: &str, children: Vec<UnknownElement>| UnknownElement {
+            name: name.to_string(),
+            attributes: vec![],
+            text: text.to_string(),
+            children,
+        };
+        let mut user = element("User", "", vec![]);
+        user.attributes = vec![("FriendlyName".to_string(), "Alice".to_string())];
+        let mut text = element("Text", "Alice sends holidays.jpg", vec![]);
+        text.attributes = vec![("Style".to_string(), "color:#545454; ".to_string())];
+        let mut invitation = element(
+            "Invitation",
+            "",
+            vec![element("From", "", vec![user]), element("File", "C:\\Pictures\\holidays.jpg", vec![]), text],
+        );
+        invitation.attributes = [
+            ("Date", "06/04/2009"),
+            ("Time", "21:41:02"),
+            ("DateTime", "2009-04-06T19:41:02.125Z"),
+            ("SessionID", "1"),
+        ]
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+
+        let mut parser = XmlParser::new(path).unwrap();
+        parser.set_raw(true);
+        let messages: Vec<Message> = parser.flatten().collect();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[0].data,
+            vec![
+                Data::Text(Text {
+                    style: "color:#004000; ".to_string(),
+                    content: "Hello !".to_string(),
+                }),
+                Data::Unknown(element("b", "Bob", vec![])),
+            ]
+        );
+        assert_eq!(&*messages[1].sender_friendly_name, "Alice");
+        assert_eq!(messages[1].datetime, "2009-04-06T19:41:02.125Z");
+        assert_eq!(messages[1].timezone_offset, Some(120));
+        assert_eq!(messages[1].data, vec![Data::Unknown(invitation)]);
+
+        let written = std::env::temp_dir().join("keep_unknown_elements_written.xml");
+        std::fs::write(&written, crate::XmlWriter::new(vec![]).write(messages.clone()).unwrap()).unwrap();
+        let mut parser = XmlParser::new(written.to_str().unwrap()).unwrap();
+        parser.set_raw(true);
+        assert_eq!(parser.flatten().collect::<Vec<_>>(), messages);
+
+        let messages: Vec<Message> = XmlParser::new(path).unwrap().flatten().collect();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].data, vec![Data::Text(Text {
+            style: "color:#004000; ".to_string(),
+            content: "Hello !".to_string(),
+        })]);
+    }
+
+    #[test]
+    fn detect_date_format_from_later_messages() {
+        let message = |date: &str, time: &str, utc: &str| format!(
+            "<Message Date=\"{}\" Time=\"{}\" DateTime=\"{}\" SessionID=\"1\"><From><User FriendlyName=\"Alice\"/></From><Text>Hi</Text></Message>",
+            date, time, utc
+        );
+        let content = [
+            "<?xml version=\"1.0\"?>\n<Log FirstSessionID=\"1\" LastSessionID=\"1\">".to_string(),
+            message("04/06/2009", "21:40:41", "2009-04-06T19:40:41.851Z"),
+            message("04/13/2009", "21:40:41", "2009-04-13T19:40:41.851Z"),
+            "</Log>".to_string(),
+        ]
+        .concat();
+        let path = std::env::temp_dir().join("detect_date_format_from_later_messages.xml");
+        std::fs::write(&path, content).unwrap();
+
+        let mut parser = XmlParser::new(path.to_str().unwrap()).unwrap();
+        let first = parser.next().unwrap().unwrap();
+        assert_eq!(parser.date_format, Some(DateFormat::MonthDayYear));
+        assert_eq!(first.timezone_offset, Some(120));
+        assert_eq!(parser.next().unwrap().unwrap().timezone_offset, Some(120));
+        assert!(parser.next().is_none());
+    }
+
+    /// Writes an archive with the messages of the given local dates (without `Date` if empty).
+    fn write_dated_archive(name: &str, dates: &[&str]) -> std::path::PathBuf {
+        let messages = dates.iter().map(|date| {
+            let date = if date.is_empty() { String::new() } else { format!("Date=\"{}\" ", date) };
+            format!(
+                "<Message {}Time=\"21:40:41\" DateTime=\"2009-04-04T19:40:41.851Z\" SessionID=\"1\"><Text>Hi</Text></Message>",
+                date
+            )
+        });
+        let content = ["<?xml version=\"1.0\"?>\n<Log FirstSessionID=\"1\" LastSessionID=\"1\">".to_string()]
+            .into_iter()
+            .chain(messages)
+            .chain(["</Log>".to_string()])
+            .collect::<String>();
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn stream_archive_without_local_dates() {
+        let path = write_dated_archive("stream_archive_without_local_dates.xml", &["", "", ""]);
+        let mut parser = XmlParser::new(path.to_str().unwrap()).unwrap();
+        let first = parser.next().unwrap().unwrap();
+        assert!(parser.detector.is_none());
+        assert!(parser.pending.is_empty());
+        assert_eq!(parser.date_format, None);
+        assert_eq!(first.timezone_offset, Some(120));
+        assert_eq!(parser.count(), 2);
+    }
+
+    #[test]
+    fn stream_archive_with_ambiguous_dates() {
+        let dates = vec!["04/04/2009"; MAX_PENDING * 2];
+        let path = write_dated_archive("stream_archive_with_ambiguous_dates.xml", &dates);
+        let mut parser = XmlParser::new(path.to_str().unwrap()).unwrap();
+        let first = parser.next().unwrap().unwrap();
+        assert!(parser.detector.is_none());
+        assert_eq!(parser.pending.len(), MAX_PENDING - 1);
+        assert_eq!(parser.date_format, Some(DateFormat::DayMonthYear));
+        assert_eq!(first.timezone_offset, Some(120));
+        assert_eq!(parser.count(), MAX_PENDING * 2 - 1);
+    }
+
+    #[test]
+    fn skip_messages_rejected_by_date_or_sender() {
+        let day = chrono::NaiveDate::from_ymd_opt(2009, 4, 6).unwrap();
+        let filter = |sender: &str| MessageFilter {
+            sender: Some(sender.to_string()),
+            since: day.and_hms_opt(21, 0, 0),
+            until: day.and_hms_opt(22, 0, 0),
+            ..MessageFilter::default()
+        };
+        let mut parser = XmlParser::new("test/alice1234.xml").unwrap();
+        parser.set_filter(filter("Bob"));
+        // Bob wrote at 22:22, his name must not accept the message rejected by its date
+        assert!(parser.next().is_none());
+        let mut parser = XmlParser::new("test/alice1234.xml").unwrap();
+        parser.set_filter(filter("Alice"));
+        assert_eq!(parser.flatten().count(), 1);
+    }
 }
@@ -0,0 +1,255 @@
+use crate::messenger::date_format::DateFormat;
+use crate::messenger::{common, Data, Message, UnknownElement};
+use chrono::{Duration, NaiveDateTime};
+use std::error::Error;
+use std::io::Write;
+use xml::writer::{EmitterConfig, EventWriter, XmlEvent};
+
+/// The elements WLM saves in a log next to the messages, read as messages in raw mode.
+const LOG_ELEMENTS: &[&str] = &["Invitation", "InvitationResponse", "Join", "Leave"];
+
+/// Writes messages as a standard Windows Live Messenger XML archive, readable by `XmlParser`.
+pub struct XmlWriter<W: Write> {
+    writer: EventWriter<W>,
+    date_format: DateFormat,
+}
+
+impl<W: Write> XmlWriter<W> {
+    pub fn new(writer: W) -> Self {
+        XmlWriter {
+            writer: EmitterConfig::new()
+                .perform_indent(false)
+                .pad_self_closing(false)
+                .create_writer(writer),
+            date_format: DateFormat::DayMonthYear,
+        }
+    }
+
+    /// Writes the local dates of the `Date` attributes with the given format, instead of
+    /// `DateFormat::DayMonthYear` (e.g. `06/04/2009`).
+    pub fn date_format(mut self, format: DateFormat) -> Self {
+        self.date_format = format;
+        self
+    }
+
+    /// Writes the whole archive. The messages are buffered first, because the `Log` element must
+    /// declare the lowest and the highest session IDs.
+    ///
+    /// The WLM format cannot store images nor system messages: images are replaced by their
+    /// alternative text and the system parts are dropped, with the messages made only of them.
+    pub fn write<I>(mut self, messages: I) -> Result<W, Box<dyn Error>>
+    where
+        I: IntoIterator<Item = Message>,
+    {
+        let messages: Vec<Message> = messages
+            .into_iter()
+            .filter(|message| message.data.is_empty() || message.data.iter().any(|d| !matches!(d, Data::System(_))))
+            .collect();
+        // the numeric IDs are compared as numbers, the other ones before them
+        let order = |message: &&Message| (message.session_id.parse::<u64>().ok(), message.session_id.clone());
+
+        self.writer.write(XmlEvent::processing_instruction(
+            "xml-stylesheet",
+            Some("type='text/xsl' href='MessageLog.xsl'"),
+        ))?;
+        let mut log = XmlEvent::start_element("Log");
+        if let (Some(first), Some(last)) = (messages.iter().min_by_key(order), messages.iter().max_by_key(order)) {
+            log = log
+                .attr("FirstSessionID", &first.session_id)
+                .attr("LastSessionID", &last.session_id);
+        }
+        self.writer.write(log)?;
+        for message in &messages {
+            self.write_message(message)?;
+        }
+        self.writer.write(XmlEvent::end_element())?;
+
+        Ok(self.writer.into_inner())
+    }
+
+    fn write_message(&mut self, message: &Message) -> Result<(), Box<dyn Error>> {
+        if let [Data::Unknown(element)] = message.data.as_slice() {
+            if LOG_ELEMENTS.contains(&element.name.as_str()) {
+                return self.write_element(element);
+            }
+        }
+        let datetime = common::parse_datetime(&message.datetime);
+        let local = datetime
+            .map(|datetime| datetime + Duration::minutes(message.timezone_offset.unwrap_or(0)));
+        let date = local.map(|local| self.date_format.format(local.date()));
+        let time = local.map(|local| local.format("%H:%M:%S").to_string());
+        // the dates are in UTC when their timezone is known, or when the archive saved them so
+        let utc = message.timezone_offset.is_some() || message.datetime.ends_with('Z');
+        let datetime = Self::format_datetime(&message.datetime, datetime, utc);
+
+        let mut element = XmlEvent::start_element("Message");
+        if let Some(date) = &date {
+            element = element.attr("Date", date);
+        }
+        if let (Some(time), Some(_)) = (&time, message.timezone_offset) {
+            element = element.attr("Time", time);
+        }
+        element = element
+            .attr("DateTime", &datetime)
+            .attr("SessionID", &message.session_id);
+        self.writer.write(element)?;
+
+        self.write_user("From", &message.sender_friendly_name)?;
+        self.write_user("To", &message.receiver_friendly_name)?;
+
+        let mut style = "";
+        for data in &message.data {
+            match data {
+                Data::Text(text) => {
+                    style = &text.style;
+                    self.write_text(&text.style, &text.content)?
+                }
+                Data::Image(image) => self.write_text("", &image.alt)?,
+                // WLM saves the emoticons as their shortcut, within the text
+                Data::Emoticon(emoticon) => self.write_text(style, &emoticon.shortcut)?,
+                Data::System(_) => {}
+                Data::Unknown(element) => self.write_element(element)?,
+            }
+        }
+        self.writer.write(XmlEvent::end_element())?;
+        Ok(())
+    }
+
+    fn write_user(&mut self, element: &str, friendly_name: &str) -> Result<(), Box<dyn Error>> {
+        self.writer.write(XmlEvent::start_element(element))?;
+        self.writer
+            .write(XmlEvent::start_element("User").attr("FriendlyName", friendly_name))?;
+        self.writer.write(XmlEvent::end_element())?;
+        self.writer.write(XmlEvent::end_element())?;
+        Ok(())
+    }
+
+    fn write_text(&mut self, style: &str, content: &str) -> Result<(), Box<dyn Error>> {
+        self.writer
+            .write(XmlEvent::start_element("Text").attr("Style", style))?;
+        self.writer.write(XmlEvent::characters(content))?;
+        self.writer.write(XmlEvent::end_element())?;
+        Ok(())
+    }
+
+    /// Writes back an element kept in raw mode, as it was read.
+    fn write_element(&mut self, element: &UnknownElement) -> Result<(), Box<dyn Error>> {
+        let mut start = XmlEvent::start_element(element.name.as_str());
+        for (name, value) in &element.attributes {
+            start = start.attr(name.as_str(), value);
+        }
+        self.writer.write(start)?;
+        if !element.text.is_empty() {
+            self.writer.write(XmlEvent::characters(&element.text))?;
+        }
+        for child in &element.children {
+            self.write_element(child)?;
+        }
+        self.writer.write(XmlEvent::end_element())?;
+        Ok(())
+    }
+
+    /// The dates are written in the WLM format, whatever their precision (e.g. the Messenger
+    /// Plus! dates or the ones converted to UTC by `Normalizer`). The local dates whose timezone
+    /// is unknown are written without the `Z` suffix, as they are not in UTC.
+    fn format_datetime(original: &str, datetime: Option<NaiveDateTime>, utc: bool) -> String {
+        match (datetime, utc) {
+            (Some(datetime), true) => datetime.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+            (Some(datetime), false) => datetime.format("%Y-%m-%dT%H:%M:%S%.3f").to_string(),
+            (None, _) => original.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messenger::xml_parser::XmlParser;
+    use crate::messenger::{MessengerArchive, Text};
+
+    #[test]
+    fn round_trip_sample_file() {
+        let messages: Vec<Message> = XmlParser::new("test/alice1234.xml")
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let path = std::env::temp_dir().join("round_trip_sample_file.xml");
+        let file = std::fs::File::create(&path).unwrap();
+        XmlWriter::new(file).write(messages).unwrap();
+
+        let mut original = XmlParser::new("test/alice1234.xml").unwrap();
+        let mut written = XmlParser::new(path.to_str().unwrap()).unwrap();
+        for message in original.by_ref() {
+            assert_eq!(written.next().unwrap().unwrap(), message.unwrap());
+        }
+        assert!(written.next().is_none());
+        assert_eq!(
+            written.details().unwrap().first_session_id,
+            original.details().unwrap().first_session_id
+        );
+    }
+
+    #[test]
+    fn write_messenger_plus_message() {
+        let message = Message {
+            datetime: "2009-08-05T19:30".to_string(),
+            session_id: "Session_2009-08-05T19-30-21".into(),
+            sender_friendly_name: "Bob".into(),
+            receiver_friendly_name: "Alice & co".into(),
+            data: vec![
+                Data::Text(Text {
+                    style: "font-family:\"Segoe UI\";".to_string(),
+                    content: "Hello <Alice>".to_string(),
+                }),
+                Data::System("Alice is now offline".to_string()),
+            ],
+            ..Message::default()
+        };
+        let output = XmlWriter::new(Vec::new()).write(vec![message.clone()]).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("<?xml-stylesheet type='text/xsl' href='MessageLog.xsl'?>"));
+        // the timezone is unknown, so the local date is not written as a UTC one
+        assert!(output.contains(concat!(
+            "<Message Date=\"05/08/2009\" DateTime=\"2009-08-05T19:30:00.000\" ",
+            "SessionID=\"Session_2009-08-05T19-30-21\">",
+            "<From><User FriendlyName=\"Bob\"/></From>",
+            "<To><User FriendlyName=\"Alice &amp; co\"/></To>",
+            "<Text Style=\"font-family:&quot;Segoe UI&quot;;\">Hello &lt;Alice&gt;</Text>",
+            "</Message>"
+        )));
+        let written = crate::Parser::from_reader(std::io::Cursor::new(output), "alice.xml", crate::FileType::XML)
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(written.datetime, "2009-08-05T19:30:00.000");
+        assert_eq!(written.timezone_offset, None);
+
+        let output = XmlWriter::new(Vec::new())
+            .date_format(DateFormat::YearMonthDay)
+            .write(vec![message])
+            .unwrap();
+        assert!(String::from_utf8(output).unwrap().contains("<Message Date=\"2009-08-05\" "));
+    }
+
+    #[test]
+    fn declare_the_lowest_and_highest_session_ids() {
+        let message = |session_id: &str, data: Vec<Data>| Message {
+            datetime: "2009-04-06T19:40:41.851Z".to_string(),
+            session_id: session_id.into(),
+            data,
+            ..Message::default()
+        };
+        let text = || vec![Data::Text(Text::default())];
+        let messages = vec![
+            message("10", text()),
+            message("2", text()),
+            message("1", vec![Data::System("Alice is now offline".to_string())]),
+            message("11", vec![]),
+            message("3", text()),
+        ];
+        let output = XmlWriter::new(Vec::new()).write(messages).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("<Log FirstSessionID=\"2\" LastSessionID=\"11\">"));
+        assert_eq!(output.matches("<Message ").count(), 4);
+    }
+}
@@ -0,0 +1,136 @@
+use crate::{IteratorItem, Message, Parser};
+use std::error::Error;
+
+/// An iterator through the sessions of an archive, from the latest one to the first one (see
+/// `Parser::reverse_sessions`).
+///
+/// The positions of the sessions are read first (see `SessionIndex`), then only the session
+/// returned is read and parsed: the latest messages are read without parsing the whole archive.
+/// The filters and the options of the parser apply to the messages of each session.
+pub struct ReverseSessions<'a> {
+    parser: Parser<'a>,
+    /// The count of sessions not returned yet, unknown until the index is built.
+    remaining: Option<usize>,
+    /// The beginning and the end of the archive, read once and parsed around each session.
+    frame: (String, String),
+}
+
+impl<'a> ReverseSessions<'a> {
+    pub(crate) fn new(parser: Parser<'a>) -> Self {
+        ReverseSessions {
+            parser,
+            remaining: None,
+            frame: (String::new(), String::new()),
+        }
+    }
+
+    /// Builds the index of the archive and reads its frame. Returns the count of sessions.
+    fn start(&mut self) -> Result<usize, Box<dyn Error>> {
+        let count = self.parser.sessions_count()?;
+        if let Some(index) = &self.parser.index {
+            self.frame = index.frame(&self.parser.source)?;
+        }
+        Ok(count)
+    }
+
+    /// Parses only the session at `position` in the index.
+    fn read_session(&mut self, position: usize) -> Result<Vec<Message>, Box<dyn Error>> {
+        let index = self.parser.index.as_ref().ok_or("The archive is not indexed")?;
+        let session = index
+            .sessions()
+            .get(position)
+            .ok_or_else(|| format!("The archive has no session {}", position))?;
+        let (header, footer) = &self.frame;
+        let content = [header.as_str(), &index.session_text(&self.parser.source, session)?, footer].concat();
+        let first_session = index.is_first(session);
+        self.parser.resume(content.into_bytes(), first_session);
+        self.parser.by_ref().collect()
+    }
+}
+
+impl Iterator for ReverseSessions<'_> {
+    /// The messages of a session, in the order of the archive.
+    type Item = Result<Vec<Message>, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let remaining = match self.remaining {
+            Some(remaining) => remaining,
+            None => match self.start() {
+                Ok(count) => count,
+                Err(e) => {
+                    self.remaining = Some(0);
+                    return Some(Err(e));
+                }
+            },
+        };
+        let position = remaining.checked_sub(1)?;
+        self.remaining = Some(position);
+        Some(self.read_session(position))
+    }
+}
+
+/// An iterator through the messages of an archive, the latest one first (see `Parser::reverse`).
+/// The sessions are parsed one after the other, when their messages are needed:
+/// ```
+/// use lib_messenger_archive::Parser;
+///
+/// let latest: Vec<_> = Parser::new("test/alice1234.xml").unwrap().reverse().take(1).flatten().collect();
+/// assert_eq!(latest[0].datetime, "2009-04-06T20:22:05.918Z");
+/// ```
+pub struct ReverseMessages<'a> {
+    sessions: ReverseSessions<'a>,
+    /// The messages of the current session, not returned yet.
+    messages: Vec<Message>,
+}
+
+impl<'a> ReverseMessages<'a> {
+    pub(crate) fn new(sessions: ReverseSessions<'a>) -> Self {
+        ReverseMessages {
+            sessions,
+            messages: vec![],
+        }
+    }
+}
+
+impl Iterator for ReverseMessages<'_> {
+    type Item = IteratorItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(message) = self.messages.pop() {
+                return Some(Ok(message));
+            }
+            match self.sessions.next()? {
+                Ok(messages) => self.messages = messages,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::XmlWriter;
+
+    #[test]
+    fn read_latest_messages_first() {
+        let messages: Vec<Message> = Parser::new("test/alice@example.com.html").unwrap().flatten().collect();
+        let reversed: Vec<Message> = Parser::new("test/alice@example.com.html").unwrap().reverse().flatten().collect();
+        assert_eq!(reversed, messages.into_iter().rev().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn read_sessions_backwards() {
+        let mut messages: Vec<Message> = Parser::new("test/alice1234.xml").unwrap().flatten().collect();
+        messages[1].session_id = "2".into();
+        let path = std::env::temp_dir().join("read_sessions_backwards.xml");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, XmlWriter::new(vec![]).write(messages.clone()).unwrap()).unwrap();
+
+        let sessions: Vec<Vec<Message>> = Parser::new(path).unwrap().from("Alice").reverse_sessions().flatten().collect();
+        assert_eq!(sessions, vec![vec![], vec![messages[0].clone()]]);
+        let latest: Vec<Message> = Parser::new(path).unwrap().reverse().take(1).flatten().collect();
+        assert_eq!(latest, vec![messages[1].clone()]);
+    }
+}
@@ -0,0 +1,509 @@
+use crate::messenger::common;
+use crate::{ArchiveCollection, ArchiveSource, Data, FileType, Message, MessageFilter, Parser};
+use chrono::NaiveDateTime;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::error::Error;
+use std::fs;
+
+/// The criteria of a search. All the given criteria must match.
+#[derive(Default, Clone, Debug)]
+pub struct Query {
+    /// Words that must all be found in the text of the message (case insensitive).
+    pub words: Vec<String>,
+    /// Sequences of words that must all be found in the text of the message (case insensitive).
+    pub phrases: Vec<String>,
+    /// A part of the sender friendly name.
+    pub sender: Option<String>,
+    /// A part of the receiver friendly name.
+    pub receiver: Option<String>,
+    /// The oldest date of the messages, compared with their local date, like `Parser::between`.
+    pub since: Option<NaiveDateTime>,
+    /// The newest date of the messages, compared with their local date.
+    pub until: Option<NaiveDateTime>,
+    /// The session of the messages.
+    pub session_id: Option<String>,
+    /// The type of the archives to search into. Only used when searching in a collection.
+    pub file_type: Option<FileType>,
+}
+
+/// The errors of the archives that cannot be parsed, with their path.
+pub type ArchiveErrors = Vec<(String, Box<dyn Error>)>;
+
+/// A message matching a `Query`, with the messages sent just before and after it.
+#[derive(PartialEq, Debug)]
+pub struct Hit {
+    /// The path of the archive containing the message, when searching in a collection.
+    pub archive: Option<String>,
+    /// The position of the message in its archive.
+    pub position: usize,
+    pub message: Message,
+    pub before: Vec<Message>,
+    pub after: Vec<Message>,
+}
+
+impl Query {
+    /// Builds a query from a free text: the quoted parts are phrases and the others are words.
+    /// e.g. `called "this weekend"`
+    pub fn parse(text: &str) -> Self {
+        let mut query = Query::default();
+        for (index, part) in text.split('"').enumerate() {
+            if index % 2 == 1 {
+                if !part.trim().is_empty() {
+                    query.phrases.push(part.trim().to_string());
+                }
+            } else {
+                query.words.extend(tokenize(part));
+            }
+        }
+        query
+    }
+
+    /// Returns `true` if the message matches all the criteria (except `file_type`).
+    pub fn matches(&self, message: &Message) -> bool {
+        let contains = |value: &str, part: &Option<String>| {
+            part.as_ref()
+                .is_none_or(|part| value.to_lowercase().contains(&part.to_lowercase()))
+        };
+        if !contains(&message.sender_friendly_name, &self.sender)
+            || !contains(&message.receiver_friendly_name, &self.receiver)
+            || self.session_id.as_ref().is_some_and(|id| **id != *message.session_id)
+        {
+            return false;
+        }
+        let dates = MessageFilter {
+            since: self.since,
+            until: self.until,
+            ..MessageFilter::default()
+        };
+        if !dates.accepts_datetime(&message.datetime, message.timezone_offset) {
+            return false;
+        }
+        if self.words.is_empty() && self.phrases.is_empty() {
+            return true;
+        }
+        let tokens = tokenize(&message_text(message));
+        let text = format!(" {} ", tokens.join(" "));
+        self.words
+            .iter()
+            .all(|word| tokens.contains(&word.to_lowercase()))
+            && self
+                .phrases
+                .iter()
+                .all(|phrase| text.contains(&format!(" {} ", tokenize(phrase).join(" "))))
+    }
+
+    /// The words that must be found in the messages, used to look up the index.
+    fn terms(&self) -> Vec<String> {
+        let mut terms: Vec<String> = self.words.iter().map(|word| word.to_lowercase()).collect();
+        terms.extend(self.phrases.iter().flat_map(|phrase| tokenize(phrase)));
+        terms
+    }
+}
+
+/// Searches the messages matching the `query`, with `context` messages before and after each hit.
+pub fn search<I>(messages: I, query: &Query, context: usize) -> Vec<Hit>
+where
+    I: IntoIterator<Item = Message>,
+{
+    let mut hits = vec![];
+    search_archive(messages.into_iter().enumerate(), query, context, None, &mut hits);
+    hits
+}
+
+/// Searches the messages matching the `query` in all the archives of the collection. The
+/// archives that cannot be parsed are searched up to their first error, which is returned with
+/// their path.
+pub fn search_collection(
+    collection: &ArchiveCollection,
+    query: &Query,
+    context: usize,
+) -> (Vec<Hit>, ArchiveErrors) {
+    let mut hits = vec![];
+    let mut errors = vec![];
+    for entry in collection.entries() {
+        if query.file_type.is_some_and(|file_type| file_type != entry.file_type) {
+            continue;
+        }
+        if let Err(e) = search_path(&entry.path, query, context, &mut hits) {
+            errors.push((entry.path.clone(), e));
+        }
+    }
+    (hits, errors)
+}
+
+/// Searches in the archive at `path`, until its end or its first error.
+fn search_path(path: &str, query: &Query, context: usize, hits: &mut Vec<Hit>) -> Result<(), Box<dyn Error>> {
+    let mut error = None;
+    let messages = Parser::new(path)?
+        .enumerate()
+        .map_while(|(position, message)| message.map_err(|e| error = Some(e)).ok().map(|message| (position, message)));
+    search_archive(messages, query, context, Some(path), hits);
+    error.map_or(Ok(()), Err)
+}
+
+fn search_archive<I>(messages: I, query: &Query, context: usize, archive: Option<&str>, hits: &mut Vec<Hit>)
+where
+    I: Iterator<Item = (usize, Message)>,
+{
+    let mut before: VecDeque<Message> = VecDeque::with_capacity(context + 1);
+    let first_hit = hits.len();
+    for (position, message) in messages {
+        for hit in hits[first_hit..]
+            .iter_mut()
+            .filter(|hit| hit.after.len() < context)
+        {
+            hit.after.push(message.clone());
+        }
+        if query.matches(&message) {
+            hits.push(Hit {
+                archive: archive.map(str::to_string),
+                position,
+                message: message.clone(),
+                before: before.iter().cloned().collect(),
+                after: vec![],
+            });
+        }
+        before.push_back(message);
+        if before.len() > context {
+            before.pop_front();
+        }
+    }
+}
+
+/// The text of a message, used for the searches.
+fn message_text(message: &Message) -> String {
+    message
+        .data
+        .iter()
+        .filter_map(|d| match d {
+            Data::Text(text) => Some(text.content.clone()),
+            Data::System(content) => Some(content.clone()),
+            Data::Unknown(element) => Some(element.text_content()),
+            Data::Image(_) | Data::Emoticon(_) => None,
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Splits a text into lowercase words.
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// An inverted index of the words of all the messages of a collection, which can be saved on disk
+/// to search in the archives without parsing all of them.
+#[derive(Default, PartialEq, Debug)]
+pub struct SearchIndex {
+    archives: Vec<IndexedArchive>,
+    /// For each word, the archives indexes and the positions of the messages containing it.
+    postings: BTreeMap<String, BTreeSet<(usize, usize)>>,
+}
+
+/// An archive of a `SearchIndex`, with its size and the date it was last modified (in
+/// milliseconds) when it was indexed.
+#[derive(PartialEq, Debug)]
+struct IndexedArchive {
+    path: String,
+    file_type: FileType,
+    size: u64,
+    modified: Option<u64>,
+}
+
+impl IndexedArchive {
+    fn new(path: &str, file_type: FileType) -> Result<Self, Box<dyn Error>> {
+        let (size, modified) = ArchiveSource::open(path)?.stamp()?;
+        Ok(IndexedArchive {
+            path: path.to_string(),
+            file_type,
+            size,
+            modified: modified.and_then(common::milliseconds),
+        })
+    }
+
+    /// Checks whether the archive changed since it was indexed. Returns `None` if it cannot be
+    /// found anymore.
+    fn is_stale(&self) -> Option<bool> {
+        let (size, modified) = ArchiveSource::open(&self.path).and_then(|source| source.stamp()).ok()?;
+        let modified = modified.and_then(common::milliseconds);
+        Some(size != self.size || (self.modified.is_some() && modified.is_some() && modified != self.modified))
+    }
+}
+
+impl SearchIndex {
+    /// Indexes all the messages of the collection. The archives that cannot be parsed are indexed
+    /// up to their first error, which is returned with their path.
+    pub fn build(collection: &ArchiveCollection) -> (Self, ArchiveErrors) {
+        let mut index = SearchIndex::default();
+        let mut errors = vec![];
+        for entry in collection.entries() {
+            let result = IndexedArchive::new(&entry.path, entry.file_type).and_then(|archive| {
+                index.archives.push(archive);
+                index.index_archive(index.archives.len() - 1)
+            });
+            if let Err(e) = result {
+                errors.push((entry.path.clone(), e));
+            }
+        }
+        (index, errors)
+    }
+
+    /// Indexes again the archives changed since they were indexed, and removes the ones that
+    /// cannot be found anymore. Returns their paths, and the errors of the archives that cannot be
+    /// parsed (indexed up to their first error).
+    pub fn refresh(&mut self) -> (Vec<String>, ArchiveErrors) {
+        let mut refreshed = vec![];
+        let mut errors = vec![];
+        // from the last archive, as the removed ones change the positions of the next ones
+        for archive in (0..self.archives.len()).rev() {
+            let stale = self.archives[archive].is_stale();
+            if stale == Some(false) {
+                continue;
+            }
+            self.postings = std::mem::take(&mut self.postings)
+                .into_iter()
+                .filter_map(|(word, postings)| {
+                    let postings: BTreeSet<(usize, usize)> = postings
+                        .into_iter()
+                        .filter(|(indexed, _)| *indexed != archive)
+                        .map(|(indexed, position)| match (stale, indexed > archive) {
+                            (None, true) => (indexed - 1, position),
+                            _ => (indexed, position),
+                        })
+                        .collect();
+                    (!postings.is_empty()).then_some((word, postings))
+                })
+                .collect();
+            let path = self.archives[archive].path.clone();
+            if stale.is_none() {
+                self.archives.remove(archive);
+            } else {
+                let file_type = self.archives[archive].file_type;
+                let result = IndexedArchive::new(&path, file_type).and_then(|indexed| {
+                    self.archives[archive] = indexed;
+                    self.index_archive(archive)
+                });
+                if let Err(e) = result {
+                    errors.push((path.clone(), e));
+                }
+            }
+            refreshed.push(path);
+        }
+        refreshed.reverse();
+        errors.reverse();
+        (refreshed, errors)
+    }
+
+    /// Adds the words of the archive at `archive` in `archives` to the postings, up to its first
+    /// error.
+    fn index_archive(&mut self, archive: usize) -> Result<(), Box<dyn Error>> {
+        for (position, message) in Parser::new(&self.archives[archive].path)?.enumerate() {
+            for word in tokenize(&message_text(&message?)) {
+                self.postings.entry(word).or_default().insert((archive, position));
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves the index in a text file.
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut content = String::new();
+        for archive in &self.archives {
+            let file_type = match archive.file_type {
+                FileType::XML => "xml",
+                FileType::MessengerPlus => "mplus",
+            };
+            let modified = archive.modified.map_or("-".to_string(), |modified| modified.to_string());
+            content.push_str(&format!(
+                "archive\t{}\t{}\t{}\t{}\n",
+                file_type, archive.size, modified, archive.path
+            ));
+        }
+        for (word, postings) in &self.postings {
+            let postings: Vec<String> = postings
+                .iter()
+                .map(|(archive, position)| format!("{}:{}", archive, position))
+                .collect();
+            content.push_str(&format!("word\t{}\t{}\n", word, postings.join(" ")));
+        }
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// Loads an index previously saved with `save`.
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let mut index = SearchIndex::default();
+        for line in fs::read_to_string(path)?.lines() {
+            match line.splitn(5, '\t').collect::<Vec<&str>>()[..] {
+                ["archive", file_type, size, modified, archive] => index.archives.push(IndexedArchive {
+                    path: archive.to_string(),
+                    file_type: match file_type {
+                        "xml" => FileType::XML,
+                        "mplus" => FileType::MessengerPlus,
+                        _ => return Err(format!("Invalid index line: {}", line).into()),
+                    },
+                    size: size.parse()?,
+                    modified: match modified {
+                        "-" => None,
+                        modified => Some(modified.parse()?),
+                    },
+                }),
+                ["word", word, postings] => {
+                    let mut set = BTreeSet::new();
+                    for posting in postings.split(' ') {
+                        let (archive, position) = posting.split_once(':').ok_or("Invalid posting")?;
+                        set.insert((archive.parse()?, position.parse()?));
+                    }
+                    index.postings.insert(word.to_string(), set);
+                }
+                _ => return Err(format!("Invalid index line: {}", line).into()),
+            }
+        }
+        Ok(index)
+    }
+
+    /// Searches the messages matching the `query`. Only the archives containing all the words of
+    /// the query are parsed again, to check the other criteria and to read the context. The
+    /// archives changed since they were indexed are all parsed, and the ones that cannot be found
+    /// anymore are ignored (see `refresh`). The archives that cannot be parsed are searched up to
+    /// their first error, which is returned with their path.
+    pub fn search(&self, query: &Query, context: usize) -> (Vec<Hit>, ArchiveErrors) {
+        let terms = query.terms();
+        let mut candidates: Option<BTreeSet<(usize, usize)>> = None;
+        for term in &terms {
+            let postings = self.postings.get(term).cloned().unwrap_or_default();
+            candidates = Some(match candidates {
+                None => postings,
+                Some(candidates) => candidates.intersection(&postings).copied().collect(),
+            });
+        }
+
+        let mut hits = vec![];
+        let mut errors = vec![];
+        for (position, archive) in self.archives.iter().enumerate() {
+            if query.file_type.is_some_and(|expected| expected != archive.file_type) {
+                continue;
+            }
+            let candidate = candidates
+                .as_ref()
+                .is_none_or(|candidates| candidates.iter().any(|(a, _)| *a == position));
+            match archive.is_stale() {
+                None => continue,
+                Some(false) if !candidate => continue,
+                _ => {}
+            }
+            if let Err(e) = search_path(&archive.path, query, context, &mut hits) {
+                errors.push((archive.path.clone(), e));
+            }
+        }
+        (hits, errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_messages_with_context() {
+        let messages = Parser::new("test/alice@example.com.html").unwrap().flatten();
+        let query = Query {
+            sender: Some("ali".to_string()),
+            ..Query::parse("called \"this weekend\"")
+        };
+        let hits = search(messages, &query, 1);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].position, 1);
+        assert_eq!(&*hits[0].before[0].sender_friendly_name, "Bob");
+        assert_eq!(hits[0].after.len(), 1);
+
+        let query = Query {
+            since: NaiveDateTime::parse_from_str("2009-08-05T19:31", "%Y-%m-%dT%H:%M").ok(),
+            until: NaiveDateTime::parse_from_str("2009-08-05T19:35", "%Y-%m-%dT%H:%M").ok(),
+            ..Query::default()
+        };
+        let messages = Parser::new("test/alice@example.com.html").unwrap().flatten();
+        assert_eq!(search(messages, &query, 0).len(), 3);
+    }
+
+    #[test]
+    fn search_with_saved_index() {
+        let collection = ArchiveCollection::scan("test").unwrap();
+        let path = std::env::temp_dir().join("search_with_saved_index.idx");
+        let path = path.to_str().unwrap();
+        SearchIndex::build(&collection).0.save(path).unwrap();
+        let index = SearchIndex::load(path).unwrap();
+        assert_eq!(index, SearchIndex::build(&collection).0);
+
+        let (hits, errors) = index.search(&Query::parse("Alice"), 0);
+        assert!(errors.is_empty());
+        assert_eq!(hits.len(), 3);
+        assert_eq!(hits[0].archive.as_deref(), Some("test/alice1234.xml"));
+
+        let query = Query {
+            file_type: Some(FileType::MessengerPlus),
+            ..Query::parse("alice")
+        };
+        assert_eq!(index.search(&query, 0).0, search_collection(&collection, &query, 0).0);
+        assert!(index.search(&Query::parse("unknown"), 0).0.is_empty());
+    }
+
+    #[test]
+    fn compare_local_dates() {
+        let mut messages: Vec<Message> = Parser::new("test/alice1234.xml").unwrap().flatten().collect();
+        let query = Query {
+            since: NaiveDateTime::parse_from_str("2009-04-06T21:40", "%Y-%m-%dT%H:%M").ok(),
+            until: NaiveDateTime::parse_from_str("2009-04-06T21:41", "%Y-%m-%dT%H:%M").ok(),
+            ..Query::default()
+        };
+        // 19:40 UTC is 21:40 in the timezone of the message
+        assert_eq!(search(messages.clone(), &query, 0).len(), 1);
+        messages[0].timezone_offset = None;
+        assert!(search(messages, &query, 0).is_empty());
+    }
+
+    #[test]
+    fn report_errors_and_index_changed_archives() {
+        let root = std::env::temp_dir().join("report_errors_and_index_changed_archives");
+        fs::create_dir_all(&root).unwrap();
+        let archive = root.join("alice1234.xml");
+        fs::copy("test/alice1234.xml", &archive).unwrap();
+        let content = fs::read_to_string("test/alice1234.xml").unwrap();
+        let broken = &content[..content.find("</Message>").unwrap() + "</Message>".len()];
+        fs::write(root.join("broken.xml"), broken).unwrap();
+
+        let collection = ArchiveCollection::scan(root.to_str().unwrap()).unwrap();
+        let (hits, errors) = search_collection(&collection, &Query::parse("Hello"), 0);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].0.ends_with("broken.xml"));
+        let (mut index, errors) = SearchIndex::build(&collection);
+        assert_eq!(errors.len(), 1);
+        let (hits, errors) = index.search(&Query::parse("Hello"), 0);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].0.ends_with("broken.xml"));
+
+        fs::write(&archive, content.replace("Hello", "Goodbye")).unwrap();
+        fs::write(root.join("broken.xml"), &content).unwrap();
+        assert_eq!(index.search(&Query::parse("Goodbye"), 0).0.len(), 1);
+        let (refreshed, errors) = index.refresh();
+        assert_eq!(refreshed.len(), 2);
+        assert!(errors.is_empty());
+        assert_eq!(index.postings.get("goodbye").map(BTreeSet::len), Some(1));
+        assert_eq!(index.search(&Query::parse("Hello"), 0).0.len(), 1);
+
+        // the removed archives are ignored, then dropped from the index
+        fs::remove_file(&archive).unwrap();
+        let (hits, errors) = index.search(&Query::parse("Hello"), 0);
+        assert_eq!((hits.len(), errors.len()), (1, 0));
+        assert!(index.search(&Query::parse("Goodbye"), 0).0.is_empty());
+        let (refreshed, errors) = index.refresh();
+        assert!(refreshed[0].ends_with("alice1234.xml") && errors.is_empty());
+        assert_eq!(index.archives.len(), 1);
+        assert!(index.postings.values().flatten().all(|(archive, _)| *archive == 0));
+        assert_eq!(index.search(&Query::parse("Hello"), 0).0.len(), 1);
+    }
+}
@@ -0,0 +1,552 @@
+use crate::{ArchiveDetails, Data, Emoticon, FileType, Image, Message, MessengerArchive, Parser, Text, UnknownElement};
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS archives (
+        id INTEGER PRIMARY KEY,
+        content_hash TEXT NOT NULL UNIQUE,
+        path TEXT NOT NULL,
+        file_type TEXT NOT NULL,
+        first_session_id TEXT NOT NULL,
+        last_session_id TEXT NOT NULL,
+        recipient_id TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS sessions (
+        id INTEGER PRIMARY KEY,
+        archive_id INTEGER NOT NULL REFERENCES archives(id),
+        session_key TEXT NOT NULL,
+        UNIQUE (archive_id, session_key)
+    );
+    CREATE TABLE IF NOT EXISTS participants (
+        id INTEGER PRIMARY KEY,
+        friendly_name TEXT NOT NULL UNIQUE
+    );
+    CREATE TABLE IF NOT EXISTS messages (
+        id INTEGER PRIMARY KEY,
+        archive_id INTEGER NOT NULL REFERENCES archives(id),
+        session_id INTEGER NOT NULL REFERENCES sessions(id),
+        position INTEGER NOT NULL,
+        datetime TEXT NOT NULL,
+        timezone_offset INTEGER,
+        sender_id INTEGER NOT NULL REFERENCES participants(id),
+        receiver_id INTEGER NOT NULL REFERENCES participants(id)
+    );
+    CREATE TABLE IF NOT EXISTS text_parts (
+        message_id INTEGER NOT NULL REFERENCES messages(id),
+        position INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        style TEXT NOT NULL,
+        content TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS emoticon_parts (
+        message_id INTEGER NOT NULL REFERENCES messages(id),
+        position INTEGER NOT NULL,
+        emoticon_id TEXT NOT NULL,
+        shortcut TEXT NOT NULL,
+        image_id INTEGER REFERENCES images(id),
+        src TEXT,
+        alt TEXT
+    );
+    CREATE TABLE IF NOT EXISTS images (
+        id INTEGER PRIMARY KEY,
+        content_hash TEXT NOT NULL UNIQUE,
+        content BLOB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS image_parts (
+        message_id INTEGER NOT NULL REFERENCES messages(id),
+        position INTEGER NOT NULL,
+        image_id INTEGER NOT NULL REFERENCES images(id),
+        src TEXT NOT NULL,
+        alt TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS element_parts (
+        id INTEGER PRIMARY KEY,
+        message_id INTEGER NOT NULL REFERENCES messages(id),
+        position INTEGER NOT NULL,
+        parent_id INTEGER REFERENCES element_parts(id),
+        name TEXT NOT NULL,
+        text TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS element_attributes (
+        element_id INTEGER NOT NULL REFERENCES element_parts(id),
+        position INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        value TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS messages_archive ON messages (archive_id, position);
+    CREATE INDEX IF NOT EXISTS text_parts_message ON text_parts (message_id);
+    CREATE INDEX IF NOT EXISTS image_parts_message ON image_parts (message_id);
+    CREATE INDEX IF NOT EXISTS emoticon_parts_message ON emoticon_parts (message_id);
+    CREATE INDEX IF NOT EXISTS element_parts_message ON element_parts (message_id, parent_id);
+    CREATE INDEX IF NOT EXISTS element_attributes_element ON element_attributes (element_id);
+";
+
+/// A SQLite database containing some parsed archives.
+pub struct SqliteStore {
+    connection: Connection,
+}
+
+impl SqliteStore {
+    /// Opens (or creates) the database at the given `path`.
+    pub fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        Self::init(Connection::open(path)?)
+    }
+
+    /// Creates a temporary database, kept in memory.
+    pub fn open_in_memory() -> Result<Self, Box<dyn Error>> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(connection: Connection) -> Result<Self, Box<dyn Error>> {
+        connection.execute_batch(SCHEMA)?;
+        Ok(SqliteStore { connection })
+    }
+
+    /// Parses the archive at the given `path` and saves it in the database, with all the parts of
+    /// its messages.
+    /// Returns the ID of the new archive, or `None` if an archive with the same content was
+    /// already imported.
+    pub fn import(&mut self, path: &str) -> Result<Option<i64>, Box<dyn Error>> {
+        let hash = hash(&fs::read(path)?);
+        let known: Option<i64> = self
+            .connection
+            .query_row("SELECT id FROM archives WHERE content_hash = ?1", [&hash], |row| row.get(0))
+            .optional()?;
+        if known.is_some() {
+            return Ok(None);
+        }
+
+        let transaction = self.connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO archives (content_hash, path, file_type, first_session_id, last_session_id, recipient_id)
+             VALUES (?1, ?2, ?3, '', '', '')",
+            params![hash, path, file_type_name(&FileType::from_path(path))],
+        )?;
+        let archive_id = transaction.last_insert_rowid();
+
+        let mut parser = Parser::new(path)?;
+        for (position, message) in parser.by_ref().enumerate() {
+            insert_message(&transaction, archive_id, position, &message?)?;
+        }
+        if let Some(details) = parser.details() {
+            transaction.execute(
+                "UPDATE archives SET file_type = ?1, first_session_id = ?2, last_session_id = ?3, recipient_id = ?4
+                 WHERE id = ?5",
+                params![
+                    file_type_name(&details.file_type),
+                    details.first_session_id,
+                    details.last_session_id,
+                    details.recipient_id,
+                    archive_id
+                ],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(Some(archive_id))
+    }
+
+    /// Lists the imported archives, with their IDs.
+    pub fn archives(&self) -> Result<Vec<(i64, ArchiveDetails)>, Box<dyn Error>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, file_type, first_session_id, last_session_id, recipient_id FROM archives ORDER BY id",
+        )?;
+        let archives = statement
+            .query_map([], |row| {
+                let file_type: String = row.get(1)?;
+                Ok((
+                    row.get(0)?,
+                    ArchiveDetails {
+                        file_type: match file_type.as_str() {
+                            "XML" => FileType::XML,
+                            "MessengerPlus" => FileType::MessengerPlus,
+                            _ => {
+                                return Err(rusqlite::Error::FromSqlConversionFailure(
+                                    1,
+                                    rusqlite::types::Type::Text,
+                                    format!("Unknown archive type: {}", file_type).into(),
+                                ))
+                            }
+                        },
+                        first_session_id: row.get(2)?,
+                        last_session_id: row.get(3)?,
+                        recipient_id: row.get(4)?,
+                        ..ArchiveDetails::default()
+                    },
+                ))
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(archives)
+    }
+
+    /// Iterates through the messages of the given archive, in their original order.
+    pub fn messages(&self, archive_id: i64) -> Result<SqliteMessages<'_>, Box<dyn Error>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT id FROM messages WHERE archive_id = ?1 ORDER BY position")?;
+        let ids: Vec<i64> = statement
+            .query_map([archive_id], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(SqliteMessages {
+            store: self,
+            ids: ids.into_iter(),
+        })
+    }
+
+    fn load_message(&self, id: i64) -> Result<Message, Box<dyn Error>> {
+        let mut message = self.connection.query_row(
+            "SELECT m.datetime, m.timezone_offset, s.session_key, sender.friendly_name, receiver.friendly_name
+             FROM messages m
+             JOIN sessions s ON s.id = m.session_id
+             JOIN participants sender ON sender.id = m.sender_id
+             JOIN participants receiver ON receiver.id = m.receiver_id
+             WHERE m.id = ?1",
+            [id],
+            |row| {
+                Ok(Message {
+                    datetime: row.get(0)?,
+                    timezone_offset: row.get(1)?,
+                    session_id: row.get(2)?,
+                    sender_friendly_name: row.get(3)?,
+                    receiver_friendly_name: row.get(4)?,
+                    data: vec![],
+                })
+            },
+        )?;
+
+        let mut parts: Vec<(i64, Data)> = vec![];
+        let mut statement = self
+            .connection
+            .prepare_cached("SELECT position, kind, style, content FROM text_parts WHERE message_id = ?1")?;
+        let texts = statement.query_map([id], |row| {
+            let kind: String = row.get(1)?;
+            let data = match kind.as_str() {
+                "system" => Data::System(row.get(3)?),
+                _ => Data::Text(Text {
+                    style: row.get(2)?,
+                    content: row.get(3)?,
+                }),
+            };
+            Ok((row.get(0)?, data))
+        })?;
+        for text in texts {
+            parts.push(text?);
+        }
+        let mut statement = self.connection.prepare_cached(
+            "SELECT p.position, p.src, p.alt, i.content
+             FROM image_parts p JOIN images i ON i.id = p.image_id
+             WHERE p.message_id = ?1",
+        )?;
+        let images = statement.query_map([id], |row| {
+            Ok((
+                row.get(0)?,
+                Data::Image(Image {
+                    src: row.get(1)?,
+                    alt: row.get(2)?,
+                    content: row.get(3)?,
+                }),
+            ))
+        })?;
+        for image in images {
+            parts.push(image?);
+        }
+        let mut statement = self.connection.prepare_cached(
+            "SELECT p.position, p.emoticon_id, p.shortcut, p.src, p.alt, i.content
+             FROM emoticon_parts p LEFT JOIN images i ON i.id = p.image_id
+             WHERE p.message_id = ?1",
+        )?;
+        let emoticons = statement.query_map([id], |row| {
+            let content: Option<Vec<u8>> = row.get(5)?;
+            Ok((
+                row.get(0)?,
+                Data::Emoticon(Emoticon {
+                    id: row.get(1)?,
+                    shortcut: row.get(2)?,
+                    image: match content {
+                        Some(content) => Some(Image {
+                            src: row.get(3)?,
+                            alt: row.get(4)?,
+                            content,
+                        }),
+                        None => None,
+                    },
+                }),
+            ))
+        })?;
+        for emoticon in emoticons {
+            parts.push(emoticon?);
+        }
+        for (position, element) in self.load_elements(id, None)? {
+            parts.push((position, Data::Unknown(element)));
+        }
+        parts.sort_by_key(|(position, _)| *position);
+        message.data = parts.into_iter().map(|(_, data)| data).collect();
+        Ok(message)
+    }
+
+    /// Loads the unrecognised elements of a message inside the element `parent` (or the top ones),
+    /// with their positions.
+    fn load_elements(&self, message_id: i64, parent: Option<i64>) -> Result<Vec<(i64, UnknownElement)>, Box<dyn Error>> {
+        let mut statement = self.connection.prepare_cached(
+            "SELECT id, position, name, text FROM element_parts
+             WHERE message_id = ?1 AND parent_id IS ?2 ORDER BY position",
+        )?;
+        let rows: Vec<(i64, i64, String, String)> = statement
+            .query_map(params![message_id, parent], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?
+            .collect::<Result<_, _>>()?;
+        let mut elements = vec![];
+        for (id, position, name, text) in rows {
+            let mut statement = self
+                .connection
+                .prepare_cached("SELECT name, value FROM element_attributes WHERE element_id = ?1 ORDER BY position")?;
+            let attributes = statement
+                .query_map([id], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<_, _>>()?;
+            let children = self.load_elements(message_id, Some(id))?;
+            elements.push((
+                position,
+                UnknownElement {
+                    name,
+                    attributes,
+                    text,
+                    children: children.into_iter().map(|(_, child)| child).collect(),
+                },
+            ));
+        }
+        Ok(elements)
+    }
+}
+
+/// An iterator through the messages of an archive saved in a `SqliteStore`.
+pub struct SqliteMessages<'a> {
+    store: &'a SqliteStore,
+    ids: std::vec::IntoIter<i64>,
+}
+
+impl Iterator for SqliteMessages<'_> {
+    type Item = Result<Message, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let id = self.ids.next()?;
+        Some(self.store.load_message(id))
+    }
+}
+
+fn insert_message(connection: &Connection, archive_id: i64, position: usize, message: &Message) -> Result<(), Box<dyn Error>> {
+    connection.execute(
+        "INSERT OR IGNORE INTO sessions (archive_id, session_key) VALUES (?1, ?2)",
+        params![archive_id, message.session_id],
+    )?;
+    let session_id: i64 = connection.query_row(
+        "SELECT id FROM sessions WHERE archive_id = ?1 AND session_key = ?2",
+        params![archive_id, message.session_id],
+        |row| row.get(0),
+    )?;
+    let sender_id = participant_id(connection, &message.sender_friendly_name)?;
+    let receiver_id = participant_id(connection, &message.receiver_friendly_name)?;
+    connection.execute(
+        "INSERT INTO messages (archive_id, session_id, position, datetime, timezone_offset, sender_id, receiver_id)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            archive_id,
+            session_id,
+            position as i64,
+            message.datetime,
+            message.timezone_offset,
+            sender_id,
+            receiver_id
+        ],
+    )?;
+    let message_id = connection.last_insert_rowid();
+
+    for (position, data) in message.data.iter().enumerate() {
+        match data {
+            Data::Text(text) => {
+                connection.execute(
+                    "INSERT INTO text_parts (message_id, position, kind, style, content) VALUES (?1, ?2, 'text', ?3, ?4)",
+                    params![message_id, position as i64, text.style, text.content],
+                )?;
+            }
+            Data::System(content) => {
+                connection.execute(
+                    "INSERT INTO text_parts (message_id, position, kind, style, content) VALUES (?1, ?2, 'system', '', ?3)",
+                    params![message_id, position as i64, content],
+                )?;
+            }
+            Data::Emoticon(emoticon) => {
+                let image = emoticon.image.as_ref();
+                let image_id = image.map(|image| image_id(connection, &image.content)).transpose()?;
+                connection.execute(
+                    "INSERT INTO emoticon_parts (message_id, position, emoticon_id, shortcut, image_id, src, alt)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        message_id,
+                        position as i64,
+                        emoticon.id,
+                        emoticon.shortcut,
+                        image_id,
+                        image.map(|image| &image.src),
+                        image.map(|image| &image.alt)
+                    ],
+                )?;
+            }
+            Data::Unknown(element) => insert_element(connection, message_id, position, None, element)?,
+            Data::Image(image) => {
+                let image_id = image_id(connection, &image.content)?;
+                connection.execute(
+                    "INSERT INTO image_parts (message_id, position, image_id, src, alt) VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![message_id, position as i64, image_id, image.src, image.alt],
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Saves an unrecognised element of a message, inside the element `parent` (or at the top).
+fn insert_element(
+    connection: &Connection,
+    message_id: i64,
+    position: usize,
+    parent: Option<i64>,
+    element: &UnknownElement,
+) -> Result<(), Box<dyn Error>> {
+    connection.execute(
+        "INSERT INTO element_parts (message_id, position, parent_id, name, text) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![message_id, position as i64, parent, element.name, element.text],
+    )?;
+    let element_id = connection.last_insert_rowid();
+    for (position, (name, value)) in element.attributes.iter().enumerate() {
+        connection.execute(
+            "INSERT INTO element_attributes (element_id, position, name, value) VALUES (?1, ?2, ?3, ?4)",
+            params![element_id, position as i64, name, value],
+        )?;
+    }
+    for (position, child) in element.children.iter().enumerate() {
+        insert_element(connection, message_id, position, Some(element_id), child)?;
+    }
+    Ok(())
+}
+
+/// The ID of an image with this `content`, saved once for all the messages.
+fn image_id(connection: &Connection, content: &[u8]) -> Result<i64, Box<dyn Error>> {
+    let hash = hash(content);
+    connection.execute(
+        "INSERT OR IGNORE INTO images (content_hash, content) VALUES (?1, ?2)",
+        params![hash, content],
+    )?;
+    Ok(connection.query_row(
+        "SELECT id FROM images WHERE content_hash = ?1",
+        [&hash],
+        |row| row.get(0),
+    )?)
+}
+
+fn participant_id(connection: &Connection, friendly_name: &str) -> Result<i64, Box<dyn Error>> {
+    connection.execute(
+        "INSERT OR IGNORE INTO participants (friendly_name) VALUES (?1)",
+        [friendly_name],
+    )?;
+    Ok(connection.query_row(
+        "SELECT id FROM participants WHERE friendly_name = ?1",
+        [friendly_name],
+        |row| row.get(0),
+    )?)
+}
+
+fn file_type_name(file_type: &FileType) -> &'static str {
+    match file_type {
+        FileType::XML => "XML",
+        FileType::MessengerPlus => "MessengerPlus",
+    }
+}
+
+fn hash(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn import_and_read_archives() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let xml = store.import("test/alice1234.xml").unwrap().unwrap();
+        let plus = store.import("test/alice@example.com.html").unwrap().unwrap();
+
+        let archives = store.archives().unwrap();
+        assert_eq!(archives.len(), 2);
+        assert_eq!(archives[0].1.recipient_id, "alice1234");
+        assert_eq!(archives[1].1.file_type, FileType::MessengerPlus);
+
+        let expected: Vec<Message> = Parser::new("test/alice1234.xml").unwrap().flatten().collect();
+        let messages: Vec<Message> = store.messages(xml).unwrap().flatten().collect();
+        assert_eq!(messages, expected);
+        let expected: Vec<Message> = Parser::new("test/alice@example.com.html").unwrap().flatten().collect();
+        let messages: Vec<Message> = store.messages(plus).unwrap().flatten().collect();
+        assert_eq!(messages, expected);
+    }
+
+    #[test]
+    fn import_archive_without_details() {
+        let path = std::env::temp_dir().join("import_archive_without_details.html");
+        std::fs::write(&path, "<?xml version=\"1.0\"?><html><body><h1>Messenger Plus!</h1></body></html>").unwrap();
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        store.import(path.to_str().unwrap()).unwrap().unwrap();
+        assert_eq!(store.archives().unwrap()[0].1.file_type, FileType::MessengerPlus);
+    }
+
+    #[test]
+    fn store_emoticons_and_unknown_elements() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let archive = store.import("test/alice@example.com.html").unwrap().unwrap();
+        let image = Image {
+            src: "./Images/MsgPlus_Img0663.png".to_string(),
+            alt: ":wave:".to_string(),
+            content: vec![1, 2, 3],
+        };
+        let message = Message {
+            data: vec![
+                Data::Emoticon(Emoticon {
+                    id: "smile".to_string(),
+                    shortcut: ":)".to_string(),
+                    image: None,
+                }),
+                Data::Emoticon(Emoticon {
+                    id: "wave".to_string(),
+                    shortcut: ":wave:".to_string(),
+                    image: Some(image),
+                }),
+                Data::Unknown(UnknownElement {
+                    name: "a".to_string(),
+                    attributes: vec![("href".to_string(), "http://example.com".to_string()), ("title".to_string(), "x".to_string())],
+                    text: "see ".to_string(),
+                    children: vec![UnknownElement {
+                        name: "b".to_string(),
+                        text: "here".to_string(),
+                        ..UnknownElement::default()
+                    }],
+                }),
+                Data::System("end".to_string()),
+            ],
+            ..Message::default()
+        };
+        insert_message(&store.connection, archive, 100, &message).unwrap();
+        let messages: Vec<Message> = store.messages(archive).unwrap().flatten().collect();
+        assert_eq!(messages.last(), Some(&message));
+    }
+
+    #[test]
+    fn skip_already_imported_archive() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        assert!(store.import("test/alice1234.xml").unwrap().is_some());
+        assert!(store.import("test/alice1234.xml").unwrap().is_none());
+        assert_eq!(store.archives().unwrap().len(), 1);
+    }
+}
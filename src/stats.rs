@@ -0,0 +1,243 @@
+use crate::messenger::common;
+use crate::{emoticon, ArchiveCollection, Data, Message};
+use chrono::{Datelike, Duration, NaiveDateTime, Timelike};
+use std::collections::BTreeMap;
+
+/// Some statistics about the conversations, computed from the messages of one or more archives.
+/// The hours and the weekdays are computed in the local time of the messages, when the timezone
+/// is known.
+#[derive(Default, Debug)]
+pub struct Statistics {
+    /// The number of messages, including the system messages.
+    pub messages: usize,
+    /// The number of words of the text parts.
+    pub words: usize,
+    /// The statistics of each participant, by friendly name.
+    pub participants: BTreeMap<String, ParticipantStats>,
+    /// The statistics of each session, by session ID (prefixed by the contact when computed from
+    /// a collection).
+    pub sessions: BTreeMap<String, SessionStats>,
+    /// The number of messages sent during each hour of the day.
+    pub by_hour: [usize; 24],
+    /// The number of messages sent each day of the week, starting on Monday.
+    pub by_weekday: [usize; 7],
+    /// The number of times each emoticon was used, by identifier (e.g. `smile`, see
+    /// `emoticon::EMOTICONS`), whether it was typed or saved as an image.
+    pub emoticons: BTreeMap<String, usize>,
+    /// The number of text parts written with each font.
+    pub fonts: BTreeMap<String, usize>,
+    /// The number of text parts written with each colour.
+    pub colors: BTreeMap<String, usize>,
+    /// The sender, date and session of the previous message, to compute the response times.
+    last: Option<(String, NaiveDateTime, String)>,
+}
+
+/// Some statistics about a participant of the conversations.
+#[derive(Default, PartialEq, Debug)]
+pub struct ParticipantStats {
+    pub messages: usize,
+    pub words: usize,
+    /// The number of messages answering to the other participant in the same session.
+    pub responses: usize,
+    /// The total time taken to send these responses.
+    pub response_time: Duration,
+}
+
+/// Some statistics about a conversation session.
+#[derive(Default, PartialEq, Debug)]
+pub struct SessionStats {
+    pub messages: usize,
+    pub words: usize,
+    /// The date of the first message of the session.
+    pub start: Option<NaiveDateTime>,
+    /// The date of the last message of the session.
+    pub end: Option<NaiveDateTime>,
+}
+
+impl ParticipantStats {
+    /// The average time taken by the participant to answer.
+    pub fn average_response_time(&self) -> Option<Duration> {
+        if self.responses == 0 {
+            None
+        } else {
+            Some(self.response_time / self.responses as i32)
+        }
+    }
+}
+
+impl SessionStats {
+    /// The time between the first and the last message of the session.
+    pub fn length(&self) -> Option<Duration> {
+        Some(self.end? - self.start?)
+    }
+}
+
+impl Statistics {
+    /// Computes the statistics of the given messages (e.g. a `Parser`, ignoring the errors with
+    /// `flatten()`).
+    pub fn from_messages<I>(messages: I) -> Self
+    where
+        I: IntoIterator<Item = Message>,
+    {
+        let mut stats = Statistics::default();
+        for message in messages {
+            stats.add(&message);
+        }
+        stats
+    }
+
+    /// Computes the statistics of all the archives of the collection. The messages that cannot
+    /// be parsed are ignored.
+    pub fn from_collection(collection: &ArchiveCollection) -> Self {
+        let mut stats = Statistics::default();
+        let mut contact = "";
+        for (entry, message) in collection.messages() {
+            if entry.contact != contact {
+                contact = &entry.contact;
+                stats.last = None;
+            }
+            if let Ok(message) = message {
+                let session = format!("{}/{}", entry.contact, message.session_id);
+                stats.record(session, &message);
+            }
+        }
+        stats
+    }
+
+    /// Adds a message to the statistics.
+    pub fn add(&mut self, message: &Message) {
+        self.record(message.session_id.to_string(), message);
+    }
+
+    /// The `count` most used emoticons, the most used first.
+    pub fn most_used_emoticons(&self, count: usize) -> Vec<(&str, usize)> {
+        let mut emoticons: Vec<(&str, usize)> = self
+            .emoticons
+            .iter()
+            .map(|(emoticon, used)| (emoticon.as_str(), *used))
+            .collect();
+        emoticons.sort_by_key(|(_, used)| std::cmp::Reverse(*used));
+        emoticons.truncate(count);
+        emoticons
+    }
+
+    fn record(&mut self, session: String, message: &Message) {
+        let words: usize = message
+            .data
+            .iter()
+            .map(|d| match d {
+                Data::Text(text) => text.content.split_whitespace().count(),
+                _ => 0,
+            })
+            .sum();
+        self.messages += 1;
+        self.words += words;
+
+        let local = common::parse_datetime(&message.datetime)
+            .map(|datetime| datetime + Duration::minutes(message.timezone_offset.unwrap_or(0)));
+        if let Some(local) = local {
+            self.by_hour[local.hour() as usize] += 1;
+            self.by_weekday[local.weekday().num_days_from_monday() as usize] += 1;
+        }
+
+        let session_stats = self.sessions.entry(session.clone()).or_default();
+        session_stats.messages += 1;
+        session_stats.words += words;
+        if local.is_some() {
+            session_stats.start = session_stats.start.or(local);
+            session_stats.end = local.max(session_stats.end);
+        }
+
+        if !message.sender_friendly_name.is_empty() {
+            let participant = self
+                .participants
+                .entry(message.sender_friendly_name.to_string())
+                .or_default();
+            participant.messages += 1;
+            participant.words += words;
+            if let (Some(local), Some((sender, last, last_session))) = (local, &self.last) {
+                if *sender != *message.sender_friendly_name && *last_session == session {
+                    participant.responses += 1;
+                    // the dates of Messenger Plus! are truncated to the minute
+                    participant.response_time += (local - *last).max(Duration::zero());
+                }
+            }
+            if let Some(local) = local {
+                self.last = Some((message.sender_friendly_name.to_string(), local, session));
+            }
+        }
+
+        for data in &message.data {
+            match data {
+                Data::Image(image) => {
+                    if let Some(emoticon) = emoticon::from_image(image) {
+                        *self.emoticons.entry(emoticon.id).or_default() += 1;
+                    }
+                }
+                Data::Emoticon(emoticon) => {
+                    *self.emoticons.entry(emoticon.id.clone()).or_default() += 1;
+                }
+                Data::Text(text) => {
+                    for (property, value) in parse_style(&text.style) {
+                        match property.as_str() {
+                            "font-family" => *self.fonts.entry(value).or_default() += 1,
+                            "color" => *self.colors.entry(value.to_lowercase()).or_default() += 1,
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Splits a CSS-like style (e.g. `font-family:"Courier New";color:#004000;`) into its properties.
+fn parse_style(style: &str) -> Vec<(String, String)> {
+    style
+        .split(';')
+        .filter_map(|declaration| declaration.split_once(':'))
+        .map(|(property, value)| {
+            (
+                property.trim().to_lowercase(),
+                value.trim().trim_matches(|c| c == '"' || c == '\'').to_string(),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+
+    #[test]
+    fn compute_statistics() {
+        let stats = Statistics::from_messages(Parser::new("test/alice@example.com.html").unwrap().flatten());
+        assert_eq!(stats.messages, 6);
+        assert_eq!(stats.participants["Bob"].messages, 3);
+        assert_eq!(stats.participants["Alice"].words, 16);
+        assert_eq!(stats.participants["Bob"].average_response_time(), Some(Duration::seconds(150)));
+        assert_eq!(stats.participants["Alice"].average_response_time(), Some(Duration::zero()));
+        assert_eq!(stats.by_hour[19], 6);
+        assert_eq!(stats.by_weekday[2], 6);
+        assert_eq!(
+            stats.sessions["Session_2009-08-05T19-30-21"].length(),
+            Some(Duration::seconds(13 * 60 + 39))
+        );
+        assert_eq!(stats.most_used_emoticons(3), vec![("smile", 1)]);
+        assert_eq!(stats.fonts["Courier New"], 5);
+        assert_eq!(stats.colors["#004000"], 5);
+    }
+
+    #[test]
+    fn compute_collection_statistics() {
+        let collection = ArchiveCollection::scan("test").unwrap();
+        let stats = Statistics::from_collection(&collection);
+        assert_eq!(stats.messages, 8);
+        assert_eq!(stats.by_hour[21], 1);
+        assert_eq!(stats.by_hour[22], 1);
+        assert_eq!(stats.sessions["alice1234/1"].messages, 2);
+        assert_eq!(stats.participants["Alice"].messages, 3);
+    }
+}
@@ -0,0 +1,370 @@
+use crate::messenger::{common, messenger_plus_parser, xml_parser};
+use crate::{ArchiveSource, Data, FileType, IdentityResolver, Message, MessengerArchive, Parser};
+use chrono::Timelike;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use xml::common::Position as _;
+use xml::reader::XmlEvent;
+
+/// A position in an archive file, starting at 1.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+pub struct Position {
+    pub line: u64,
+    pub column: u64,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Severity {
+    /// The archive cannot be read entirely.
+    Error,
+    /// The archive can be read, but some of its content is ignored or looks wrong.
+    Warning,
+}
+
+/// The problems found by `validate`.
+#[derive(PartialEq, Debug, Clone)]
+pub enum IssueKind {
+    /// The archive is not well-formed, or a message cannot be read. The following messages are not
+    /// checked.
+    Invalid(String),
+    /// An element ignored by the parser, e.g. `<Wtf>`.
+    UnknownElement(String),
+    /// An attribute ignored by the parser.
+    UnknownAttribute { element: String, attribute: String },
+    /// A session outside the `FirstSessionID`..`LastSessionID` range declared by a WLM archive.
+    SessionOutOfRange(String),
+    /// A message older than the previous one.
+    NonMonotonicTimestamp { previous: String, current: String },
+    /// An image of a Messenger Plus! archive whose file cannot be read.
+    MissingImage(String),
+    /// A message without text nor image.
+    EmptyMessage,
+    /// A message whose sender and receiver are not the participants of its session.
+    InconsistentParticipants { sender: String, receiver: String },
+}
+
+/// A problem found in an archive, with its position.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Issue {
+    pub kind: IssueKind,
+    pub position: Position,
+}
+
+/// The result of `validate`: the count of messages read and the problems found.
+#[derive(PartialEq, Debug, Default)]
+pub struct ValidationReport {
+    pub messages: usize,
+    /// The problems, ordered by position.
+    pub issues: Vec<Issue>,
+}
+
+impl IssueKind {
+    pub fn severity(&self) -> Severity {
+        match self {
+            IssueKind::Invalid(_) => Severity::Error,
+            _ => Severity::Warning,
+        }
+    }
+}
+
+impl fmt::Display for IssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IssueKind::Invalid(e) => write!(f, "invalid archive: {}", e),
+            IssueKind::UnknownElement(element) => write!(f, "unknown element <{}>", element),
+            IssueKind::UnknownAttribute { element, attribute } => {
+                write!(f, "unknown attribute `{}` of <{}>", attribute, element)
+            }
+            IssueKind::SessionOutOfRange(id) => write!(f, "session {} outside the declared range", id),
+            IssueKind::NonMonotonicTimestamp { previous, current } => {
+                write!(f, "message sent at {}, before the previous one ({})", current, previous)
+            }
+            IssueKind::MissingImage(src) => write!(f, "missing image {}", src),
+            IssueKind::EmptyMessage => write!(f, "empty message"),
+            IssueKind::InconsistentParticipants { sender, receiver } => {
+                write!(f, "message from {} to {}, not the participants of the session", sender, receiver)
+            }
+        }
+    }
+}
+
+impl ValidationReport {
+    /// Checks the archive could not be read entirely.
+    pub fn has_errors(&self) -> bool {
+        self.issues.iter().any(|issue| issue.kind.severity() == Severity::Error)
+    }
+
+    fn push(&mut self, kind: IssueKind, position: Position) {
+        self.issues.push(Issue { kind, position });
+    }
+}
+
+/// Parses the archive at `path` and reports what the parser ignores or what looks wrong, to check
+/// an archive before converting it. Only an unreadable file returns an error: the parsing errors
+/// are reported as `IssueKind::Invalid`.
+pub fn validate(path: &str) -> Result<ValidationReport, Box<dyn Error>> {
+    let source = ArchiveSource::open(path)?;
+    let file_type = FileType::from_path(path);
+    let content = source.read()?;
+    let mut report = ValidationReport::default();
+
+    let (positions, session_range) = check_elements(&content, file_type, &mut report);
+    // the images are checked below, instead of failing the parsing
+    let name = Path::new(path).file_name().unwrap_or_default().to_string_lossy().to_string();
+    let mut parser = Parser::from_source(ArchiveSource::Memory { name, content: content.into() }, file_type)?;
+
+    let mut previous: Option<String> = None;
+    let mut messages = vec![];
+    let mut message_positions = vec![];
+    for (index, item) in parser.by_ref().enumerate() {
+        // the positions found by `check_elements` are only used to report the issues
+        let position = positions
+            .get(index)
+            .or(positions.last())
+            .copied()
+            .unwrap_or(Position { line: 1, column: 1 });
+        let message = match item {
+            Ok(message) => message,
+            Err(e) => {
+                if !report.has_errors() {
+                    report.push(IssueKind::Invalid(e.to_string()), position);
+                }
+                break;
+            }
+        };
+        report.messages = index + 1;
+
+        if let (Some((first, last)), Ok(id)) = (session_range, message.session_id.parse::<u64>()) {
+            if id < first || id > last {
+                report.push(IssueKind::SessionOutOfRange(message.session_id.to_string()), position);
+            }
+        }
+        if let Some(previous) = &previous {
+            if is_before(&message.datetime, previous) {
+                report.push(
+                    IssueKind::NonMonotonicTimestamp {
+                        previous: previous.clone(),
+                        current: message.datetime.clone(),
+                    },
+                    position,
+                );
+            }
+        }
+        for data in &message.data {
+            if let Data::Image(image) = data {
+                if !image.src.is_empty() && source.resource(&image.src).is_err() {
+                    report.push(IssueKind::MissingImage(image.src.clone()), position);
+                }
+            }
+        }
+        if is_empty(&message) {
+            report.push(IssueKind::EmptyMessage, position);
+        }
+        previous = Some(message.datetime.clone());
+        messages.push(message);
+        message_positions.push(position);
+    }
+
+    // the participants are compared by e-mail (or ID), as their nicknames can change during a session
+    let details = parser.details().cloned().unwrap_or_default();
+    let (_, identified) = IdentityResolver::new().resolve(messages, &details);
+    let mut session_participants: Option<(String, [String; 2])> = None;
+    for (message, position) in identified.iter().zip(message_positions) {
+        // the system messages have no participants
+        if message.sender_id.is_empty() && message.receiver_id.is_empty() {
+            continue;
+        }
+        let mut participants = [message.sender_id.clone(), message.receiver_id.clone()];
+        participants.sort();
+        let consistent = match &session_participants {
+            Some((session, expected)) if *session == *message.message.session_id => *expected == participants,
+            _ => {
+                session_participants = Some((message.message.session_id.to_string(), participants.clone()));
+                true
+            }
+        };
+        let sender = &message.message.sender_friendly_name;
+        let receiver = &message.message.receiver_friendly_name;
+        if !consistent || participants[0] == participants[1] || sender == receiver {
+            report.push(
+                IssueKind::InconsistentParticipants {
+                    sender: sender.to_string(),
+                    receiver: receiver.to_string(),
+                },
+                position,
+            );
+        }
+    }
+    report.issues.sort_by_key(|issue| issue.position);
+    Ok(report)
+}
+
+/// Walks through the elements of the archive to report the ones the parser ignores. Returns the
+/// positions of the messages and the range of sessions declared by a WLM archive.
+fn check_elements(content: &[u8], file_type: FileType, report: &mut ValidationReport) -> (Vec<Position>, Option<(u64, u64)>) {
+    let (known, message_path) = match file_type {
+        FileType::XML => (xml_parser::ELEMENTS, xml_parser::MESSAGE),
+        FileType::MessengerPlus => (messenger_plus_parser::ELEMENTS, messenger_plus_parser::MESSAGE),
+    };
+    let mut reader = common::get_content_parser(content.to_vec());
+    let mut parents: Vec<String> = vec![];
+    let mut positions = vec![];
+    let mut session_range = None;
+    loop {
+        let event = reader.next();
+        let text_position = reader.position();
+        let position = Position {
+            line: text_position.row + 1,
+            column: text_position.column + 1,
+        };
+        match event {
+            Ok(XmlEvent::StartElement { name, attributes, .. }) => {
+                parents.push(name.local_name);
+                let element = parents.last().unwrap();
+                match known.iter().find(|(path, _)| is_at(path, &parents)) {
+                    None => report.push(IssueKind::UnknownElement(element.clone()), position),
+                    Some((_, known_attributes)) => {
+                        for attribute in &attributes {
+                            if !known_attributes.contains(&attribute.name.local_name.as_str()) {
+                                report.push(
+                                    IssueKind::UnknownAttribute {
+                                        element: element.clone(),
+                                        attribute: attribute.name.local_name.clone(),
+                                    },
+                                    position,
+                                );
+                            }
+                        }
+                    }
+                }
+                if file_type == FileType::XML && is_at(xml_parser::LOG, &parents) {
+                    let attributes = common::parse_attributes(&attributes);
+                    let first = attributes.get("FirstSessionID").and_then(|id| id.parse().ok());
+                    let last = attributes.get("LastSessionID").and_then(|id| id.parse().ok());
+                    session_range = first.zip(last);
+                } else if is_at(message_path, &parents) {
+                    positions.push(position);
+                }
+            }
+            Ok(XmlEvent::EndElement { .. }) => {
+                parents.pop();
+            }
+            Ok(XmlEvent::EndDocument) => break,
+            Err(e) => {
+                report.push(IssueKind::Invalid(e.msg().to_string()), position);
+                break;
+            }
+            _ => {}
+        }
+    }
+    (positions, session_range)
+}
+
+/// Checks the elements `parents` are at `path`, from the root.
+fn is_at(path: &[&str], parents: &[String]) -> bool {
+    path.len() == parents.len() && path.iter().zip(parents).all(|(name, parent)| name == parent)
+}
+
+/// Checks the date `datetime` is before `previous`. The dates saved with minutes only (as in the
+/// Messenger Plus! archives) are compared without the seconds.
+fn is_before(datetime: &str, previous: &str) -> bool {
+    let minutes_only = |date: &str| date.trim_end_matches('Z').len() <= "2009-04-06T19:40".len();
+    let truncate = minutes_only(datetime) || minutes_only(previous);
+    let parse = |date: &str| {
+        let date = common::parse_datetime(date)?;
+        if truncate {
+            date.with_second(0)?.with_nanosecond(0)
+        } else {
+            Some(date)
+        }
+    };
+    match (parse(datetime), parse(previous)) {
+        (Some(datetime), Some(previous)) => datetime < previous,
+        _ => false,
+    }
+}
+
+/// Checks the message has no text, emoticon nor image.
+fn is_empty(message: &Message) -> bool {
+    message.data.iter().all(|data| match data {
+        Data::Text(text) => text.content.trim().is_empty(),
+        Data::System(content) => content.trim().is_empty(),
+        Data::Image(_) | Data::Emoticon(_) | Data::Unknown(_) => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_ignored_content() {
+        let report = validate("test/scrappy.xml").unwrap();
+        assert_eq!(
+            report.issues,
+            vec![Issue {
+                kind: IssueKind::UnknownElement("Wtf".to_string()),
+                position: Position { line: 3, column: 6 },
+            }]
+        );
+        assert_eq!(validate("test/alice1234.xml").unwrap(), ValidationReport { messages: 2, issues: vec![] });
+        assert_eq!(validate("test/alice@example.com.html").unwrap().issues, vec![]);
+
+        let path = std::env::temp_dir().join("report_ignored_content.html");
+        std::fs::copy("test/alice@example.com.html", &path).unwrap();
+        let report = validate(path.to_str().unwrap()).unwrap();
+        assert_eq!(report.issues.len(), 1);
+        assert_eq!(report.issues[0].kind, IssueKind::MissingImage("./Images/MsgPlus_Img0663.png".to_string()));
+        assert!(!report.has_errors());
+    }
+
+    #[test]
+    fn report_inconsistent_messages() {
+        let message = |session: &str, time: &str, from: &str, to: &str, text: &str| {
+            format!(
+                "<Message Date=\"06/04/2009\" Time=\"21:40:41\" DateTime=\"2009-04-06T{}.000Z\" SessionID=\"{}\" Read=\"1\">\
+                 <From><User FriendlyName=\"{}\"/></From><To><User FriendlyName=\"{}\"/></To>\
+                 <Text>{}</Text></Message>\n",
+                time, session, from, to, text
+            )
+        };
+        let content = [
+            "<?xml version=\"1.0\"?>\n<Log FirstSessionID=\"1\" LastSessionID=\"1\">\n".to_string(),
+            message("1", "19:40:41", "Alice", "Bob", "Hello!"),
+            message("1", "19:40:00", "Bob", "Alice", "Hi"),
+            message("1", "19:41:00", "Alice", "Alice", " "),
+            // a nickname change is not an inconsistency
+            message("1", "19:41:30", "Alice (away)", "Bob", "Back"),
+            message("2", "19:42:00", "Bob", "Alice", "Bye"),
+            "</Log><Message".to_string(),
+        ]
+        .concat();
+        let path = std::env::temp_dir().join("report_inconsistent_messages.xml");
+        std::fs::write(&path, content).unwrap();
+
+        let report = validate(path.to_str().unwrap()).unwrap();
+        let issues: Vec<(u64, String)> = report
+            .issues
+            .iter()
+            .map(|issue| (issue.position.line, issue.kind.to_string()))
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                (3, "unknown attribute `Read` of <Message>".to_string()),
+                (4, "unknown attribute `Read` of <Message>".to_string()),
+                (4, "message sent at 2009-04-06T19:40:00.000Z, before the previous one (2009-04-06T19:40:41.000Z)".to_string()),
+                (5, "unknown attribute `Read` of <Message>".to_string()),
+                (5, "empty message".to_string()),
+                (5, "message from Alice to Alice, not the participants of the session".to_string()),
+                (6, "unknown attribute `Read` of <Message>".to_string()),
+                (7, "unknown attribute `Read` of <Message>".to_string()),
+                (7, "session 2 outside the declared range".to_string()),
+                (8, "invalid archive: Unexpected end of stream".to_string()),
+            ]
+        );
+        assert_eq!(report.messages, 5);
+        assert!(report.has_errors());
+    }
+}